@@ -0,0 +1,351 @@
+use futures::{channel::mpsc, prelude::*};
+use pam_client::{ConversationHandler, ErrorCode};
+use std::{
+    cell::{Cell, RefCell},
+    ffi::{CStr, CString},
+    rc::Rc,
+    sync::mpsc as std_mpsc,
+};
+use wstk::*;
+
+/// i.e. /etc/pam.d/waysmoke-lock (see pam/waysmoke-lock)
+static PAM_SERVICE: &str = "waysmoke-lock";
+
+/// What the PAM conversation tells the UI
+enum PamEvent {
+    Prompt { txt: String, echo_on: bool },
+    Info(String),
+    Error(String),
+    Done(bool),
+}
+
+/// The PAM side of the conversation. PAM blocks while waiting for answers, so it runs on its own thread.
+struct Conversation {
+    events: mpsc::UnboundedSender<PamEvent>,
+    /// None cancels the prompt
+    responses: std_mpsc::Receiver<Option<String>>,
+}
+
+impl Conversation {
+    fn send(&self, event: PamEvent) {
+        // the UI only goes away when exiting
+        let _ = self.events.unbounded_send(event);
+    }
+
+    fn prompt(&mut self, prompt: &CStr, echo_on: bool) -> Result<CString, ErrorCode> {
+        self.send(PamEvent::Prompt {
+            txt: prompt.to_string_lossy().into_owned(),
+            echo_on,
+        });
+        match self.responses.recv() {
+            Ok(Some(response)) => CString::new(response).map_err(|_| ErrorCode::CONV_ERR),
+            _ => Err(ErrorCode::CONV_ERR),
+        }
+    }
+}
+
+impl ConversationHandler for Conversation {
+    fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+        self.prompt(prompt, true)
+    }
+
+    fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+        self.prompt(prompt, false)
+    }
+
+    fn text_info(&mut self, msg: &CStr) {
+        self.send(PamEvent::Info(msg.to_string_lossy().into_owned()));
+    }
+
+    fn error_msg(&mut self, msg: &CStr) {
+        self.send(PamEvent::Error(msg.to_string_lossy().into_owned()));
+    }
+}
+
+fn pam_authenticate(user: &str, conv: Conversation) -> bool {
+    let events = conv.events.clone();
+    let result = match pam_client::Context::new(PAM_SERVICE, Some(user), conv) {
+        Ok(mut context) => match context.authenticate(pam_client::Flag::NONE) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Authentication failed: {}", e);
+                false
+            }
+        },
+        Err(e) => {
+            eprintln!("Could not start PAM: {}", e);
+            false
+        }
+    };
+    let _ = events.unbounded_send(PamEvent::Done(result));
+    result
+}
+
+/// Authentication state shared by the lock screens of all outputs
+struct AuthState {
+    user: String,
+    notifier: event_listener::Event,
+    /// Bumped on every change, so that screens that weren't listening still notice
+    generation: Cell<u64>,
+    elements: RefCell<Vec<PromptElement>>,
+    /// To the PAM thread of the current attempt
+    responses: RefCell<Option<std_mpsc::Sender<Option<String>>>>,
+}
+
+impl AuthState {
+    fn new(user: String) -> AuthState {
+        AuthState {
+            user,
+            notifier: event_listener::Event::new(),
+            generation: Cell::new(0),
+            elements: RefCell::new(Vec::new()),
+            responses: RefCell::new(None),
+        }
+    }
+
+    fn changed(&self) {
+        self.generation.set(self.generation.get() + 1);
+        self.notifier.notify(usize::MAX);
+    }
+
+    fn response(&self, response: String) {
+        if let Some(ref tx) = *self.responses.borrow() {
+            let _ = tx.send(Some(response));
+        }
+    }
+
+    /// Runs PAM until it succeeds, showing its messages and prompts
+    async fn authenticate(&self) {
+        loop {
+            let (events_tx, mut events) = mpsc::unbounded();
+            let (responses_tx, responses) = std_mpsc::channel();
+            *self.responses.borrow_mut() = Some(responses_tx);
+            let user = self.user.clone();
+            std::thread::spawn(move || {
+                pam_authenticate(
+                    &user,
+                    Conversation {
+                        events: events_tx,
+                        responses,
+                    },
+                )
+            });
+
+            let mut success = false;
+            while let Some(event) = events.next().await {
+                let element = match event {
+                    PamEvent::Prompt { txt, echo_on } => PromptElement::Prompt {
+                        txt,
+                        echo_on,
+                        done: false,
+                    },
+                    PamEvent::Info(txt) => PromptElement::Info(txt),
+                    PamEvent::Error(txt) => PromptElement::Error(txt),
+                    PamEvent::Done(result) => {
+                        success = result;
+                        break;
+                    }
+                };
+                self.elements.borrow_mut().push(element);
+                self.changed();
+            }
+            *self.responses.borrow_mut() = None;
+            if success {
+                return;
+            }
+
+            // start over, keeping only what explains the failure
+            let mut elements = self.elements.borrow_mut();
+            elements.retain(|e| matches!(e, PromptElement::Error(_)));
+            if elements.is_empty() {
+                elements.push(PromptElement::Error("Authentication failed".to_owned()));
+            }
+            drop(elements);
+            self.changed();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Msg {
+    InputChange(String),
+    Paste(String),
+    SubmitResponse,
+}
+
+struct LockScreen {
+    st: Rc<AuthState>,
+    seen_generation: u64,
+    prompt: PasswordPrompt,
+    submit_btn: iced_native::button::State,
+}
+
+impl LockScreen {
+    fn new(st: Rc<AuthState>) -> LockScreen {
+        LockScreen {
+            st,
+            seen_generation: 0,
+            prompt: PasswordPrompt::new(),
+            submit_btn: Default::default(),
+        }
+    }
+}
+
+// lock surfaces always cover their output, there's nothing to configure
+impl DesktopSurface for LockScreen {
+    fn layer_props(&self) -> LayerProps {
+        LayerProps::default()
+    }
+}
+
+#[async_trait(?Send)]
+impl IcedSurface for LockScreen {
+    type Message = Msg;
+
+    fn view(&mut self) -> Element<Self::Message> {
+        use iced_native::*;
+
+        let title = Text::new(format!("Locked by {}", self.st.user))
+            .size(20)
+            .horizontal_alignment(HorizontalAlignment::Center);
+
+        let mut elems = Column::new().spacing(16).push(title).push(self.prompt.view(
+            &self.st.elements.borrow(),
+            Msg::InputChange,
+            Msg::SubmitResponse,
+        ));
+
+        elems = elems.push(
+            Button::new(
+                &mut self.submit_btn,
+                Text::new("Unlock")
+                    .size(18)
+                    .horizontal_alignment(HorizontalAlignment::Center),
+            )
+            .on_press(Msg::SubmitResponse)
+            .width(Length::Fill)
+            .style(style::Action(style::ActionType::Good)),
+        );
+
+        let dialog = Container::new(elems)
+            .style(style::Dialog)
+            .width(Length::Units(420))
+            .padding(16);
+
+        Container::new(Column::new().push(dialog))
+            .style(style::Backdrop)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+    }
+
+    fn retained_images(&mut self) -> Vec<wstk::ImageHandle> {
+        vec![]
+    }
+
+    fn on_paste(&mut self, paste: wstk::Paste) -> Option<Self::Message> {
+        paste.text().map(Msg::Paste)
+    }
+
+    fn primary_selection(&self) -> Option<String> {
+        self.prompt.primary_selection(&self.st.elements.borrow())
+    }
+
+    fn text_field(&self) -> Option<TextField> {
+        self.prompt.text_field(&self.st.elements.borrow())
+    }
+
+    async fn on_preedit(&mut self, preedit: Option<Preedit>) {
+        self.prompt.set_preedit(preedit);
+    }
+
+    async fn update(&mut self, message: Self::Message) {
+        match message {
+            Msg::InputChange(new_input) => self.prompt.set_value(new_input),
+            Msg::Paste(txt) => self.prompt.paste(&txt),
+            Msg::SubmitResponse => {
+                if prompt_echo(&self.st.elements.borrow()).is_none() {
+                    return;
+                }
+                let sent_val = self.prompt.take_response();
+                finish_prompt(&mut self.st.elements.borrow_mut());
+                self.st.response(sent_val);
+                self.st.changed();
+            }
+        }
+    }
+
+    async fn run(&mut self) -> Action {
+        // check before listening! the state might have changed while the instance wasn't being run
+        if self.seen_generation == self.st.generation.get() {
+            let listener = self.st.notifier.listen();
+            if self.seen_generation == self.st.generation.get() {
+                listener.await;
+            }
+        }
+        self.seen_generation = self.st.generation.get();
+        Action::Rerender
+    }
+}
+
+async fn main_(env: &'static Environment<Env>, display: &'static Display) {
+    let lock = match SessionLock::new(env) {
+        Some(lock) => lock,
+        None => {
+            eprintln!("The compositor does not support ext-session-lock-v1");
+            std::process::exit(1);
+        }
+    };
+    let auth = Rc::new(AuthState::new(glib::user_name().to_string_lossy().into_owned()));
+
+    let lock_ref = &lock;
+    let auth_ref = auth.clone();
+    let mut screens = MultiMonitor::new(
+        Box::new(move |output, _output_info| {
+            IcedInstance::new_locked(
+                LockScreen::new(auth_ref.clone()),
+                lock_ref,
+                env.clone(),
+                display.clone(),
+                output,
+            )
+            .boxed_local()
+        }),
+        env,
+    )
+    .await;
+    display.flush().unwrap();
+
+    let locked = lock.locked().fuse();
+    let finished = lock.finished().fuse();
+    let authenticated = future::Fuse::terminated();
+    futures::pin_mut!(locked, finished, authenticated);
+    loop {
+        futures::select! {
+            _ = screens.run().fuse() => (),
+            is_locked = locked => {
+                if !is_locked {
+                    eprintln!("Could not lock the session, is another locker running?");
+                    std::process::exit(1);
+                }
+                // only now, unlocking before the session is locked would be a protocol error
+                authenticated.set(auth.authenticate().fuse());
+            },
+            () = authenticated => break,
+            () = finished => {
+                eprintln!("The compositor ended the lock");
+                std::process::exit(0);
+            },
+        }
+    }
+
+    lock.unlock();
+    drop(screens);
+    // don't exit before the compositor has seen the unlock
+    let _ = wayland_sync(display).await;
+    std::process::exit(0);
+}
+
+wstk_main!(main_);
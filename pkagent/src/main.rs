@@ -10,12 +10,6 @@ use wstk::*;
 
 static OBJ_PATH: &str = "/technology/unrelenting/waysmoke/Agent";
 
-enum DialogElement {
-    Info(String),
-    Error(String),
-    Prompt { txt: String, echo_on: bool, done: bool },
-}
-
 struct AuthRequest {
     action_id: String,
     message: String,
@@ -25,15 +19,17 @@ struct AuthRequest {
     begin_invo: gio::DBusMethodInvocation,
 }
 
+/// What the dialogs show, apart from the bus request so that they can be rendered without one
 struct AuthRunState {
-    req: AuthRequest,
+    message: String,
     session: polkit_agent::Session,
     notifier: event_listener::Event,
-    elements: RefCell<Vec<DialogElement>>,
+    elements: RefCell<Vec<PromptElement>>,
     done: Cell<bool>,
 }
 
 struct AuthRun<'a> {
+    req: AuthRequest,
     state: Rc<AuthRunState>,
     dialog: MultiMonitor<'a, IcedInstance<AuthDialog>>,
 }
@@ -85,13 +81,15 @@ impl<'a> Runnable for AuthAgent<'a> {
         if let Some(run) = this.cur_dialog.as_mut() {
             futures::select_biased! {
                 cookie = this.cancel_rx.select_next_some().fuse() => {
-                    if run.state.req.cookie == cookie {
+                    if run.req.cookie == cookie {
                         this.cur_dialog = None;
                     }
                     // TODO: support canceling queued reqs
                 },
                 cont = run.dialog.run().fuse() => {
+                    // every output's dialog closes when it's done, answer the request once
                     if !cont {
+                        run.req.begin_invo.return_value(None);
                         this.cur_dialog = None;
                     }
                 }
@@ -105,7 +103,7 @@ impl<'a> Runnable for AuthAgent<'a> {
                 let session =
                     polkit_agent::Session::new(&convert_ident(req.identities.first().unwrap()).unwrap(), &req.cookie);
                 let state = Rc::new(AuthRunState {
-                    req,
+                    message: req.message.clone(),
                     session,
                     notifier: event_listener::Event::new(),
 
@@ -115,7 +113,7 @@ impl<'a> Runnable for AuthAgent<'a> {
                 state
                     .session
                     .connect_request(clone!(@strong state => move |_s, prompt, echo_on| {
-                        state.elements.borrow_mut().push(DialogElement::Prompt {
+                        state.elements.borrow_mut().push(PromptElement::Prompt {
                             txt: prompt.to_string(),
                             echo_on,
                             done: false,
@@ -125,13 +123,13 @@ impl<'a> Runnable for AuthAgent<'a> {
                 state
                     .session
                     .connect_show_error(clone!(@strong state => move |_s, err| {
-                        state.elements.borrow_mut().push(DialogElement::Error(err.to_string()));
+                        state.elements.borrow_mut().push(PromptElement::Error(err.to_string()));
                         state.notifier.notify(usize::MAX);
                     }));
                 state
                     .session
                     .connect_show_info(clone!(@strong state => move |_s, info| {
-                        state.elements.borrow_mut().push(DialogElement::Info(info.to_string()));
+                        state.elements.borrow_mut().push(PromptElement::Info(info.to_string()));
                         state.notifier.notify(usize::MAX);
                     }));
                 state
@@ -142,6 +140,7 @@ impl<'a> Runnable for AuthAgent<'a> {
                     }));
                 state.session.initiate();
                 this.cur_dialog = Some(AuthRun {
+                    req,
                     state: state.clone(),
                     dialog: MultiMonitor::new(
                         Box::new(move |output, _output_info| {
@@ -260,14 +259,14 @@ fn convert_ident(ident: &(String, HashMap<String, glib::Variant>)) -> Option<pol
 #[derive(Debug, Clone)]
 enum Msg {
     InputChange(String),
+    Paste(String),
     SubmitResponse,
     CancelResponse,
 }
 
 struct AuthDialog {
     st: Rc<AuthRunState>,
-    input: iced_native::text_input::State,
-    input_val: String,
+    prompt: PasswordPrompt,
     cancel_btn: iced_native::button::State,
     submit_btn: iced_native::button::State,
 }
@@ -276,8 +275,7 @@ impl AuthDialog {
     pub fn new(st: Rc<AuthRunState>) -> AuthDialog {
         AuthDialog {
             st,
-            input: iced_native::text_input::State::focused(),
-            input_val: "".to_string(),
+            prompt: PasswordPrompt::new(),
             cancel_btn: Default::default(),
             submit_btn: Default::default(),
         }
@@ -285,15 +283,17 @@ impl AuthDialog {
 }
 
 impl DesktopSurface for AuthDialog {
-    fn setup_lsh(&self, layer_surface: &Main<layer_surface::ZwlrLayerSurfaceV1>) {
-        layer_surface.set_anchor(
-            layer_surface::Anchor::Left
+    fn layer_props(&self) -> LayerProps {
+        LayerProps {
+            namespace: "waysmoke-pkagent".to_owned(),
+            anchor: layer_surface::Anchor::Left
                 | layer_surface::Anchor::Top
                 | layer_surface::Anchor::Right
                 | layer_surface::Anchor::Bottom,
-        );
-        layer_surface.set_exclusive_zone(-1);
-        layer_surface.set_keyboard_interactivity(1);
+            exclusive_zone: -1,
+            keyboard: KeyboardMode::Exclusive,
+            ..Default::default()
+        }
     }
 }
 
@@ -304,48 +304,15 @@ impl IcedSurface for AuthDialog {
     fn view(&mut self) -> Element<Self::Message> {
         use iced_native::*;
 
-        let title = Text::new(self.st.req.message.clone())
+        let title = Text::new(self.st.message.clone())
             .size(20)
             .horizontal_alignment(HorizontalAlignment::Center);
 
-        let mut elems = Column::new().spacing(16).push(title);
-
-        // We're only supposed to have one not-done prompt, but Rust doesn't know.
-        // Enforce this by borrowing outside of the loop here.
-        let mut input_stref = Some(&mut self.input);
-
-        for e in self.st.elements.borrow().iter() {
-            match e {
-                DialogElement::Info(txt) => {
-                    // TODO: style
-                    elems = elems.push(Text::new(txt.clone()).size(18));
-                }
-                DialogElement::Error(txt) => {
-                    // TODO: style
-                    elems = elems.push(Text::new(txt.clone()).size(18));
-                }
-                DialogElement::Prompt { txt, echo_on, done } => {
-                    let mut row = Row::new()
-                        .align_items(Align::Center)
-                        .spacing(8)
-                        .push(Text::new(txt.clone()).size(18));
-                    if !done {
-                        if let Some(input) = input_stref.take() {
-                            let mut input = TextInput::new(input, "", &self.input_val, Msg::InputChange)
-                                .on_submit(Msg::SubmitResponse)
-                                .width(Length::Fill)
-                                .style(style::Dialog)
-                                .padding(4);
-                            if !echo_on {
-                                input = input.password();
-                            }
-                            row = row.push(input);
-                        }
-                    }
-                    elems = elems.push(row);
-                }
-            }
-        }
+        let mut elems = Column::new().spacing(16).push(title).push(self.prompt.view(
+            &self.st.elements.borrow(),
+            Msg::InputChange,
+            Msg::SubmitResponse,
+        ));
 
         elems = elems.push(
             Row::new()
@@ -392,18 +359,29 @@ impl IcedSurface for AuthDialog {
         vec![]
     }
 
+    fn on_paste(&mut self, paste: wstk::Paste) -> Option<Self::Message> {
+        paste.text().map(Msg::Paste)
+    }
+
+    fn primary_selection(&self) -> Option<String> {
+        self.prompt.primary_selection(&self.st.elements.borrow())
+    }
+
+    fn text_field(&self) -> Option<TextField> {
+        self.prompt.text_field(&self.st.elements.borrow())
+    }
+
+    async fn on_preedit(&mut self, preedit: Option<Preedit>) {
+        self.prompt.set_preedit(preedit);
+    }
+
     async fn update(&mut self, message: Self::Message) {
         match message {
-            Msg::InputChange(new_input) => self.input_val = new_input,
+            Msg::InputChange(new_input) => self.prompt.set_value(new_input),
+            Msg::Paste(txt) => self.prompt.paste(&txt),
             Msg::SubmitResponse => {
-                let sent_val = self.input_val.clone();
-                self.input_val.clear();
-                for e in self.st.elements.borrow_mut().iter_mut().rev() {
-                    if let DialogElement::Prompt { ref mut done, .. } = e {
-                        *done = true;
-                        break;
-                    }
-                }
+                let sent_val = self.prompt.take_response();
+                finish_prompt(&mut self.st.elements.borrow_mut());
                 self.st.session.response(&sent_val);
             }
             Msg::CancelResponse => self.st.session.cancel(),
@@ -416,7 +394,6 @@ impl IcedSurface for AuthDialog {
             self.st.notifier.listen().await;
         }
         if self.st.done.get() {
-            self.st.req.begin_invo.return_value(None);
             Action::Close
         } else {
             Action::Rerender
@@ -433,3 +410,51 @@ async fn main_(env: &'static Environment<Env>, display: &'static Display) {
 }
 
 wstk_main!(main_);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn reference(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/reference")
+            .join(name)
+    }
+
+    /// A session that is never initiated, so nothing talks to polkit
+    fn run_state(elements: Vec<PromptElement>) -> Rc<AuthRunState> {
+        Rc::new(AuthRunState {
+            message: "Authentication is required to run /usr/bin/true as the super user".to_owned(),
+            session: polkit_agent::Session::new(&polkit::UnixUser::new(1000), "cookie"),
+            notifier: event_listener::Event::new(),
+            elements: RefCell::new(elements),
+            done: Cell::new(false),
+        })
+    }
+
+    #[test]
+    fn dialog_hides_passwords() {
+        block_on(async {
+            let st = run_state(vec![
+                PromptElement::Info("Touch the security key".to_owned()),
+                PromptElement::Prompt {
+                    txt: "Password:".to_owned(),
+                    echo_on: false,
+                    done: false,
+                },
+            ]);
+            let mut h = Headless::new(AuthDialog::new(st), 640, 400, 1.0);
+            h.render().await;
+            h.keyboard_enter().await;
+            for c in "hunter2".chars() {
+                h.type_key(c as u32, &c.to_string()).await;
+            }
+            let field = h.text_field().unwrap();
+            assert!(field.password);
+            assert_eq!(field.surrounding, "hunter2");
+            assert_eq!(h.surface().primary_selection(), None);
+            wstk::assert_matches_reference(&h.snapshot(), reference("dialog.png"));
+        });
+    }
+}
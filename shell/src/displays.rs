@@ -0,0 +1,476 @@
+use crate::{style, svc};
+use futures::prelude::*;
+use std::time::{Duration, Instant};
+use wstk::*;
+
+/// How long a new configuration stays without confirmation
+const REVERT_TIMEOUT: Duration = Duration::from_secs(15);
+const SCALE_STEP: f64 = 0.25;
+
+#[derive(Debug, Clone)]
+pub enum Msg {
+    ToggleEnabled(usize),
+    NextMode(usize),
+    Scale(usize, f64),
+    Rotate(usize),
+    MoveLeft(usize),
+    MoveRight(usize),
+    Apply,
+    Keep,
+    Revert,
+    Close,
+}
+
+#[derive(Default)]
+struct HeadBtns {
+    enabled: iced_native::button::State,
+    mode: iced_native::button::State,
+    scale_down: iced_native::button::State,
+    scale_up: iced_native::button::State,
+    rotate: iced_native::button::State,
+    left: iced_native::button::State,
+    right: iced_native::button::State,
+}
+
+/// An applied configuration waiting for confirmation
+struct Revert {
+    previous: Vec<HeadConfig>,
+    deadline: Instant,
+}
+
+impl Revert {
+    fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Rounded up, so that the countdown ends at 1 rather than showing 0 for a second
+    fn seconds_left(&self) -> u64 {
+        let remaining = self.remaining();
+        remaining.as_secs() + if remaining.subsec_nanos() > 0 { 1 } else { 0 }
+    }
+}
+
+pub struct DisplaySettings {
+    services: &'static svc::Services,
+    display: Display,
+    /// Being edited, in left to right order
+    config: Vec<HeadConfig>,
+    edited: bool,
+    revert: Option<Revert>,
+    status: Option<String>,
+    closing: bool,
+    head_btns: Vec<HeadBtns>,
+    apply_btn: iced_native::button::State,
+    close_btn: iced_native::button::State,
+    keep_btn: iced_native::button::State,
+    revert_btn: iced_native::button::State,
+}
+
+impl DisplaySettings {
+    pub fn new(services: &'static svc::Services, display: Display) -> DisplaySettings {
+        let mut settings = DisplaySettings {
+            services,
+            display,
+            config: Vec::new(),
+            edited: false,
+            revert: None,
+            status: None,
+            closing: false,
+            head_btns: Vec::new(),
+            apply_btn: Default::default(),
+            close_btn: Default::default(),
+            keep_btn: Default::default(),
+            revert_btn: Default::default(),
+        };
+        settings.reload();
+        settings
+    }
+
+    /// Starts over from what the compositor has now
+    fn reload(&mut self) {
+        self.config = self.services.outputs.current_config();
+        // disabled heads don't have a position, put them at the end
+        self.config.sort_by_key(|c| {
+            if c.enabled {
+                (0, c.position.0, c.position.1)
+            } else {
+                (1, 0, 0)
+            }
+        });
+        self.head_btns.resize_with(self.config.len(), Default::default);
+        self.edited = false;
+    }
+
+    /// Sizes of the heads being edited, in the global compositor space
+    fn logical_sizes(&self) -> Vec<(i32, i32)> {
+        let heads = self.services.outputs.heads();
+        self.config
+            .iter()
+            .map(|conf| {
+                heads
+                    .iter()
+                    .find(|h| h.name == conf.name)
+                    .map_or((0, 0), |h| conf.logical_size(h))
+            })
+            .collect()
+    }
+
+    /// Finds a place for a head that was just enabled, or that changed size and now covers another one:
+    /// to the right of all the other enabled heads, top-aligned with them. The others stay where they are.
+    fn place(&mut self, i: usize, just_enabled: bool) {
+        self.edited = true;
+        if !self.config[i].enabled {
+            return;
+        }
+        let sizes = self.logical_sizes();
+        let others = (0..self.config.len())
+            .filter(|&j| j != i && self.config[j].enabled)
+            .collect::<Vec<_>>();
+        let (x, y) = self.config[i].position;
+        let (w, h) = sizes[i];
+        let overlaps = others.iter().any(|&j| {
+            let ((ox, oy), (ow, oh)) = (self.config[j].position, sizes[j]);
+            x < ox + ow && ox < x + w && y < oy + oh && oy < y + h
+        });
+        if !just_enabled && !overlaps {
+            return;
+        }
+        let right = others
+            .iter()
+            .map(|&j| self.config[j].position.0 + sizes[j].0)
+            .max()
+            .unwrap_or(0);
+        let top = others.iter().map(|&j| self.config[j].position.1).min().unwrap_or(0);
+        self.config[i].position = (right, top);
+    }
+
+    /// Swaps two neighbouring heads in the list, and on screen if both are enabled:
+    /// the right one takes the left one's place, and the left one goes right after it.
+    fn swap(&mut self, left: usize) {
+        let right = left + 1;
+        if self.config[left].enabled && self.config[right].enabled {
+            let width = self.logical_sizes()[right].0;
+            let x = self.config[left].position.0.min(self.config[right].position.0);
+            self.config[right].position.0 = x;
+            self.config[left].position.0 = x + width;
+        }
+        self.config.swap(left, right);
+        self.head_btns.swap(left, right);
+        self.edited = true;
+    }
+
+    async fn apply(&mut self, config: Vec<HeadConfig>) -> ConfigResult {
+        let result = self.services.outputs.apply(&config);
+        if let Err(e) = self.display.flush() {
+            eprintln!("Error flushing the wayland socket: {:?}", e);
+        }
+        let result = result.await;
+        self.status = match result {
+            ConfigResult::Succeeded => None,
+            ConfigResult::Failed => Some("The compositor could not apply this configuration".to_owned()),
+            ConfigResult::Cancelled => Some("The displays changed, please try again".to_owned()),
+        };
+        result
+    }
+
+    fn head_row<'a>(
+        conf: &HeadConfig,
+        head: Option<&OutputHead>,
+        i: usize,
+        btns: &'a mut HeadBtns,
+    ) -> Element<'a, Msg> {
+        use iced_native::*;
+
+        let btn = |state, label: String, msg| {
+            Button::new(state, Text::new(label).size(14))
+                .style(style::Toplevel)
+                .on_press(msg)
+        };
+        let mode = match conf.mode.and_then(|m| head.and_then(|h| h.modes.get(m))) {
+            Some(mode) => mode.to_string(),
+            None if conf.enabled => "Custom mode".to_owned(),
+            None => "Preferred mode".to_owned(),
+        };
+        let rotation = match conf.transform {
+            wl_output::Transform::_90 => "90°",
+            wl_output::Transform::_180 => "180°",
+            wl_output::Transform::_270 => "270°",
+            wl_output::Transform::Normal => "0°",
+            _ => "Flipped",
+        };
+        let description = head.map_or_else(String::new, |h| h.description.clone());
+
+        Column::new()
+            .spacing(4)
+            .push(
+                Row::new()
+                    .spacing(8)
+                    .align_items(Align::Center)
+                    .push(Text::new(conf.name.clone()).size(18))
+                    .push(Text::new(description).size(12).width(Length::Fill)),
+            )
+            .push(
+                Row::new()
+                    .spacing(4)
+                    .align_items(Align::Center)
+                    .push(btn(
+                        &mut btns.enabled,
+                        if conf.enabled { "On" } else { "Off" }.to_owned(),
+                        Msg::ToggleEnabled(i),
+                    ))
+                    .push(if on_custom_mode(head) {
+                        Button::new(&mut btns.mode, Text::new(mode).size(14))
+                            .style(style::Toplevel)
+                            .width(Length::Fill)
+                    } else {
+                        btn(&mut btns.mode, mode, Msg::NextMode(i)).width(Length::Fill)
+                    })
+                    .push(btn(&mut btns.scale_down, "−".to_owned(), Msg::Scale(i, -SCALE_STEP)))
+                    .push(Text::new(format!("{:.2}×", conf.scale)).size(14))
+                    .push(btn(&mut btns.scale_up, "+".to_owned(), Msg::Scale(i, SCALE_STEP)))
+                    .push(btn(&mut btns.rotate, rotation.to_owned(), Msg::Rotate(i)))
+                    .push(btn(&mut btns.left, "◀".to_owned(), Msg::MoveLeft(i)))
+                    .push(btn(&mut btns.right, "▶".to_owned(), Msg::MoveRight(i))),
+            )
+            .into()
+    }
+}
+
+/// A custom mode can't be set again after switching away from it (see `HeadConfig::mode`),
+/// so reverting wouldn't bring it back: such heads keep their mode
+fn on_custom_mode(head: Option<&OutputHead>) -> bool {
+    head.map_or(false, |h| h.enabled && h.current_mode.is_none())
+}
+
+impl DesktopSurface for DisplaySettings {
+    fn layer_props(&self) -> LayerProps {
+        LayerProps {
+            layer: layer_shell::Layer::Overlay,
+            namespace: "waysmoke-displays".to_owned(),
+            anchor: layer_surface::Anchor::Left
+                | layer_surface::Anchor::Top
+                | layer_surface::Anchor::Right
+                | layer_surface::Anchor::Bottom,
+            exclusive_zone: -1,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl IcedSurface for DisplaySettings {
+    type Message = Msg;
+
+    fn view(&mut self) -> Element<Self::Message> {
+        use iced_native::*;
+
+        let mut elems = Column::new().spacing(16).push(
+            Text::new("Displays")
+                .size(20)
+                .horizontal_alignment(HorizontalAlignment::Center),
+        );
+
+        if !self.services.outputs.is_available() {
+            elems = elems.push(Text::new("The compositor does not support configuring displays").size(16));
+        }
+
+        let heads = self.services.outputs.heads();
+        for (i, (conf, btns)) in self.config.iter().zip(self.head_btns.iter_mut()).enumerate() {
+            let head = heads.iter().find(|h| h.name == conf.name);
+            elems = elems.push(Self::head_row(conf, head, i, btns));
+        }
+        drop(heads);
+
+        if let Some(ref status) = self.status {
+            elems = elems.push(Text::new(status.clone()).size(16));
+        }
+
+        let action_btn = |state, label: &str, msg, typ| {
+            Button::new(
+                state,
+                Text::new(label)
+                    .size(18)
+                    .horizontal_alignment(HorizontalAlignment::Center),
+            )
+            .on_press(msg)
+            .width(Length::Fill)
+            .style(style::Action(typ))
+        };
+        elems = elems.push(match self.revert {
+            Some(ref revert) => Column::new()
+                .spacing(16)
+                .push(Text::new(format!("Keep these settings? Reverting in {} s", revert.seconds_left())).size(18))
+                .push(
+                    Row::new()
+                        .spacing(16)
+                        .push(action_btn(
+                            &mut self.revert_btn,
+                            "Revert",
+                            Msg::Revert,
+                            style::ActionType::Bad,
+                        ))
+                        .push(action_btn(
+                            &mut self.keep_btn,
+                            "Keep",
+                            Msg::Keep,
+                            style::ActionType::Good,
+                        )),
+                ),
+            None => Column::new().push(
+                Row::new()
+                    .spacing(16)
+                    .push(action_btn(
+                        &mut self.close_btn,
+                        "Close",
+                        Msg::Close,
+                        style::ActionType::Bad,
+                    ))
+                    .push(action_btn(
+                        &mut self.apply_btn,
+                        "Apply",
+                        Msg::Apply,
+                        style::ActionType::Good,
+                    )),
+            ),
+        });
+
+        let dialog = Container::new(elems)
+            .style(style::Dialog)
+            .width(Length::Units(560))
+            .padding(16);
+
+        Container::new(Column::new().push(dialog))
+            .style(style::DarkBar)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .center_x()
+            .center_y()
+            .into()
+    }
+
+    fn retained_images(&mut self) -> Vec<wstk::ImageHandle> {
+        vec![]
+    }
+
+    async fn update(&mut self, message: Self::Message) {
+        match message {
+            Msg::ToggleEnabled(i) => {
+                let conf = &mut self.config[i];
+                conf.enabled = !conf.enabled;
+                if conf.enabled && conf.mode.is_none() {
+                    let heads = self.services.outputs.heads();
+                    conf.mode = heads
+                        .iter()
+                        .find(|h| h.name == conf.name)
+                        .and_then(|h| h.modes.iter().position(|m| m.preferred));
+                }
+                if conf.enabled {
+                    self.place(i, true);
+                } else {
+                    self.edited = true;
+                }
+            }
+            Msg::NextMode(i) => {
+                let conf = &mut self.config[i];
+                let heads = self.services.outputs.heads();
+                let head = heads.iter().find(|h| h.name == conf.name);
+                let num_modes = if on_custom_mode(head) {
+                    0
+                } else {
+                    head.map_or(0, |h| h.modes.len())
+                };
+                drop(heads);
+                if num_modes > 0 {
+                    conf.mode = Some(conf.mode.map_or(0, |m| (m + 1) % num_modes));
+                    self.place(i, false);
+                }
+            }
+            Msg::Scale(i, delta) => {
+                let conf = &mut self.config[i];
+                conf.scale = (conf.scale + delta).max(0.5).min(4.0);
+                self.place(i, false);
+            }
+            Msg::Rotate(i) => {
+                let conf = &mut self.config[i];
+                conf.transform = match conf.transform {
+                    wl_output::Transform::Normal => wl_output::Transform::_90,
+                    wl_output::Transform::_90 => wl_output::Transform::_180,
+                    wl_output::Transform::_180 => wl_output::Transform::_270,
+                    _ => wl_output::Transform::Normal,
+                };
+                self.place(i, false);
+            }
+            Msg::MoveLeft(i) => {
+                if i > 0 {
+                    self.swap(i - 1);
+                }
+            }
+            Msg::MoveRight(i) => {
+                if i + 1 < self.config.len() {
+                    self.swap(i);
+                }
+            }
+            Msg::Apply => {
+                let previous = self.services.outputs.current_config();
+                let config = self.config.clone();
+                match self.apply(config).await {
+                    ConfigResult::Succeeded => {
+                        self.revert = Some(Revert {
+                            previous,
+                            deadline: Instant::now() + REVERT_TIMEOUT,
+                        })
+                    }
+                    ConfigResult::Failed => (),
+                    ConfigResult::Cancelled => self.reload(),
+                }
+            }
+            Msg::Keep => {
+                self.revert = None;
+                self.reload();
+            }
+            Msg::Revert => {
+                if let Some(revert) = self.revert.take() {
+                    self.apply(revert.previous).await;
+                }
+                self.reload();
+            }
+            Msg::Close => self.closing = true,
+        }
+    }
+
+    async fn run(&mut self) -> Action {
+        if self.closing {
+            return Action::Close;
+        }
+        // run() is called again after every event, so the wait is derived from the deadline
+        // rather than started over: redraw on each whole second left, revert at the end
+        let countdown = match self.revert {
+            Some(ref revert) => {
+                // whole milliseconds, rounded up so that it doesn't wake up just before
+                let ms = ((revert.remaining().as_nanos() + 999_999) / 1_000_000) as u64;
+                let wait = match ms % 1000 {
+                    0 => ms.min(1000),
+                    to_next_second => to_next_second,
+                };
+                glib::timeout_future(Duration::from_millis(wait)).boxed_local()
+            }
+            None => future::pending().boxed_local(),
+        };
+        futures::select! {
+            () = self.services.outputs.subscribe().fuse() => {
+                // don't throw away the user's changes, applying will tell them if it's outdated
+                if !self.edited && self.revert.is_none() {
+                    self.reload();
+                }
+            },
+            () = countdown.fuse() => {
+                if self.revert.as_ref().map_or(false, |r| r.remaining() == Duration::from_secs(0)) {
+                    let previous = self.revert.take().unwrap().previous;
+                    self.apply(previous).await;
+                    self.reload();
+                }
+            },
+        }
+        Action::Rerender
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{style, svc, util::*};
-use futures::prelude::*;
-use std::cell::Cell;
+use futures::{channel::mpsc, prelude::*};
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
 use wstk::*;
 
 lazy_static::lazy_static! {
@@ -9,7 +12,6 @@ lazy_static::lazy_static! {
 }
 
 pub const ICON_SIZE: u16 = 48;
-pub const POPOVER_HEIGHT_MAX: u16 = 420;
 pub const TOPLEVELS_WIDTH: u16 = 290;
 pub const APP_PADDING: u16 = 4;
 pub const DOCK_PADDING: u16 = 4;
@@ -17,11 +19,17 @@ pub const DOCK_GAP: u16 = 8;
 pub const BAR_HEIGHT: u16 = 10;
 pub const DOCK_HEIGHT: u16 = ICON_SIZE + APP_PADDING * 2 + DOCK_PADDING * 2;
 pub const DOCK_AND_GAP_HEIGHT: u16 = DOCK_HEIGHT + DOCK_GAP;
+pub const REVEAL_DURATION: Duration = Duration::from_millis(160);
+pub const POPOVER_DURATION: Duration = Duration::from_millis(120);
+/// How far below its place the popover starts rising from
+pub const POPOVER_RISE: f32 = 12.0;
+pub const HIGHLIGHT_DURATION: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Clone)]
 pub enum DockletMsg {
     Hover,
     App(app::Msg),
+    NightLight(night_light::Msg),
 }
 
 #[derive(Debug, Clone)]
@@ -31,22 +39,28 @@ pub enum Msg {
 
 #[async_trait(?Send)]
 pub trait Docklet {
-    fn widget(&mut self) -> Element<DockletMsg>;
+    /// `highlight` goes from 0 to 1 as the docklet gets hovered
+    fn widget(&mut self, highlight: f32) -> Element<DockletMsg>;
     fn width(&self) -> u16;
-    fn retained_icon(&self) -> Option<wstk::ImageHandle> {
-        None
+    /// Images shown by the docklet and its popover
+    fn retained_images(&self) -> Vec<wstk::ImageHandle> {
+        Vec::new()
     }
     fn popover(&mut self) -> Option<Element<DockletMsg>> {
         None
     }
-    fn update(&mut self, msg: DockletMsg);
+    /// `seat` is the one whose input produced the message
+    fn update(&mut self, msg: DockletMsg, seat: &wl_seat::WlSeat);
     async fn run(&mut self);
 }
 
 mod app;
+mod night_light;
 mod power;
 
-fn popover<'a>(icon_offset: i16, content: Element<'a, Msg>, popover_region: &'a Cell<Rectangle>) -> Element<'a, Msg> {
+/// Docklet popover contents in a box with a triangle pointing down at the docklet,
+/// rising into place as `appear` goes from 0 to 1
+fn popover(content: Element<Msg>, appear: f32) -> Element<Msg> {
     use iced_graphics::{
         triangle::{Mesh2D, Vertex2D},
         Primitive,
@@ -81,28 +95,14 @@ fn popover<'a>(icon_offset: i16, content: Element<'a, Msg>, popover_region: &'a
     .width(Length::Units(16))
     .height(Length::Units(8));
 
-    let content_col = GetRegion::new(
-        popover_region,
+    Slide::new(
         Column::new()
             .align_items(Align::Center)
             .push(content_box)
             .push(triangle),
-    );
-
-    let mut offset_row = Row::new().height(Length::Shrink);
-    if icon_offset < 0 {
-        offset_row = offset_row.push(Space::new(Length::Units(-icon_offset as _), Length::Units(0)));
-    }
-    offset_row = offset_row.push(content_col);
-    if icon_offset > 0 {
-        offset_row = offset_row.push(Space::new(Length::Units(icon_offset as _), Length::Units(0)));
-    }
-    Container::new(offset_row)
-        .width(Length::Fill)
-        .height(Length::Units(POPOVER_HEIGHT_MAX))
-        .center_x()
-        .align_y(Align::End)
-        .into()
+    )
+    .offset(0.0, (1.0 - appear) * POPOVER_RISE)
+    .into()
 }
 
 pub struct Dock {
@@ -110,11 +110,21 @@ pub struct Dock {
     is_pointed: bool,
     is_touched: bool,
     hovered_docklet: Option<usize>,
+    input_seat: Option<wl_seat::WlSeat>,
+    toplevel_events: mpsc::UnboundedReceiver<toplevels::ToplevelEvent>,
 
     dock_region: Cell<Rectangle>,
-    popover_region: Cell<Rectangle>,
+
+    /// Time of the frame being drawn
+    now: Instant,
+    reveal: Tween,
+    popover: Tween,
+    popover_docklet: Option<usize>,
+    /// By docklet index
+    highlights: Vec<Tween>,
 
     apps: Vec<app::AppDocklet>,
+    night_light: Option<night_light::NightLightDocklet>,
     power: power::PowerDocklet,
 }
 
@@ -126,9 +136,16 @@ impl Dock {
             is_pointed: false,
             is_touched: false,
             hovered_docklet: None,
+            input_seat: None,
+            toplevel_events: services.toplevels.events(),
             dock_region: Default::default(),
-            popover_region: Default::default(),
+            now: Instant::now(),
+            reveal: Tween::new(0.0, REVEAL_DURATION, Easing::EaseOut),
+            popover: Tween::new(0.0, POPOVER_DURATION, Easing::EaseOut),
+            popover_docklet: None,
+            highlights: Vec::new(),
             apps: Vec::new(),
+            night_light: night_light::NightLightDocklet::new(services),
             power,
         }
     }
@@ -171,10 +188,37 @@ impl Dock {
         });
     }
 
+    /// Only the app list depends on which toplevels exist, the rest only needs a render, or not even that
+    fn on_toplevel_events(&mut self, events: impl Iterator<Item = toplevels::ToplevelEvent>) -> Action {
+        use toplevels::ToplevelEvent::*;
+
+        let (mut apps_changed, mut looks_changed) = (false, false);
+        for event in events {
+            match event {
+                Added(_) | Removed(_) => apps_changed = true,
+                Changed { fields, .. } => {
+                    apps_changed |= fields.app_id;
+                    // titles and nesting are only shown in popovers, state is shown as the active app highlight
+                    looks_changed |=
+                        fields.state || ((fields.title || fields.parent) && self.hovered_docklet().is_some());
+                }
+            }
+        }
+        if apps_changed {
+            self.update_apps();
+        }
+        if apps_changed || looks_changed {
+            Action::Rerender
+        } else {
+            Action::DoNothing
+        }
+    }
+
     fn docklets(&self) -> impl Iterator<Item = &dyn Docklet> {
         self.apps
             .iter()
             .map(|x| &*x as &dyn Docklet)
+            .chain(self.night_light.iter().map(|x| x as &dyn Docklet))
             .chain(std::iter::once(&self.power as &dyn Docklet))
     }
 
@@ -182,14 +226,10 @@ impl Dock {
         self.apps
             .iter_mut()
             .map(|x| &mut *x as &mut dyn Docklet)
+            .chain(self.night_light.iter_mut().map(|x| x as &mut dyn Docklet))
             .chain(std::iter::once(&mut self.power as &mut dyn Docklet))
     }
 
-    fn width(&self) -> u16 {
-        let (wid, cnt) = self.docklets().fold((0, 0), |(w, c), d| (w + d.width(), c + 1));
-        wid + DOCK_PADDING * (std::cmp::max(cnt as u16, 1) - 1) + DOCK_PADDING * 2
-    }
-
     fn center_of_docklet(&self, id: usize) -> u16 {
         DOCK_PADDING
             + self.docklets().take(id).fold(0, |x, d| x + d.width() + DOCK_PADDING)
@@ -203,14 +243,45 @@ impl Dock {
             None
         }
     }
+
+    /// Points the animations at the current state
+    fn animate(&mut self) {
+        let now = self.now;
+        self.reveal
+            .set(if self.is_pointed || self.is_touched { 1.0 } else { 0.0 }, now);
+
+        let hovered = self.hovered_docklet();
+        if hovered != self.popover_docklet {
+            // every docklet's popover appears anew, moving between them shouldn't look like one popover moving
+            self.popover.jump(0.0);
+            self.popover.set(1.0, now);
+            self.popover_docklet = hovered;
+        }
+
+        let num_docklets = self.docklets().count();
+        self.highlights
+            .resize_with(num_docklets, || Tween::new(0.0, HIGHLIGHT_DURATION, Easing::EaseOut));
+        for (i, highlight) in self.highlights.iter_mut().enumerate() {
+            highlight.set(if hovered == Some(i) { 1.0 } else { 0.0 }, now);
+        }
+    }
 }
 
 impl DesktopSurface for Dock {
-    fn setup_lsh(&self, layer_surface: &Main<layer_surface::ZwlrLayerSurfaceV1>) {
-        layer_surface
-            .set_anchor(layer_surface::Anchor::Left | layer_surface::Anchor::Right | layer_surface::Anchor::Bottom);
-        layer_surface.set_size(0, (BAR_HEIGHT + DOCK_AND_GAP_HEIGHT + POPOVER_HEIGHT_MAX) as _);
-        layer_surface.set_exclusive_zone(BAR_HEIGHT as _);
+    fn layer_props(&self) -> LayerProps {
+        LayerProps {
+            // while revealed, the dock goes above fullscreen windows too
+            layer: if self.is_pointed || self.is_touched {
+                layer_shell::Layer::Overlay
+            } else {
+                layer_shell::Layer::Top
+            },
+            namespace: "waysmoke-dock".to_owned(),
+            anchor: layer_surface::Anchor::Left | layer_surface::Anchor::Right | layer_surface::Anchor::Bottom,
+            size: (0, (BAR_HEIGHT + DOCK_AND_GAP_HEIGHT) as _),
+            exclusive_zone: BAR_HEIGHT as _,
+            ..Default::default()
+        }
     }
 }
 
@@ -223,33 +294,17 @@ impl IcedSurface for Dock {
 
         let mut col = Column::new().width(Length::Fill);
 
-        let dock_width = self.width();
-        let mut has_popover = false;
-        if let Some(docklet_idx) = self.hovered_docklet() {
-            let our_center = self.center_of_docklet(docklet_idx);
-            let docklet = self.docklets().nth(docklet_idx).unwrap();
-            if let Some(oh) = unsafe { &mut *(docklet as *const dyn Docklet as *mut dyn Docklet) }.popover() {
-                let i = oh.map(move |m| Msg::IdxMsg(docklet_idx, m)).into();
-                col = col.push(popover(
-                    (dock_width as i16 / 2 - our_center as i16) * 2, // XXX: why is the *2 needed?
-                    i,
-                    &self.popover_region,
-                ));
-                has_popover = true;
-            }
-        }
-        if !has_popover {
-            self.popover_region.set(Default::default()); // probably not the best way to clear input region but w/e
-            col = col.push(Space::with_height(Length::Units(POPOVER_HEIGHT_MAX)));
-        }
-
-        if self.is_pointed || self.is_touched {
+        let reveal = self.reveal.value(self.now);
+        if reveal > 0.0 {
+            let now = self.now;
+            let highlights = &self.highlights;
             let row = self.docklets().enumerate().fold(
                 Row::new().align_items(Align::Center).spacing(DOCK_PADDING),
                 |row, (i, docklet)| {
+                    let highlight = highlights.get(i).map_or(0.0, |h| h.value(now));
                     row.push(
                         unsafe { &mut *(docklet as *const dyn Docklet as *mut dyn Docklet) }
-                            .widget()
+                            .widget(highlight)
                             .map(move |m| Msg::IdxMsg(i, m)),
                     )
                 },
@@ -274,7 +329,16 @@ impl IcedSurface for Dock {
             .height(Length::Units(DOCK_HEIGHT))
             .center_x();
 
-            col = col.push(dock).push(Space::with_height(Length::Units(DOCK_GAP)));
+            // slides up from behind the bar
+            col = col.push(
+                Slide::new(
+                    Column::new()
+                        .width(Length::Fill)
+                        .push(dock)
+                        .push(Space::with_height(Length::Units(DOCK_GAP))),
+                )
+                .offset(0.0, (1.0 - reveal) * DOCK_AND_GAP_HEIGHT as f32),
+            );
         } else {
             col = col.push(Space::with_height(Length::Units(DOCK_AND_GAP_HEIGHT)));
         }
@@ -318,47 +382,87 @@ impl IcedSurface for Dock {
         };
         let bar = Rectangle {
             x: 0,
-            y: (DOCK_AND_GAP_HEIGHT + POPOVER_HEIGHT_MAX + BAR_HEIGHT - hover_zone_height) as _,
+            y: (DOCK_AND_GAP_HEIGHT + BAR_HEIGHT - hover_zone_height) as _,
             width,
             height: hover_zone_height as _,
         };
         let mut result = vec![bar];
         if self.is_pointed || self.is_touched {
             result.push(pad(self.dock_region.get().snap(), 12));
-            if self.hovered_docklet().is_some() {
-                result.push(pad(self.popover_region.get().snap(), 6));
-            }
         }
         Some(result)
     }
 
+    fn popup_anchor(&self) -> Option<PopupAnchor> {
+        let docklet_idx = self.hovered_docklet()?;
+        let dock = self.dock_region.get();
+        let width = self.docklets().nth(docklet_idx)?.width();
+        Some(PopupAnchor {
+            x: dock.x as i32 + (self.center_of_docklet(docklet_idx) - width / 2) as i32,
+            y: dock.y as _,
+            width: width as _,
+            height: DOCK_HEIGHT as _,
+            grab: false,
+        })
+    }
+
+    fn popup_view(&mut self) -> Option<Element<Self::Message>> {
+        let docklet_idx = self.hovered_docklet()?;
+        let appear = self.popover.value(self.now);
+        let content = self.docklets_mut().nth(docklet_idx)?.popover()?;
+        Some(popover(content.map(move |m| Msg::IdxMsg(docklet_idx, m)), appear))
+    }
+
     fn retained_images(&mut self) -> Vec<wstk::ImageHandle> {
-        self.docklets().flat_map(|d| d.retained_icon()).collect()
+        self.docklets().flat_map(|d| d.retained_images()).collect()
     }
 
     async fn update(&mut self, message: Self::Message) {
         match message {
             Msg::IdxMsg(i, DockletMsg::Hover) => self.hovered_docklet = Some(i),
             Msg::IdxMsg(i, dmsg) => {
+                let seat = match self.input_seat.clone() {
+                    Some(seat) => seat,
+                    None => {
+                        eprintln!("WARN: docklet message without input");
+                        return;
+                    }
+                };
                 let docklet = self.docklets().nth(i).unwrap();
-                unsafe { &mut *(docklet as *const dyn Docklet as *mut dyn Docklet) }.update(dmsg)
+                unsafe { &mut *(docklet as *const dyn Docklet as *mut dyn Docklet) }.update(dmsg, &seat)
             }
         }
+        self.animate();
     }
 
     async fn run(&mut self) -> Action {
         // ARGH: avoiding multiple mutable borrows is so hard in a situation like this!
-        //       even sel's Drop (!) mutably borrows self.apps, hence the clone/drop dance.
-        let sel = future::select(
-            self.services.toplevels.subscribe(),
-            future::select_all(self.docklets_mut().map(|x| x.run())),
+        //       docklets_mut() would borrow all of self, so the fields are borrowed separately here.
+        let docklets = self
+            .apps
+            .iter_mut()
+            .map(|x| &mut *x as &mut dyn Docklet)
+            .chain(self.night_light.iter_mut().map(|x| x as &mut dyn Docklet))
+            .chain(std::iter::once(&mut self.power as &mut dyn Docklet));
+        let event = match future::select(
+            self.toplevel_events.select_next_some(),
+            future::select_all(docklets.map(|x| x.run())),
         )
-        .await;
-        if let future::Either::Left(((), _)) = sel {
-            drop(sel);
-            self.update_apps();
+        .await
+        {
+            future::Either::Left((event, _)) => event,
+            future::Either::Right(_) => return Action::Rerender,
+        };
+        // handle a burst of title changes (terminals, browsers) at once
+        let mut events = vec![event];
+        while let Ok(Some(event)) = self.toplevel_events.try_next() {
+            events.push(event);
         }
-        Action::Rerender
+        self.on_toplevel_events(events.into_iter())
+    }
+
+    fn set_input_seat(&mut self, seat: &wl_seat::WlSeat) {
+        self.input_seat = Some(seat.clone());
     }
 
     async fn on_pointer_enter(&mut self) {
@@ -377,4 +481,90 @@ impl IcedSurface for Dock {
     async fn on_touch_leave(&mut self) {
         self.is_touched = false;
     }
+
+    fn on_frame(&mut self, now: Instant) {
+        self.now = now;
+        self.animate();
+    }
+
+    fn is_animating(&self) -> bool {
+        self.reveal.is_running(self.now)
+            || self.popover.is_running(self.now)
+            || self.highlights.iter().any(|h| h.is_running(self.now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::rc::Rc;
+
+    const WIDTH: u32 = 400;
+    const HEIGHT: u32 = (BAR_HEIGHT + DOCK_AND_GAP_HEIGHT) as u32;
+
+    fn reference(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/reference")
+            .join(name)
+    }
+
+    /// No bus and no compositor: no toplevels, no media, no battery, no night light
+    fn offline_services() -> &'static svc::Services {
+        Box::leak(Box::new(svc::Services {
+            toplevels: Rc::new(ToplevelService::new()),
+            outputs: Rc::new(OutputConfigService::new()),
+            power: svc::power::PowerService::with_state(svc::power::PowerState { total: None }),
+            media: svc::media::MediaService::with_state(Default::default()),
+            capture: None,
+            night_light: svc::night_light::NightLightService::new(None),
+        }))
+    }
+
+    fn test_app() -> apps::App {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/apps/org.example.Test.desktop");
+        apps::App {
+            id: "org.example.Test".to_owned(),
+            info: gio::DesktopAppInfo::from_filename(path).unwrap(),
+        }
+    }
+
+    #[test]
+    fn reveals_and_shows_popovers() {
+        block_on(async {
+            let services = offline_services();
+            let mut dock = Dock::new(services);
+            dock.apps.push(app::AppDocklet::new(services, test_app()));
+            let mut h = Headless::new(dock, WIDTH, HEIGHT, 1.0);
+            // only the bar while hidden
+            let hidden = h.render().await;
+            assert!((0..DOCK_AND_GAP_HEIGHT as u32).all(|y| hidden.get_pixel(WIDTH / 2, y).0[3] == 0));
+            assert_eq!(hidden.get_pixel(WIDTH / 2, HEIGHT - BAR_HEIGHT as u32 / 2).0[3], 255);
+
+            // pointing at the bar slides the dock up
+            h.pointer_enter(200.0, HEIGHT as f64 - 2.0).await;
+            h.advance(REVEAL_DURATION * 2);
+            let revealed = h.render().await;
+            wstk::assert_matches_reference(&revealed, reference("dock.png"));
+
+            // the app is first: the dock is 2 docklets wide, centered
+            let dock_width = 2 * (ICON_SIZE + APP_PADDING * 2) + 3 * DOCK_PADDING;
+            let app_x = (WIDTH as u16 - dock_width) / 2 + DOCK_PADDING + ICON_SIZE / 2 + APP_PADDING;
+            h.pointer_motion(app_x as f64, (DOCK_HEIGHT / 2) as f64).await;
+            h.advance(POPOVER_DURATION * 2);
+            h.frame().await;
+            let anchor = h.popup_anchor().unwrap();
+            assert_eq!(anchor.width, (ICON_SIZE + APP_PADDING * 2) as i32);
+            wstk::assert_matches_reference(&h.snapshot(), reference("dock-hovered.png"));
+            wstk::assert_matches_reference(&h.popup_snapshot().unwrap(), reference("dock-popover.png"));
+
+            // the power docklet has no popover
+            h.pointer_motion(
+                (app_x + ICON_SIZE + APP_PADDING * 2 + DOCK_PADDING) as f64,
+                (DOCK_HEIGHT / 2) as f64,
+            )
+            .await;
+            assert!(h.popup_snapshot().is_none());
+        });
+    }
 }
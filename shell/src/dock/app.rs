@@ -1,6 +1,10 @@
 use crate::{dock::*, style};
 use gio::prelude::AppInfoExt;
-use std::cell::Ref;
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
 
 lazy_static::lazy_static! {
     static ref PLAY_ICON: wstk::ImageHandle =
@@ -11,13 +15,40 @@ lazy_static::lazy_static! {
     //     icons::icon_from_path(apps::icon("media-skip-forward-symbolic"));
 }
 
+const CHILD_INDENT: u16 = 16;
+const MAX_CHILD_DEPTH: usize = 8;
+const THUMBNAIL_WIDTH: u16 = 160;
+const THUMBNAIL_HEIGHT: u16 = 90;
+/// Captured at this multiple of the thumbnail size, to stay sharp on scaled outputs
+const THUMBNAIL_DENSITY: u32 = 2;
+/// How often the thumbnails are recaptured while the popover is open
+const THUMBNAIL_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 pub enum Msg {
     ActivateApp,
+    LaunchApp,
     ActivateToplevel(usize),
+    CloseToplevel(usize),
     MediaControl(usize, &'static str),
 }
 
+/// The latest capture of each window, kept to show right away when the popover opens again.
+/// Captures run as tasks of their own: the dock drops run() whenever something else happens first.
+#[derive(Default)]
+struct Thumbnails {
+    images: RefCell<HashMap<wstk::toplevels::ToplevelKey, wstk::ImageHandle>>,
+    at: Cell<Option<Instant>>,
+    capturing: Cell<bool>,
+    captured: wstk::event_listener::Event,
+}
+
+#[derive(Default)]
+struct ToplevelBtns {
+    activate: iced_native::button::State,
+    close: iced_native::button::State,
+}
+
 #[derive(Default)]
 struct MediaBtns {
     play: iced_native::button::State,
@@ -31,8 +62,10 @@ pub struct AppDocklet {
     button: iced_native::button::State,
     evl: addeventlistener::State,
     toplevels_scrollable: iced_native::scrollable::State,
-    toplevels_buttons: Vec<iced_native::button::State>,
+    toplevels_buttons: Vec<ToplevelBtns>,
     media_buttons: Vec<MediaBtns>,
+    thumbnails: Rc<Thumbnails>,
+    popover_shown_at: Option<Instant>,
 }
 
 impl AppDocklet {
@@ -50,6 +83,8 @@ impl AppDocklet {
             toplevels_scrollable: Default::default(),
             toplevels_buttons: Default::default(),
             media_buttons: Default::default(),
+            thumbnails: Default::default(),
+            popover_shown_at: None,
         }
     }
 
@@ -64,12 +99,13 @@ impl AppDocklet {
 
 #[async_trait(?Send)]
 impl Docklet for AppDocklet {
-    fn widget(&mut self) -> Element<DockletMsg> {
+    fn widget(&mut self, highlight: f32) -> Element<DockletMsg> {
         use iced_native::*;
 
         let running = our_toplevels(&self.services.toplevels.state(), self.id())
             .next()
             .is_some();
+        let active = our_toplevels(&self.services.toplevels.state(), self.id()).any(|t| t.state.activated);
 
         let big_button = Button::new(&mut self.button, icons::icon_widget(self.icon.clone(), ICON_SIZE))
             .style(style::Dock(style::DARK_COLOR))
@@ -113,16 +149,24 @@ impl Docklet for AppDocklet {
             );
         }
 
-        let listener = AddEventListener::new(&mut self.evl, content).on_pointer_enter(DockletMsg::Hover);
+        let listener = AddEventListener::new(&mut self.evl, content)
+            .on_pointer_enter(DockletMsg::Hover)
+            .on_middle_click(|_| DockletMsg::App(Msg::LaunchApp));
 
         Container::new(listener)
             .center_x()
             .center_y()
-            .style(style::Dock(if running {
-                style::RUNNING_DARK_COLOR
-            } else {
-                style::DARK_COLOR
-            }))
+            .style(style::Dock(animation::mix_colors(
+                if active {
+                    style::ACTIVE_DARK_COLOR
+                } else if running {
+                    style::RUNNING_DARK_COLOR
+                } else {
+                    style::DARK_COLOR
+                },
+                style::HOVER_DARK_COLOR,
+                highlight,
+            )))
             .into()
     }
 
@@ -133,26 +177,47 @@ impl Docklet for AppDocklet {
                 .fold(0, |acc, _| acc + ICON_SIZE / 2 + APP_PADDING * 2)
     }
 
-    fn retained_icon(&self) -> Option<wstk::ImageHandle> {
-        Some(self.icon.clone())
+    fn retained_images(&self) -> Vec<wstk::ImageHandle> {
+        std::iter::once(self.icon.clone())
+            .chain(self.thumbnails.images.borrow().values().cloned())
+            .collect()
     }
 
     fn popover(&mut self) -> Option<Element<DockletMsg>> {
         use iced_native::*;
 
-        while self.toplevels_buttons.len() < our_toplevels(&self.services.toplevels.state(), &self.app.id).count() {
+        self.popover_shown_at = Some(Instant::now());
+        let toplevels = self.services.toplevels.state();
+        let windows = our_windows(&toplevels, &self.app.id);
+        while self.toplevels_buttons.len() < windows.len() {
             self.toplevels_buttons.push(Default::default());
         }
         let mut btns = Scrollable::new(&mut self.toplevels_scrollable).spacing(2);
-        for (i, (topl, btn)) in our_toplevels(&self.services.toplevels.state(), &self.app.id)
-            .zip(self.toplevels_buttons.iter_mut())
-            .enumerate()
-        {
+        for (i, ((depth, key, topl), btn)) in windows.iter().zip(self.toplevels_buttons.iter_mut()).enumerate() {
+            let mut window = Column::new().spacing(2);
+            if let Some(wstk::ImageHandle::Raster(thumbnail)) = self.thumbnails.images.borrow().get(*key) {
+                window = window.push(
+                    Image::new(thumbnail.clone())
+                        .width(Length::Units(THUMBNAIL_WIDTH))
+                        .height(Length::Units(THUMBNAIL_HEIGHT)),
+                );
+            }
+            window = window.push(Text::new(topl.title.clone()).size(14));
             btns = btns.push(
-                Button::new(btn, Text::new(topl.title.clone()).size(14))
-                    .style(style::Toplevel)
-                    .width(Length::Fill)
-                    .on_press(DockletMsg::App(Msg::ActivateToplevel(i))),
+                Row::new()
+                    .spacing(2)
+                    .push(Space::with_width(Length::Units(CHILD_INDENT * *depth as u16)))
+                    .push(
+                        Button::new(&mut btn.activate, window)
+                            .style(style::Toplevel)
+                            .width(Length::Fill)
+                            .on_press(DockletMsg::App(Msg::ActivateToplevel(i))),
+                    )
+                    .push(
+                        Button::new(&mut btn.close, Text::new("×").size(14))
+                            .style(style::Toplevel)
+                            .on_press(DockletMsg::App(Msg::CloseToplevel(i))),
+                    ),
             )
         }
         let title = Text::new(self.app.info.name().to_string())
@@ -169,21 +234,40 @@ impl Docklet for AppDocklet {
         )
     }
 
-    fn update(&mut self, msg: DockletMsg) {
+    fn update(&mut self, msg: DockletMsg, seat: &wl_seat::WlSeat) {
         match msg {
             DockletMsg::App(Msg::ActivateApp) => {
-                for topl in our_toplevels(&self.services.toplevels.state(), &self.app.id) {
-                    topl.handle.activate(&self.services.seat);
+                let toplevels = self.services.toplevels.state();
+                // clicking the focused app hides it
+                if let Some(topl) = our_toplevels(&toplevels, &self.app.id).find(|t| t.state.activated) {
+                    topl.set_minimized(true);
+                    return;
+                }
+                // toplevels are in most recently activated order, so this raises the window used last.
+                // without control over toplevels, launching again usually raises the running instance
+                if let Some(topl) = our_toplevels(&toplevels, &self.app.id).find(|t| t.can_control()) {
+                    topl.activate(seat);
                     return;
                 }
                 self.app.info.launch::<gio::AppLaunchContext>(&[], None).unwrap()
             }
+            // usually a new window, if the app doesn't just raise the running instance
+            DockletMsg::App(Msg::LaunchApp) => {
+                if let Err(e) = self.app.info.launch::<gio::AppLaunchContext>(&[], None) {
+                    eprintln!("Could not launch {}: {:?}", self.app.id, e);
+                }
+            }
             DockletMsg::App(Msg::ActivateToplevel(topli)) => {
-                our_toplevels(&self.services.toplevels.state(), &self.app.id)
-                    .nth(topli)
-                    .unwrap()
-                    .handle
-                    .activate(&self.services.seat);
+                let toplevels = self.services.toplevels.state();
+                if let Some((_, _, topl)) = our_windows(&toplevels, &self.app.id).get(topli) {
+                    topl.activate(seat);
+                }
+            }
+            DockletMsg::App(Msg::CloseToplevel(topli)) => {
+                let toplevels = self.services.toplevels.state();
+                if let Some((_, _, topl)) = our_windows(&toplevels, &self.app.id).get(topli) {
+                    topl.close();
+                }
             }
             DockletMsg::App(Msg::MediaControl(medi, op)) => {
                 self.services.media.control_player(
@@ -199,12 +283,69 @@ impl Docklet for AppDocklet {
     }
 
     async fn run(&mut self) {
-        let this = self;
-        futures::select! {
-            () = this.services.toplevels.subscribe().fuse() => (),
-            () = this.services.media.subscribe().fuse() => (),
+        // toplevel changes are handled by the dock, which renders all docklets anyway.
+        // the popover is only drawn while it's open, so recently drawn means open: keep its thumbnails live
+        let popover_open = self
+            .popover_shown_at
+            .map_or(false, |t| t.elapsed() < THUMBNAIL_INTERVAL * 2);
+        if !popover_open {
+            return self.services.media.subscribe().await;
+        }
+        if !self.thumbnails.capturing.get() {
+            let wait = self.thumbnails.at.get().map_or(Duration::from_secs(0), |t| {
+                THUMBNAIL_INTERVAL.saturating_sub(t.elapsed())
+            });
+            futures::select! {
+                () = self.services.media.subscribe().fuse() => return,
+                () = glib::timeout_future(wait).fuse() => (),
+            }
+            self.thumbnails.capturing.set(true);
+            glib::MainContext::default().spawn_local(capture_thumbnails(
+                self.services,
+                self.app.id.clone(),
+                self.thumbnails.clone(),
+            ));
+        }
+        // the capture goes on when this is dropped, the next run waits for it instead
+        let captured = self.thumbnails.captured.listen();
+        if self.thumbnails.capturing.get() {
+            futures::select! {
+                () = self.services.media.subscribe().fuse() => (),
+                () = captured.fuse() => (),
+            }
+        }
+    }
+}
+
+/// Recaptures the windows that can be captured, forgetting the ones that are gone
+async fn capture_thumbnails(services: &'static svc::Services, app_id: String, thumbnails: Rc<Thumbnails>) {
+    // no borrow of the toplevel state can be held while waiting
+    let windows = our_windows(&services.toplevels.state(), &app_id)
+        .into_iter()
+        .filter(|(_, _, topl)| services.capture.as_ref().map_or(false, |c| c.can_capture(topl)))
+        .map(|(_, key, topl)| (key.clone(), topl.clone()))
+        .collect::<Vec<_>>();
+    thumbnails
+        .images
+        .borrow_mut()
+        .retain(|key, _| windows.iter().any(|(k, _)| k == key));
+    for (key, topl) in windows {
+        let frame = match services.capture {
+            Some(ref capture) => capture.capture_toplevel(&topl).await,
+            None => None,
         };
+        if let Some(thumbnail) = frame.and_then(|f| {
+            f.thumbnail(
+                THUMBNAIL_WIDTH as u32 * THUMBNAIL_DENSITY,
+                THUMBNAIL_HEIGHT as u32 * THUMBNAIL_DENSITY,
+            )
+        }) {
+            thumbnails.images.borrow_mut().insert(key, thumbnail.into_image());
+        }
     }
+    thumbnails.at.set(Some(Instant::now()));
+    thumbnails.capturing.set(false);
+    thumbnails.captured.notify(usize::MAX);
 }
 
 // can't just have a method on self because rustc can't see through
@@ -216,6 +357,51 @@ fn our_toplevels<'a>(
     toplevels.values().filter(move |topl| topl.matches_id(id))
 }
 
+/// Our toplevels as shown in the popover: most recently activated first,
+/// each followed by its dialogs (with their nesting depth)
+fn our_windows<'a>(
+    toplevels: &'a wstk::toplevels::ToplevelStates,
+    id: &str,
+) -> Vec<(
+    usize,
+    &'a wstk::toplevels::ToplevelKey,
+    &'a wstk::toplevels::ToplevelState,
+)> {
+    fn push_with_children<'a>(
+        toplevels: &'a wstk::toplevels::ToplevelStates,
+        id: &str,
+        key: &'a wstk::toplevels::ToplevelKey,
+        depth: usize,
+        result: &mut Vec<(
+            usize,
+            &'a wstk::toplevels::ToplevelKey,
+            &'a wstk::toplevels::ToplevelState,
+        )>,
+    ) {
+        result.push((depth, key, toplevels.get(key).unwrap()));
+        if depth >= MAX_CHILD_DEPTH {
+            return;
+        }
+        for (child_key, _) in toplevels.children(key).filter(|(_, topl)| topl.matches_id(id)) {
+            push_with_children(toplevels, id, child_key, depth + 1, result);
+        }
+    }
+
+    let mut result = Vec::new();
+    for (key, _) in toplevels.iter().filter(|(_, topl)| {
+        // a dialog of another app's window (or of a closed one) is shown at the top level
+        topl.matches_id(id)
+            && topl
+                .parent
+                .as_ref()
+                .and_then(|parent| toplevels.get(parent))
+                .map_or(true, |parent| !parent.matches_id(id))
+    }) {
+        push_with_children(toplevels, id, key, 0, &mut result);
+    }
+    result
+}
+
 fn our_medias<'a>(
     medias: &'a Ref<'a, svc::media::MediaState>,
     id: &'a String,
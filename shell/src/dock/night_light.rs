@@ -0,0 +1,95 @@
+use crate::{dock::*, style, svc::night_light::*, util::apps};
+
+lazy_static::lazy_static! {
+    static ref NIGHT_LIGHT_ICON: wstk::ImageHandle =
+        icons::icon_from_path(apps::icon("night-light-symbolic"));
+}
+
+#[derive(Debug, Clone)]
+pub enum Msg {
+    Toggle,
+}
+
+pub struct NightLightDocklet {
+    button: iced_native::button::State,
+    evl: addeventlistener::State,
+    svc: &'static NightLightService,
+}
+
+impl NightLightDocklet {
+    /// None if night light can't work with the compositor
+    pub fn new(services: &'static svc::Services) -> Option<Self> {
+        if !services.night_light.is_available() {
+            return None;
+        }
+        Some(NightLightDocklet {
+            button: Default::default(),
+            evl: Default::default(),
+            svc: &services.night_light,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl Docklet for NightLightDocklet {
+    fn widget(&mut self, highlight: f32) -> Element<DockletMsg> {
+        use iced_native::*;
+
+        let button = Button::new(
+            &mut self.button,
+            icons::icon_widget(NIGHT_LIGHT_ICON.clone(), ICON_SIZE),
+        )
+        .style(style::Dock(style::DARK_COLOR))
+        .padding(APP_PADDING)
+        .on_press(DockletMsg::NightLight(Msg::Toggle));
+
+        let listener = AddEventListener::new(&mut self.evl, button).on_pointer_enter(DockletMsg::Hover);
+
+        Container::new(listener)
+            .center_x()
+            .center_y()
+            .style(style::Dock(animation::mix_colors(
+                if self.svc.is_active() {
+                    style::ACTIVE_DARK_COLOR
+                } else {
+                    style::DARK_COLOR
+                },
+                style::HOVER_DARK_COLOR,
+                highlight,
+            )))
+            .into()
+    }
+
+    fn width(&self) -> u16 {
+        ICON_SIZE + APP_PADDING * 2
+    }
+
+    fn retained_images(&self) -> Vec<wstk::ImageHandle> {
+        vec![NIGHT_LIGHT_ICON.clone()]
+    }
+
+    fn popover(&mut self) -> Option<Element<DockletMsg>> {
+        use iced_native::*;
+
+        let state = if self.svc.is_active() {
+            format!("Night light on, {}K", self.svc.config().night)
+        } else {
+            "Night light off".to_owned()
+        };
+        let mut col = Column::new().align_items(Align::Center).push(Text::new(state).size(16));
+        if let Some((hour, minute)) = self.svc.next_change() {
+            col = col.push(Text::new(format!("until {:02}:{:02}", hour, minute)).size(14));
+        }
+        Some(col.into())
+    }
+
+    fn update(&mut self, msg: DockletMsg, _seat: &wl_seat::WlSeat) {
+        if let DockletMsg::NightLight(Msg::Toggle) = msg {
+            self.svc.toggle();
+        }
+    }
+
+    async fn run(&mut self) {
+        self.svc.subscribe().await
+    }
+}
@@ -25,7 +25,7 @@ impl PowerDocklet {
 
 #[async_trait(?Send)]
 impl Docklet for PowerDocklet {
-    fn widget(&mut self) -> Element<DockletMsg> {
+    fn widget(&mut self, highlight: f32) -> Element<DockletMsg> {
         use iced_native::*;
 
         let img = icons::icon_widget(self.icon.clone(), ICON_SIZE);
@@ -36,7 +36,11 @@ impl Docklet for PowerDocklet {
             .center_x()
             .center_y()
             .padding(APP_PADDING)
-            .style(style::Dock(style::DARK_COLOR))
+            .style(style::Dock(animation::mix_colors(
+                style::DARK_COLOR,
+                style::HOVER_DARK_COLOR,
+                highlight,
+            )))
             .into()
     }
 
@@ -44,11 +48,11 @@ impl Docklet for PowerDocklet {
         ICON_SIZE + APP_PADDING * 2
     }
 
-    fn retained_icon(&self) -> Option<wstk::ImageHandle> {
-        Some(self.icon.clone())
+    fn retained_images(&self) -> Vec<wstk::ImageHandle> {
+        vec![self.icon.clone()]
     }
 
-    fn update(&mut self, _msg: DockletMsg) {}
+    fn update(&mut self, _msg: DockletMsg, _seat: &wl_seat::WlSeat) {}
 
     async fn run(&mut self) {
         self.svc.subscribe().await;
@@ -0,0 +1,277 @@
+//! What happens when the user is away, configured with e.g. `WAYSMOKE_IDLE=dim=240,lock=300,off=600`:
+//! seconds of inactivity before dimming the outputs, locking the session and turning the outputs off.
+//! Nothing happens while a media player is playing.
+
+use futures::prelude::*;
+use std::{
+    process,
+    time::{Duration, Instant},
+};
+use wstk::*;
+
+const DEFAULT_ACTIONS: &str = "dim=300,off=600";
+const LOCK_COMMAND: &str = "waysmoke-lock";
+const DIM_ALPHA: f32 = 0.6;
+const DIM_DURATION: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    Dim,
+    Lock,
+    /// DPMS off
+    OutputsOff,
+}
+
+impl IdleAction {
+    fn parse(x: &str) -> Option<IdleAction> {
+        match x {
+            "dim" => Some(IdleAction::Dim),
+            "lock" => Some(IdleAction::Lock),
+            "off" => Some(IdleAction::OutputsOff),
+            _ => None,
+        }
+    }
+}
+
+fn parse_actions(spec: &str) -> Vec<(IdleAction, Duration)> {
+    spec.split(',')
+        .filter(|a| !a.trim().is_empty())
+        .filter_map(|a| {
+            let mut kv = a.splitn(2, '=');
+            let action = kv.next().map(str::trim).and_then(IdleAction::parse);
+            let secs = kv.next().and_then(|s| s.trim().parse::<u64>().ok());
+            match (action, secs) {
+                (Some(action), Some(secs)) => Some((action, Duration::from_secs(secs))),
+                _ => {
+                    eprintln!("Ignoring invalid idle action '{}'", a);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Shades an output, fading in
+pub struct Dim {
+    now: Instant,
+    fade: Tween,
+}
+
+impl Dim {
+    fn new() -> Dim {
+        Dim {
+            now: Instant::now(),
+            fade: Tween::new(0.0, DIM_DURATION, Easing::EaseInOut),
+        }
+    }
+}
+
+impl DesktopSurface for Dim {
+    fn layer_props(&self) -> LayerProps {
+        LayerProps {
+            layer: layer_shell::Layer::Overlay,
+            namespace: "waysmoke-dim".to_owned(),
+            anchor: layer_surface::Anchor::Left
+                | layer_surface::Anchor::Top
+                | layer_surface::Anchor::Right
+                | layer_surface::Anchor::Bottom,
+            exclusive_zone: -1,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl IcedSurface for Dim {
+    type Message = ();
+
+    fn view(&mut self) -> Element<Self::Message> {
+        use iced_native::*;
+
+        Container::new(Space::new(Length::Fill, Length::Fill))
+            .style(style::Shade(DIM_ALPHA * self.fade.value(self.now)))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    // any input ends the idleness anyway, let it go through
+    fn input_region(&self, _width: u32, _height: u32) -> Option<Vec<Rectangle<u32>>> {
+        Some(Vec::new())
+    }
+
+    fn retained_images(&mut self) -> Vec<ImageHandle> {
+        Vec::new()
+    }
+
+    async fn update(&mut self, _message: Self::Message) {}
+
+    async fn run(&mut self) -> Action {
+        future::pending::<()>().await;
+        Action::DoNothing
+    }
+
+    fn on_frame(&mut self, now: Instant) {
+        self.now = now;
+        self.fade.set(1.0, now);
+    }
+
+    fn is_animating(&self) -> bool {
+        self.fade.is_running(self.now)
+    }
+}
+
+pub struct Idle<'a> {
+    env: &'a Environment<Env>,
+    display: &'a Display,
+    /// With whether the action currently is in effect, which lags behind the timeout's state
+    /// until reacting to it is done (that can be cancelled midway when the shell's loop moves on)
+    timeouts: Vec<(IdleAction, IdleTimeout, bool)>,
+    dim: Option<MultiMonitor<'a, IcedInstance<Dim>>>,
+    outputs_off: Option<OutputsOff>,
+    locker: Option<process::Child>,
+    inhibitor: Option<IdleInhibitor>,
+}
+
+impl<'a> Idle<'a> {
+    pub fn new(env: &'a Environment<Env>, display: &'a Display) -> Idle<'a> {
+        let spec = std::env::var("WAYSMOKE_IDLE").unwrap_or_else(|_| DEFAULT_ACTIONS.to_owned());
+        let actions = parse_actions(&spec);
+        let timeouts = match IdleService::new(env) {
+            Some(service) => actions
+                .into_iter()
+                .map(|(action, timeout)| (action, service.timeout(timeout), false))
+                .collect(),
+            None => {
+                if !actions.is_empty() {
+                    eprintln!("The compositor does not support ext-idle-notify-v1, idle actions are disabled");
+                }
+                Vec::new()
+            }
+        };
+        Idle {
+            env,
+            display,
+            timeouts,
+            dim: None,
+            outputs_off: None,
+            locker: None,
+            inhibitor: None,
+        }
+    }
+
+    /// Keeps the session awake through one of the given surfaces, or lets it go idle when there are none.
+    /// The inhibitor goes away with its surface, so this has to be called again whenever surfaces do.
+    pub fn inhibit(&mut self, surfaces: &[wl_surface::WlSurface]) {
+        if let Some(ref inhibitor) = self.inhibitor {
+            if inhibitor.surface().as_ref().is_alive() && surfaces.contains(inhibitor.surface()) {
+                return;
+            }
+        }
+        self.inhibitor = surfaces
+            .first()
+            .and_then(|surface| IdleInhibitor::new(self.env, surface));
+        if let Err(e) = self.display.flush() {
+            eprintln!("Error flushing the wayland socket: {:?}", e);
+        }
+    }
+
+    async fn on_idle_change(&mut self, action: IdleAction, idle: bool) {
+        match (action, idle) {
+            (IdleAction::Dim, true) => {
+                let env = self.env;
+                let display = self.display;
+                self.dim = Some(
+                    MultiMonitor::new(
+                        Box::new(move |output, _output_info| {
+                            IcedInstance::new(Dim::new(), env.clone(), display.clone(), output).boxed_local()
+                        }),
+                        env,
+                    )
+                    .await,
+                );
+            }
+            (IdleAction::Dim, false) => self.dim = None,
+            (IdleAction::Lock, true) => self.lock(),
+            (IdleAction::Lock, false) => (),
+            (IdleAction::OutputsOff, true) => {
+                self.outputs_off = OutputsOff::new(self.env, self.display.clone());
+                if self.outputs_off.is_none() {
+                    eprintln!("The compositor does not support wlr-output-power-management, can't turn outputs off");
+                }
+            }
+            (IdleAction::OutputsOff, false) => self.outputs_off = None,
+        }
+        if let Err(e) = self.display.flush() {
+            eprintln!("Error flushing the wayland socket: {:?}", e);
+        }
+    }
+
+    fn lock(&mut self) {
+        if let Some(ref mut locker) = self.locker {
+            if let Ok(None) = locker.try_wait() {
+                return;
+            }
+        }
+        self.locker = match process::Command::new(LOCK_COMMAND).spawn() {
+            Ok(child) => Some(child),
+            Err(e) => {
+                eprintln!("Could not run {}: {}", LOCK_COMMAND, e);
+                None
+            }
+        };
+    }
+}
+
+#[async_trait(?Send)]
+impl<'a> Runnable for Idle<'a> {
+    async fn run(&mut self) -> bool {
+        let this = self; // argh macro weirdness
+        if this.timeouts.is_empty() {
+            future::pending::<()>().await;
+        }
+        let mut changes = this
+            .timeouts
+            .iter()
+            .enumerate()
+            .map(|(i, (_, timeout, applied))| timeout.changed(*applied).map(move |idle| (i, idle)))
+            .collect::<futures::stream::FuturesUnordered<_>>();
+        let change = futures::select! {
+            change = changes.select_next_some() => Some(change),
+            _ = MaybeFuture::new(this.dim.as_mut().map(|d| d.run().fuse())) => None,
+        };
+        drop(changes);
+        if let Some((i, idle)) = change {
+            this.on_idle_change(this.timeouts[i].0, idle).await;
+            this.timeouts[i].2 = idle;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!(
+            parse_actions(" dim = 240,lock=300 ,off=600"),
+            vec![
+                (IdleAction::Dim, Duration::from_secs(240)),
+                (IdleAction::Lock, Duration::from_secs(300)),
+                (IdleAction::OutputsOff, Duration::from_secs(600)),
+            ]
+        );
+        assert_eq!(parse_actions(""), vec![]);
+        assert_eq!(parse_actions(",,"), vec![]);
+    }
+
+    #[test]
+    fn skips_invalid_actions() {
+        assert_eq!(
+            parse_actions("dim,sleep=10,lock=-5,lock=soon,off=600,off="),
+            vec![(IdleAction::OutputsOff, Duration::from_secs(600))]
+        );
+    }
+}
@@ -1,7 +1,10 @@
-use gio::prelude::ApplicationExt;
+use futures::channel::mpsc;
+use gio::prelude::{ActionMapExt, ApplicationExt};
 use wstk::*;
 
+mod displays;
 mod dock;
+mod idle;
 mod svc;
 mod util;
 
@@ -14,10 +17,12 @@ async fn main_(env: &Environment<Env>, display: &Display) {
     let session_bus = app.dbus_connection().unwrap();
 
     let services: &'static _ = Box::leak(Box::new(svc::Services {
-        seat: env.get_all_seats()[0].detach(),
         toplevels: env.with_inner(|i| i.toplevel_service()),
+        outputs: env.with_inner(|i| i.output_config_service()),
         power: svc::power::PowerService::new(&session_bus).await,
         media: svc::media::MediaService::new(&session_bus).await,
+        capture: Some(CaptureService::new(env.clone(), display.clone())),
+        night_light: svc::night_light::NightLightService::new(GammaService::new(env, display.clone())),
     }));
 
     let mut dock_mm = MultiMonitor::new(
@@ -28,9 +33,44 @@ async fn main_(env: &Environment<Env>, display: &Display) {
     )
     .await;
 
+    // e.g. `gapplication action technology.unrelenting.waysmoke.Shell display-settings`
+    let (displays_tx, mut displays_rx) = mpsc::unbounded();
+    let displays_action = gio::SimpleAction::new("display-settings", None);
+    displays_action.connect_activate(move |_, _| {
+        let _ = displays_tx.unbounded_send(());
+    });
+    app.add_action(&displays_action);
+    let mut displays: Option<IcedInstance<displays::DisplaySettings>> = None;
+
+    let mut idle = idle::Idle::new(env, display);
+
     loop {
-        futures::select! {
-            _ = dock_mm.run().fuse() => (),
+        let showing_displays = displays.is_some();
+        let (show_displays, displays_done) = futures::select! {
+            _ = dock_mm.run().fuse() => (false, false),
+            // activating it again while it's open leaves it alone, closing could skip a pending revert
+            () = displays_rx.select_next_some() => if showing_displays { (false, false) } else { (true, false) },
+            cont = MaybeFuture::new(displays.as_mut().map(|d| d.run().fuse())) => (false, !cont),
+            _ = idle.run().fuse() => (false, false),
+            () = services.media.subscribe().fuse() => (false, false),
+            () = services.night_light.run().boxed_local().fuse() => (false, false),
+        };
+        // playing media keeps the session awake, through the dock since inhibitors need a visible surface.
+        // dock instances go away with their outputs (which ends a dock_mm.run()), then it moves to another one
+        let inhibit_surfaces: Vec<_> = if services.media.is_playing() {
+            dock_mm.instances().map(|d| d.wl_surface().detach()).collect()
+        } else {
+            Vec::new()
+        };
+        idle.inhibit(&inhibit_surfaces);
+        if displays_done {
+            displays = None;
+        }
+        if show_displays {
+            if let Some(output) = env.get_all_outputs().into_iter().next() {
+                let settings = displays::DisplaySettings::new(services, display.clone());
+                displays = Some(IcedInstance::new(settings, env.clone(), display.clone(), output).await);
+            }
         }
     }
 }
@@ -96,7 +96,8 @@ impl Drop for MediaSubscription {
 }
 
 pub struct MediaService {
-    dbus: gio::DBusConnection,
+    /// None without a bus, in tests
+    dbus: Option<gio::DBusConnection>,
     noc_sub: Option<gio::SignalSubscriptionId>, // not cloneable in gio lol
     notifier: Rc<event_listener::Event>,
     state: Rc<RefCell<MediaState>>,
@@ -163,13 +164,24 @@ impl MediaService {
         };
 
         MediaService {
-            dbus: dbus.clone(),
+            dbus: Some(dbus.clone()),
             noc_sub: Some(noc_sub),
             notifier,
             state,
         }
     }
 
+    /// Without a bus, for rendering the dock in tests
+    #[cfg(test)]
+    pub fn with_state(state: MediaState) -> MediaService {
+        MediaService {
+            dbus: None,
+            noc_sub: None,
+            notifier: Rc::new(event_listener::Event::new()),
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
     async fn add(
         dbus: gio::DBusConnection,
         notifier: Rc<event_listener::Event>,
@@ -257,12 +269,24 @@ impl MediaService {
         self.state.borrow()
     }
 
+    /// Whether any player is playing right now
+    pub fn is_playing(&self) -> bool {
+        self.state
+            .borrow()
+            .values()
+            .any(|p| p.status == PlaybackStatus::Playing)
+    }
+
     pub fn subscribe(&self) -> impl Future<Output = ()> {
         self.notifier.listen()
     }
 
     pub fn control_player(&self, name: &str, cmd: &str) {
-        let _ = self.dbus.send_message(
+        let dbus = match self.dbus {
+            Some(ref dbus) => dbus,
+            None => return,
+        };
+        let _ = dbus.send_message(
             &gio::DBusMessage::new_method_call(
                 Some(name),
                 "/org/mpris/MediaPlayer2",
@@ -277,6 +301,8 @@ impl MediaService {
 // not that it would ever be dropped but
 impl Drop for MediaService {
     fn drop(&mut self) {
-        self.dbus.signal_unsubscribe(self.noc_sub.take().unwrap());
+        if let (Some(dbus), Some(noc_sub)) = (self.dbus.as_ref(), self.noc_sub.take()) {
+            dbus.signal_unsubscribe(noc_sub);
+        }
     }
 }
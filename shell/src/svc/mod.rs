@@ -1,9 +1,13 @@
 pub mod media;
+pub mod night_light;
 pub mod power;
 
 pub struct Services {
-    pub seat: wstk::wl_seat::WlSeat,
     pub toplevels: std::rc::Rc<wstk::toplevels::ToplevelService>,
+    pub outputs: std::rc::Rc<wstk::output_config::OutputConfigService>,
     pub power: power::PowerService,
     pub media: media::MediaService,
+    /// None without a compositor connection, i.e. in tests
+    pub capture: Option<wstk::CaptureService>,
+    pub night_light: night_light::NightLightService,
 }
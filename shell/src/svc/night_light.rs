@@ -0,0 +1,391 @@
+//! Warmer colours at night, configured with e.g. `WAYSMOKE_NIGHT_LIGHT=night=4000,from=20:00,to=07:00`
+//! or `WAYSMOKE_NIGHT_LIGHT=night=3500,lat=52.52,lon=13.40` (sunset to sunrise there).
+//! Without a schedule it's only toggled by hand.
+
+use futures::prelude::*;
+use std::{cell::Cell, f64::consts::PI, time::Duration};
+use wstk::{event_listener, GammaService, NEUTRAL_TEMPERATURE};
+
+const DEFAULT_NIGHT_TEMPERATURE: u32 = 4000;
+const DAY_SECONDS: i32 = 24 * 60 * 60;
+/// The timer doesn't run while suspended, and the clock can change, so waits are capped
+const RECHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The current local time, with what's needed to place the sun
+struct LocalTime {
+    /// Since local midnight
+    seconds: i32,
+    day_of_year: i32,
+    /// In seconds
+    utc_offset: i32,
+}
+
+impl LocalTime {
+    fn now() -> Option<LocalTime> {
+        let now = match glib::DateTime::new_now_local() {
+            Ok(now) => now,
+            Err(e) => {
+                eprintln!("Could not get the local time: {:?}", e);
+                return None;
+            }
+        };
+        Some(LocalTime {
+            seconds: now.hour() * 3600 + now.minute() * 60 + now.second(),
+            day_of_year: now.day_of_year(),
+            utc_offset: (now.utc_offset() / 1_000_000) as i32,
+        })
+    }
+}
+
+/// Sunset and sunrise in seconds since UTC midnight (NOAA's approximation), or in polar regions,
+/// whether it's night all day
+fn sun_times(day_of_year: i32, latitude: f64, longitude: f64) -> Result<(f64, f64), bool> {
+    let y = 2.0 * PI / 365.0 * (day_of_year - 1) as f64;
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * y.cos()
+            - 0.032077 * y.sin()
+            - 0.014615 * (2.0 * y).cos()
+            - 0.040849 * (2.0 * y).sin());
+    let decl = 0.006918 - 0.399912 * y.cos() + 0.070257 * y.sin() - 0.006758 * (2.0 * y).cos()
+        + 0.000907 * (2.0 * y).sin()
+        - 0.002697 * (3.0 * y).cos()
+        + 0.00148 * (3.0 * y).sin();
+    let lat = latitude.to_radians();
+    // the sun's centre 0.833° below the horizon accounts for refraction and its size
+    let cos_ha = 90.833f64.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    if cos_ha > 1.0 {
+        return Err(true);
+    }
+    if cos_ha < -1.0 {
+        return Err(false);
+    }
+    let ha = cos_ha.acos().to_degrees();
+    let sunrise = 720.0 - 4.0 * (longitude + ha) - eqtime;
+    let sunset = 720.0 - 4.0 * (longitude - ha) - eqtime;
+    Ok((sunset * 60.0, sunrise * 60.0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    Manual,
+    /// Local times of day, in seconds since midnight
+    Fixed {
+        from: i32,
+        to: i32,
+    },
+    /// From sunset to sunrise at the place, in degrees
+    Sun {
+        latitude: f64,
+        longitude: f64,
+    },
+}
+
+impl Schedule {
+    /// Start and end of the night in seconds since local midnight,
+    /// or whether it's night all day when there's no change
+    fn night(&self, now: &LocalTime) -> Result<(i32, i32), bool> {
+        match *self {
+            Schedule::Manual => Err(false),
+            Schedule::Fixed { from, to } => Ok((from, to)),
+            Schedule::Sun { latitude, longitude } => {
+                let (sunset, sunrise) = sun_times(now.day_of_year, latitude, longitude)?;
+                let local = |t: f64| (t.round() as i32 + now.utc_offset).rem_euclid(DAY_SECONDS);
+                Ok((local(sunset), local(sunrise)))
+            }
+        }
+    }
+
+    fn is_night(&self, now: &LocalTime) -> bool {
+        match self.night(now) {
+            Ok((from, to)) if from <= to => from <= now.seconds && now.seconds < to,
+            Ok((from, to)) => now.seconds >= from || now.seconds < to,
+            Err(night) => night,
+        }
+    }
+
+    /// Seconds since local midnight when it next switches between day and night
+    fn next_change(&self, now: &LocalTime) -> Option<i32> {
+        let (from, to) = self.night(now).ok()?;
+        Some(if seconds_until(from, now) < seconds_until(to, now) {
+            from
+        } else {
+            to
+        })
+    }
+
+    fn until_change(&self, now: &LocalTime) -> Option<Duration> {
+        let next = self.next_change(now)?;
+        Some(Duration::from_secs(seconds_until(next, now) as u64))
+    }
+}
+
+/// Until the next time the local clock shows the given time of day, a whole day if it's now
+fn seconds_until(time: i32, now: &LocalTime) -> i32 {
+    match (time - now.seconds).rem_euclid(DAY_SECONDS) {
+        0 => DAY_SECONDS,
+        x => x,
+    }
+}
+
+fn parse_time(x: &str) -> Option<i32> {
+    let mut hm = x.splitn(2, ':');
+    let h = hm.next()?.trim().parse::<i32>().ok()?;
+    let m = hm.next().map_or(Some(0), |m| m.trim().parse::<i32>().ok())?;
+    if (0..24).contains(&h) && (0..60).contains(&m) {
+        Some(h * 3600 + m * 60)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NightLightConfig {
+    pub day: u32,
+    pub night: u32,
+    pub schedule: Schedule,
+}
+
+impl NightLightConfig {
+    fn parse(spec: &str) -> NightLightConfig {
+        let mut config = NightLightConfig {
+            day: NEUTRAL_TEMPERATURE,
+            night: DEFAULT_NIGHT_TEMPERATURE,
+            schedule: Schedule::Manual,
+        };
+        let (mut from, mut to, mut lat, mut lon) = (None, None, None, None);
+        for kv in spec.split(',').filter(|a| !a.trim().is_empty()) {
+            let mut kv = kv.splitn(2, '=');
+            let (key, value) = (kv.next().unwrap_or("").trim(), kv.next().unwrap_or("").trim());
+            let ok = match key {
+                "day" => value.parse().map(|t| config.day = t).is_ok(),
+                "night" => value.parse().map(|t| config.night = t).is_ok(),
+                "from" => parse_time(value).map(|t| from = Some(t)).is_some(),
+                "to" => parse_time(value).map(|t| to = Some(t)).is_some(),
+                "lat" => value.parse::<f64>().map(|l| lat = Some(l)).is_ok(),
+                "lon" => value.parse::<f64>().map(|l| lon = Some(l)).is_ok(),
+                _ => false,
+            };
+            if !ok {
+                eprintln!("Ignoring invalid night light setting '{}={}'", key, value);
+            }
+        }
+        match (from, to, lat, lon) {
+            (Some(from), Some(to), _, _) => config.schedule = Schedule::Fixed { from, to },
+            (_, _, Some(latitude), Some(longitude)) => config.schedule = Schedule::Sun { latitude, longitude },
+            (None, None, None, None) => (),
+            _ => eprintln!("The night light schedule needs either from and to, or lat and lon"),
+        }
+        config
+    }
+}
+
+pub struct NightLightService {
+    gamma: Option<GammaService>,
+    config: NightLightConfig,
+    /// What the schedule said the last time it was checked
+    scheduled: Cell<Option<bool>>,
+    /// Toggled by hand against the schedule, until the schedule changes
+    overridden: Cell<bool>,
+    notifier: event_listener::Event,
+}
+
+impl NightLightService {
+    pub fn new(gamma: Option<GammaService>) -> NightLightService {
+        if gamma.is_none() {
+            eprintln!("The compositor does not support wlr-gamma-control, night light is disabled");
+        }
+        let spec = std::env::var("WAYSMOKE_NIGHT_LIGHT").unwrap_or_default();
+        NightLightService {
+            gamma,
+            config: NightLightConfig::parse(&spec),
+            scheduled: Cell::new(None),
+            overridden: Cell::new(false),
+            notifier: event_listener::Event::new(),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.gamma.is_some()
+    }
+
+    pub fn config(&self) -> &NightLightConfig {
+        &self.config
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.scheduled.get().unwrap_or(false) != self.overridden.get()
+    }
+
+    /// Local (hour, minute) when the schedule next switches, which also ends a toggle by hand
+    pub fn next_change(&self) -> Option<(i32, i32)> {
+        let next = self.config.schedule.next_change(&LocalTime::now()?)?;
+        Some((next / 3600, next % 3600 / 60))
+    }
+
+    pub fn toggle(&self) {
+        self.overridden.set(!self.overridden.get());
+        self.apply();
+        self.notifier.notify(usize::MAX);
+    }
+
+    fn apply(&self) {
+        if let Some(ref gamma) = self.gamma {
+            gamma.set_temperature(if self.is_active() {
+                self.config.night
+            } else {
+                self.config.day
+            });
+        }
+    }
+
+    pub fn subscribe(&self) -> impl Future<Output = ()> {
+        self.notifier.listen()
+    }
+
+    /// Follows the schedule, returns after checking it once it's time to
+    pub async fn run(&self) {
+        let now = match (&self.gamma, LocalTime::now()) {
+            (Some(_), Some(now)) => now,
+            _ => return future::pending().await,
+        };
+        let night = self.config.schedule.is_night(&now);
+        if self.scheduled.replace(Some(night)) != Some(night) {
+            self.overridden.set(false);
+            self.apply();
+            self.notifier.notify(usize::MAX);
+        }
+        match self.config.schedule.until_change(&now) {
+            Some(wait) => glib::timeout_future(wait.min(RECHECK_INTERVAL)).await,
+            None => future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: i32, minute: i32) -> LocalTime {
+        LocalTime {
+            seconds: hour * 3600 + minute * 60,
+            day_of_year: 80,
+            utc_offset: 0,
+        }
+    }
+
+    #[test]
+    fn sun_times_at_the_equinox() {
+        let (sunset, sunrise) = sun_times(80, 0.0, 0.0).unwrap();
+        assert!((5.5 * 3600.0..6.5 * 3600.0).contains(&sunrise), "{}", sunrise);
+        assert!(
+            (sunset - sunrise - 12.0 * 3600.0).abs() < 15.0 * 60.0,
+            "{} {}",
+            sunset,
+            sunrise
+        );
+        // an hour later for every 15° to the west
+        let (west_sunset, _) = sun_times(80, 0.0, -15.0).unwrap();
+        assert!((west_sunset - sunset - 3600.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn sun_times_in_polar_regions() {
+        assert_eq!(sun_times(172, 80.0, 0.0), Err(false));
+        assert_eq!(sun_times(355, 80.0, 0.0), Err(true));
+        assert_eq!(sun_times(172, -80.0, 0.0), Err(true));
+
+        let polar_night = Schedule::Sun {
+            latitude: 80.0,
+            longitude: 0.0,
+        };
+        let now = LocalTime {
+            day_of_year: 355,
+            ..at(12, 0)
+        };
+        assert!(polar_night.is_night(&now));
+        assert_eq!(polar_night.next_change(&now), None);
+    }
+
+    #[test]
+    fn fixed_schedule_across_midnight() {
+        let schedule = Schedule::Fixed {
+            from: 20 * 3600,
+            to: 7 * 3600,
+        };
+        assert!(schedule.is_night(&at(23, 0)));
+        assert!(schedule.is_night(&at(3, 0)));
+        assert!(schedule.is_night(&at(20, 0)));
+        assert!(!schedule.is_night(&at(7, 0)));
+        assert!(!schedule.is_night(&at(12, 0)));
+
+        assert_eq!(schedule.next_change(&at(12, 0)), Some(20 * 3600));
+        assert_eq!(schedule.next_change(&at(23, 0)), Some(7 * 3600));
+        // right at the switch, the next one is the other end
+        assert_eq!(schedule.next_change(&at(20, 0)), Some(7 * 3600));
+        assert_eq!(schedule.until_change(&at(23, 0)), Some(Duration::from_secs(8 * 3600)));
+    }
+
+    #[test]
+    fn fixed_schedule_within_a_day() {
+        let schedule = Schedule::Fixed {
+            from: 3600,
+            to: 5 * 3600,
+        };
+        assert!(schedule.is_night(&at(2, 0)));
+        assert!(!schedule.is_night(&at(0, 30)));
+        assert!(!schedule.is_night(&at(23, 0)));
+        assert_eq!(schedule.until_change(&at(23, 0)), Some(Duration::from_secs(2 * 3600)));
+    }
+
+    #[test]
+    fn manual_schedule() {
+        assert!(!Schedule::Manual.is_night(&at(23, 0)));
+        assert_eq!(Schedule::Manual.next_change(&at(23, 0)), None);
+    }
+
+    #[test]
+    fn seconds_until_wraps() {
+        assert_eq!(seconds_until(3600, &at(23, 0)), 2 * 3600);
+        assert_eq!(seconds_until(3600, &at(1, 0)), DAY_SECONDS);
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("07:30"), Some(7 * 3600 + 30 * 60));
+        assert_eq!(parse_time(" 7 "), Some(7 * 3600));
+        assert_eq!(parse_time("0:0"), Some(0));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("noon"), None);
+    }
+
+    #[test]
+    fn parses_config() {
+        let config = NightLightConfig::parse("night=3500, from=20:00, to=7");
+        assert_eq!(config.day, NEUTRAL_TEMPERATURE);
+        assert_eq!(config.night, 3500);
+        assert_eq!(
+            config.schedule,
+            Schedule::Fixed {
+                from: 20 * 3600,
+                to: 7 * 3600
+            }
+        );
+
+        let config = NightLightConfig::parse("lat=52.52,lon=13.40,day=6000");
+        assert_eq!(config.day, 6000);
+        assert_eq!(
+            config.schedule,
+            Schedule::Sun {
+                latitude: 52.52,
+                longitude: 13.40
+            }
+        );
+
+        // incomplete or invalid parts are left out
+        let config = NightLightConfig::parse("from=20:00,night=warm,lat=1");
+        assert_eq!(config.night, DEFAULT_NIGHT_TEMPERATURE);
+        assert_eq!(config.schedule, Schedule::Manual);
+        assert_eq!(NightLightConfig::parse("").schedule, Schedule::Manual);
+    }
+}
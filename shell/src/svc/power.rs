@@ -135,6 +135,15 @@ impl PowerService {
         }
     }
 
+    /// Without UPower, for rendering the dock in tests
+    #[cfg(test)]
+    pub fn with_state(state: PowerState) -> PowerService {
+        PowerService {
+            notifier: Rc::new(event_listener::Event::new()),
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
     pub fn state(&self) -> Ref<'_, PowerState> {
         self.state.borrow()
     }
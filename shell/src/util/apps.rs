@@ -5,11 +5,16 @@ use std::path::Path;
 lazy_static::lazy_static! {
     static ref USERPATH: String =
         glib::user_data_dir().join("icons").to_str().unwrap().to_owned();
-    static ref PATHS: Vec<&'static str> = vec![
-        // linicon doesn't have the XDG_DATA_DIRS fallback paths from the spec
-        "/usr/local/share/icons", "/usr/share/icons",
-        &*USERPATH,
-    ];
+    static ref PATHS: Vec<&'static str> = if cfg!(test) {
+        // only the few icons that renders in tests use, so that they look the same everywhere
+        vec![concat!(env!("CARGO_MANIFEST_DIR"), "/tests/icons")]
+    } else {
+        vec![
+            // linicon doesn't have the XDG_DATA_DIRS fallback paths from the spec
+            "/usr/local/share/icons", "/usr/share/icons",
+            &*USERPATH,
+        ]
+    };
 }
 
 #[derive(Debug, Eq)]
@@ -0,0 +1,21 @@
+use std::{env, path::Path};
+use wayland_scanner::{generate_code, Side};
+
+// Protocols that wayland-protocols 0.28 doesn't have yet, see src/protocols.rs
+const PROTOCOLS: &[&str] = &[
+    "fractional-scale-v1",
+    "ext-foreign-toplevel-list-v1",
+    "ext-session-lock-v1",
+    "ext-idle-notify-v1",
+    "ext-image-capture-source-v1",
+    "ext-image-copy-capture-v1",
+];
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    for name in PROTOCOLS {
+        let xml = format!("protocols/{}.xml", name);
+        println!("cargo:rerun-if-changed={}", xml);
+        generate_code(&xml, Path::new(&out_dir).join(format!("{}.rs", name)), Side::Client);
+    }
+}
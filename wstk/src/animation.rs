@@ -0,0 +1,102 @@
+use iced_native::Color;
+use std::time::{Duration, Instant};
+
+/// How an animation gets from its start (0) to its end (1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    /// Starts fast and slows down, for things appearing
+    EaseOut,
+    /// Slow at both ends, for things moving from one place to another
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// A value moving towards a target over time. It's sampled with the frame time
+/// (see `IcedSurface::on_frame`), so everything drawn in one frame agrees on where it is.
+#[derive(Debug, Clone)]
+pub struct Tween {
+    from: f32,
+    to: f32,
+    start: Instant,
+    duration: Duration,
+    easing: Easing,
+}
+
+impl Tween {
+    /// A tween resting at `value`, taking `duration` to get to any new target
+    pub fn new(value: f32, duration: Duration, easing: Easing) -> Tween {
+        Tween {
+            from: value,
+            to: value,
+            start: Instant::now(),
+            duration,
+            easing,
+        }
+    }
+
+    /// Starts moving towards a new target from wherever the value is at `now`,
+    /// so that changing direction halfway doesn't make it jump.
+    pub fn set(&mut self, to: f32, now: Instant) {
+        if to == self.to {
+            return;
+        }
+        self.from = self.value(now);
+        self.to = to;
+        self.start = now;
+    }
+
+    /// Moves to the target without animating
+    pub fn jump(&mut self, to: f32) {
+        self.from = to;
+        self.to = to;
+        self.start = Instant::now();
+    }
+
+    pub fn target(&self) -> f32 {
+        self.to
+    }
+
+    fn progress(&self, now: Instant) -> f32 {
+        if self.duration == Duration::from_secs(0) {
+            return 1.0;
+        }
+        now.saturating_duration_since(self.start).as_secs_f32() / self.duration.as_secs_f32()
+    }
+
+    pub fn value(&self, now: Instant) -> f32 {
+        let t = self.easing.apply(self.progress(now));
+        self.from + (self.to - self.from) * t
+    }
+
+    pub fn is_running(&self, now: Instant) -> bool {
+        self.from != self.to && self.progress(now) < 1.0
+    }
+}
+
+/// Mixes two colors, `t` = 0 being `a` and 1 being `b`
+pub fn mix_colors(a: Color, b: Color, t: f32) -> Color {
+    let t = t.max(0.0).min(1.0);
+    Color {
+        r: a.r + (b.r - a.r) * t,
+        g: a.g + (b.g - a.g) * t,
+        b: a.b + (b.b - a.b) * t,
+        a: a.a + (b.a - a.a) * t,
+    }
+}
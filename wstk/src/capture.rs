@@ -0,0 +1,370 @@
+//! Grabbing what toplevels and outputs show into images, e.g. for window thumbnails.
+//! Toplevels need ext-image-copy-capture and a toplevel from ext-foreign-toplevel-list,
+//! outputs can also be captured with wlr-screencopy.
+//! Not many compositors offer capturing toplevels yet, without it there are just no thumbnails.
+
+pub use crate::protocols::{
+    ext_image_capture_source_v1::client::{
+        ext_foreign_toplevel_image_capture_source_manager_v1 as toplevel_source_manager,
+        ext_image_capture_source_v1 as capture_source,
+        ext_output_image_capture_source_manager_v1 as output_source_manager,
+    },
+    ext_image_copy_capture_v1::client::{
+        ext_image_copy_capture_frame_v1 as copy_capture_frame,
+        ext_image_copy_capture_manager_v1 as copy_capture_manager,
+        ext_image_copy_capture_session_v1 as copy_capture_session,
+    },
+};
+use crate::{event_loop::AsyncMain, surfaces::*, toplevels::*, ImageHandle};
+pub use smithay_client_toolkit::reexports::{
+    client::protocol::wl_buffer,
+    protocols::wlr::unstable::screencopy::v1::client::{
+        zwlr_screencopy_frame_v1 as screencopy_frame, zwlr_screencopy_manager_v1 as screencopy_manager,
+    },
+};
+
+/// A captured image, in the BGRA order of `iced_native::image::Handle::from_pixels`
+#[derive(Clone)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// Scaled down (never up) to fit into the given size, keeping the aspect ratio. None for an empty frame
+    pub fn thumbnail(&self, max_width: u32, max_height: u32) -> Option<CapturedFrame> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let scale = (max_width as f32 / self.width as f32)
+            .min(max_height as f32 / self.height as f32)
+            .min(1.0);
+        let width = ((self.width as f32 * scale).round() as u32).max(1);
+        let height = ((self.height as f32 * scale).round() as u32).max(1);
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            let (y0, y1) = (
+                y * self.height / height,
+                ((y + 1) * self.height / height).max(y * self.height / height + 1),
+            );
+            for x in 0..width {
+                let (x0, x1) = (
+                    x * self.width / width,
+                    ((x + 1) * self.width / width).max(x * self.width / width + 1),
+                );
+                // box filter, each source pixel lands in exactly one target pixel
+                let mut sum = [0u32; 4];
+                for sy in y0..y1 {
+                    let row = (sy * self.width * 4) as usize;
+                    for px in self.pixels[row + x0 as usize * 4..row + x1 as usize * 4].chunks_exact(4) {
+                        for c in 0..4 {
+                            sum[c] += px[c] as u32;
+                        }
+                    }
+                }
+                let count = (x1 - x0) * (y1 - y0);
+                pixels.extend(sum.iter().map(|s| (s / count) as u8));
+            }
+        }
+        Some(CapturedFrame { width, height, pixels })
+    }
+
+    pub fn into_image(self) -> ImageHandle {
+        ImageHandle::Raster(iced_native::image::Handle::from_pixels(
+            self.width,
+            self.height,
+            self.pixels,
+        ))
+    }
+}
+
+/// Layout of the shm buffer the compositor copies into
+#[derive(Clone, Copy)]
+struct BufferSpec {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+/// Only the formats that are BGRA in memory (on little endian) are used, there's no conversion
+fn is_bgra(format: wl_shm::Format) -> bool {
+    format == wl_shm::Format::Argb8888 || format == wl_shm::Format::Xrgb8888
+}
+
+/// A one-off buffer, the compositor doesn't release capture buffers so they're destroyed right after reading
+struct CaptureBuffer {
+    pool: smithay_client_toolkit::shm::MemPool,
+    buffer: wl_buffer::WlBuffer,
+    spec: BufferSpec,
+}
+
+impl CaptureBuffer {
+    fn new(env: &Environment<Env>, spec: BufferSpec) -> Option<CaptureBuffer> {
+        let mut pool = match env.create_simple_pool(|_| ()) {
+            Ok(pool) => pool,
+            Err(e) => {
+                eprintln!("Could not create a shm pool: {:?}", e);
+                return None;
+            }
+        };
+        pool.resize((spec.stride * spec.height) as usize).ok()?;
+        let buffer = pool.buffer(
+            0,
+            spec.width as i32,
+            spec.height as i32,
+            spec.stride as i32,
+            spec.format,
+        );
+        Some(CaptureBuffer { pool, buffer, spec })
+    }
+
+    fn read(mut self, y_invert: bool) -> CapturedFrame {
+        let BufferSpec {
+            format,
+            width,
+            height,
+            stride,
+        } = self.spec;
+        let mmap = self.pool.mmap();
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            let src_y = if y_invert { height - 1 - y } else { y };
+            let row = (src_y * stride) as usize;
+            pixels.extend_from_slice(&mmap[row..row + (width * 4) as usize]);
+        }
+        if format == wl_shm::Format::Xrgb8888 {
+            for px in pixels.chunks_exact_mut(4) {
+                px[3] = 255;
+            }
+        }
+        CapturedFrame { width, height, pixels }
+    }
+}
+
+impl Drop for CaptureBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+pub struct CaptureService {
+    env: Environment<Env>,
+    display: Display,
+}
+
+impl CaptureService {
+    pub fn new(env: Environment<Env>, display: Display) -> CaptureService {
+        CaptureService { env, display }
+    }
+
+    /// Whether `capture_toplevel` can work for the toplevel at all
+    pub fn can_capture(&self, topl: &ToplevelState) -> bool {
+        topl.ext_handle.is_some()
+            && self
+                .env
+                .get_global::<copy_capture_manager::ExtImageCopyCaptureManagerV1>()
+                .is_some()
+            && self
+                .env
+                .get_global::<toplevel_source_manager::ExtForeignToplevelImageCaptureSourceManagerV1>()
+                .is_some()
+    }
+
+    /// A single frame of the toplevel. Only works for toplevels known to ext-foreign-toplevel-list (see `can_capture`)
+    pub async fn capture_toplevel(&self, topl: &ToplevelState) -> Option<CapturedFrame> {
+        let handle = match topl.ext_handle {
+            Some(ref handle) if handle.as_ref().is_alive() => handle,
+            _ => return None,
+        };
+        let manager = self
+            .env
+            .get_global::<toplevel_source_manager::ExtForeignToplevelImageCaptureSourceManagerV1>()?;
+        // wrapped for the destructor, in case the capture is dropped midway
+        let source = AsyncMain::new(manager.create_source(handle), Some(|s| s.destroy()));
+        self.capture_source(&source).await
+    }
+
+    /// A single frame of the output, with ext-image-copy-capture or wlr-screencopy
+    pub async fn capture_output(&self, output: &wl_output::WlOutput) -> Option<CapturedFrame> {
+        if let Some(manager) = self
+            .env
+            .get_global::<output_source_manager::ExtOutputImageCaptureSourceManagerV1>()
+        {
+            let source = AsyncMain::new(manager.create_source(output), Some(|s| s.destroy()));
+            return self.capture_source(&source).await;
+        }
+        self.screencopy_output(output).await
+    }
+
+    fn flush(&self) {
+        if let Err(e) = self.display.flush() {
+            eprintln!("Error flushing the wayland socket: {:?}", e);
+        }
+    }
+
+    async fn capture_source(&self, source: &capture_source::ExtImageCaptureSourceV1) -> Option<CapturedFrame> {
+        let manager = self
+            .env
+            .get_global::<copy_capture_manager::ExtImageCopyCaptureManagerV1>()?;
+        let mut session = AsyncMain::new(manager.create_session(source, 0), Some(|s| s.destroy()));
+        self.flush();
+
+        let mut size = None;
+        let mut format = None;
+        loop {
+            match session.next().await {
+                copy_capture_session::Event::BufferSize { width, height } => size = Some((width, height)),
+                copy_capture_session::Event::ShmFormat { format: f } => match wl_shm::Format::from_raw(f) {
+                    Some(f) if is_bgra(f) && format != Some(wl_shm::Format::Argb8888) => format = Some(f),
+                    _ => (),
+                },
+                copy_capture_session::Event::Done => break,
+                copy_capture_session::Event::Stopped => return None,
+                _ => (),
+            }
+        }
+        let (width, height) = size?;
+        let format = match format {
+            Some(format) => format,
+            None => {
+                eprintln!("WARN: no supported shm format for capturing");
+                return None;
+            }
+        };
+        let buffer = CaptureBuffer::new(
+            &self.env,
+            BufferSpec {
+                format,
+                width,
+                height,
+                stride: width * 4,
+            },
+        )?;
+
+        let mut frame = AsyncMain::new(session.create_frame(), Some(|f| f.destroy()));
+        frame.attach_buffer(&buffer.buffer);
+        frame.damage_buffer(0, 0, width as i32, height as i32);
+        frame.capture();
+        self.flush();
+        loop {
+            match frame.next().await {
+                copy_capture_frame::Event::Ready => return Some(buffer.read(false)),
+                copy_capture_frame::Event::Failed { reason } => {
+                    eprintln!("WARN: capture failed, reason {}", reason);
+                    return None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    async fn screencopy_output(&self, output: &wl_output::WlOutput) -> Option<CapturedFrame> {
+        let manager = self.env.get_global::<screencopy_manager::ZwlrScreencopyManagerV1>()?;
+        let mut frame = AsyncMain::new(manager.capture_output(0, output), Some(|f| f.destroy()));
+        let announces_done = frame.as_ref().version() >= 3;
+        self.flush();
+
+        // every buffer type is announced (v3 adds dmabuf ones), ending with buffer_done since v3
+        let mut spec = None;
+        loop {
+            match frame.next().await {
+                screencopy_frame::Event::Buffer {
+                    format,
+                    width,
+                    height,
+                    stride,
+                } => {
+                    if is_bgra(format) {
+                        spec = Some(BufferSpec {
+                            format,
+                            width,
+                            height,
+                            stride,
+                        });
+                    }
+                    if !announces_done {
+                        break;
+                    }
+                }
+                screencopy_frame::Event::BufferDone => break,
+                screencopy_frame::Event::Failed => return None,
+                _ => (),
+            }
+        }
+        let spec = match spec {
+            Some(spec) => spec,
+            None => {
+                eprintln!("WARN: no supported shm format for screencopy");
+                return None;
+            }
+        };
+        let buffer = CaptureBuffer::new(&self.env, spec)?;
+
+        frame.copy(&buffer.buffer);
+        self.flush();
+        let mut y_invert = false;
+        loop {
+            match frame.next().await {
+                screencopy_frame::Event::Flags { flags } => y_invert = flags.contains(screencopy_frame::Flags::YInvert),
+                screencopy_frame::Event::Ready { .. } => return Some(buffer.read(y_invert)),
+                screencopy_frame::Event::Failed => return None,
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grey pixels of the given values, row by row
+    fn frame(width: u32, height: u32, values: &[u8]) -> CapturedFrame {
+        CapturedFrame {
+            width,
+            height,
+            pixels: values.iter().flat_map(|&v| vec![v; 4]).collect(),
+        }
+    }
+
+    #[test]
+    fn thumbnail_averages_boxes() {
+        let thumb = frame(4, 2, &[0, 100, 200, 255, 0, 100, 200, 255])
+            .thumbnail(2, 2)
+            .unwrap();
+        assert_eq!((thumb.width, thumb.height), (2, 1));
+        assert_eq!(thumb.pixels, vec![50, 50, 50, 50, 227, 227, 227, 227]);
+    }
+
+    #[test]
+    fn thumbnail_bounds() {
+        // uneven boxes still cover the whole frame without reading past it
+        let thumb = frame(5, 3, &[255; 15]).thumbnail(2, 2).unwrap();
+        assert_eq!((thumb.width, thumb.height), (2, 1));
+        assert!(thumb.pixels.iter().all(|&p| p == 255));
+
+        let thumb = frame(3, 3, &[10, 20, 30, 40, 50, 60, 70, 80, 90])
+            .thumbnail(2, 2)
+            .unwrap();
+        assert_eq!((thumb.width, thumb.height), (2, 2));
+        assert_eq!(thumb.pixels.len(), 2 * 2 * 4);
+        assert_eq!(thumb.pixels[0], 10);
+
+        // never scaled up, and never down to nothing
+        let small = frame(2, 2, &[1, 2, 3, 4]);
+        assert_eq!(small.thumbnail(10, 10).unwrap().pixels, small.pixels);
+        let line = frame(100, 1, &[7; 100]).thumbnail(10, 10).unwrap();
+        assert_eq!((line.width, line.height), (10, 1));
+        assert_eq!(
+            frame(100, 100, &[7; 10000])
+                .thumbnail(0, 0)
+                .map(|t| (t.width, t.height)),
+            Some((1, 1))
+        );
+
+        assert!(frame(0, 0, &[]).thumbnail(10, 10).is_none());
+        assert!(frame(10, 0, &[]).thumbnail(10, 10).is_none());
+    }
+}
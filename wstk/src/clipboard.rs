@@ -0,0 +1,292 @@
+use futures::channel::{mpsc, oneshot};
+use std::{
+    cell::RefCell,
+    io::{Read, Write},
+    os::unix::io::AsRawFd,
+    rc::Rc,
+};
+
+use crate::surfaces::*;
+
+/// Text types in order of preference, the X11 ones are for Xwayland clients.
+pub const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "TEXT",
+    "STRING",
+];
+
+/// What the current selection can be read as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardOffer {
+    pub mime_types: Vec<String>,
+}
+
+impl ClipboardOffer {
+    pub fn has(&self, mime_type: &str) -> bool {
+        self.mime_types.iter().any(|t| t == mime_type)
+    }
+
+    /// The preferred text type among the offered ones.
+    pub fn text_mime_type(&self) -> Option<&'static str> {
+        TEXT_MIME_TYPES.iter().find(|t| self.has(t)).copied()
+    }
+}
+
+/// Which of the two selections to operate on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// The regular clipboard (wl_data_device), Ctrl-C/Ctrl-V
+    Clipboard,
+    /// The primary selection (zwp_primary_selection_device_v1), select/middle-click
+    Primary,
+}
+
+/// Completed read of a selection, delivered to the surface via `IcedSurface::on_paste`.
+#[derive(Debug, Clone)]
+pub struct Paste {
+    pub selection: Selection,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Paste {
+    pub fn text(&self) -> Option<String> {
+        String::from_utf8(self.data.clone()).ok()
+    }
+}
+
+/// Asynchronous access to the clipboard and the primary selection of the seat that was used last.
+/// Cheap to clone, all clones share the input state and the paste channel.
+#[derive(Clone)]
+pub struct ClipboardService {
+    env: Environment<Env>,
+    display: Display,
+    input: Rc<RefCell<Option<(wl_seat::WlSeat, u32)>>>,
+    paste_tx: mpsc::UnboundedSender<Paste>,
+}
+
+impl ClipboardService {
+    pub fn new(env: Environment<Env>, display: Display) -> (ClipboardService, mpsc::UnboundedReceiver<Paste>) {
+        let (paste_tx, paste_rx) = mpsc::unbounded();
+        (
+            ClipboardService {
+                env,
+                display,
+                input: Rc::new(RefCell::new(None)),
+                paste_tx,
+            },
+            paste_rx,
+        )
+    }
+
+    /// Remembers the seat and serial of the latest input event, which are required to set a selection.
+    pub fn set_serial(&self, seat: &wl_seat::WlSeat, serial: u32) {
+        self.input.replace(Some((seat.clone(), serial)));
+    }
+
+    /// The seat of the latest input, or the first one before any input happened.
+    fn seat(&self) -> Option<wl_seat::WlSeat> {
+        if let Some((ref seat, _)) = *self.input.borrow() {
+            return Some(seat.clone());
+        }
+        self.env.get_all_seats().first().map(|s| s.detach())
+    }
+
+    fn flush(&self) {
+        if let Err(e) = self.display.flush() {
+            eprintln!("Error flushing the wayland socket: {:?}", e);
+        }
+    }
+
+    pub fn offer(&self, selection: Selection) -> Option<ClipboardOffer> {
+        let seat = self.seat()?;
+        let mut result = None;
+        let found = match selection {
+            Selection::Clipboard => self.env.with_data_device(&seat, |device| {
+                result = device.with_selection(|offer| offer.map(|offer| offer.with_mime_types(|types| types.to_vec())))
+            }),
+            Selection::Primary => self.env.with_primary_selection(&seat, |device| {
+                result = device.with_selection(|offer| offer.map(|offer| offer.with_mime_types(|types| types.to_vec())))
+            }),
+        };
+        if found.is_err() {
+            eprintln!("No {:?} device for seat", selection);
+        }
+        result.map(|mime_types| ClipboardOffer { mime_types })
+    }
+
+    /// Reads the current selection as the given type.
+    pub async fn read(&self, selection: Selection, mime_type: &str) -> Option<Vec<u8>> {
+        let seat = self.seat()?;
+        let mut pipe = None;
+        let found = match selection {
+            Selection::Clipboard => self.env.with_data_device(&seat, |device| {
+                pipe = device.with_selection(|offer| {
+                    let offer = offer?;
+                    if !offer.with_mime_types(|types| types.iter().any(|t| t == mime_type)) {
+                        return None;
+                    }
+                    Some(offer.receive(mime_type.to_owned()))
+                })
+            }),
+            Selection::Primary => self.env.with_primary_selection(&seat, |device| {
+                pipe = device.with_selection(|offer| {
+                    let offer = offer?;
+                    if !offer.with_mime_types(|types| types.iter().any(|t| t == mime_type)) {
+                        return None;
+                    }
+                    Some(offer.receive(mime_type.to_owned()))
+                })
+            }),
+        };
+        if found.is_err() {
+            eprintln!("No {:?} device for seat", selection);
+        }
+        let pipe = pipe?
+            .map_err(|e| eprintln!("Could not receive the {:?} selection: {:?}", selection, e))
+            .ok()?;
+        self.flush();
+        read_pipe(pipe).await
+    }
+
+    pub async fn read_text(&self, selection: Selection) -> Option<String> {
+        let mime_type = self.offer(selection)?.text_mime_type()?;
+        self.read(selection, mime_type)
+            .await
+            .map(|data| String::from_utf8_lossy(&data).into_owned())
+    }
+
+    /// Starts reading the selection as the given type, the result arrives as a Paste.
+    pub fn request(&self, selection: Selection, mime_type: &str) {
+        let this = self.clone();
+        let mime_type = mime_type.to_owned();
+        glib::MainContext::default().spawn_local(async move {
+            if let Some(data) = this.read(selection, &mime_type).await {
+                let _ = this.paste_tx.unbounded_send(Paste {
+                    selection,
+                    mime_type,
+                    data,
+                });
+            }
+        });
+    }
+
+    pub fn request_text(&self, selection: Selection) {
+        if let Some(mime_type) = self.offer(selection).and_then(|o| o.text_mime_type()) {
+            self.request(selection, mime_type);
+        }
+    }
+
+    /// Sets the selection, offering each (MIME type, data) pair.
+    pub fn write(&self, selection: Selection, contents: Vec<(String, Vec<u8>)>) {
+        let (seat, serial) = match *self.input.borrow() {
+            Some((ref seat, serial)) => (seat.clone(), serial),
+            None => {
+                eprintln!("WARN: setting the {:?} selection without any input", selection);
+                return;
+            }
+        };
+        let mime_types = contents.iter().map(|(t, _)| t.clone()).collect();
+        let found = match selection {
+            Selection::Clipboard => {
+                let source = self.env.new_data_source(mime_types, move |event, _| {
+                    if let data_device::DataSourceEvent::Send { mime_type, pipe } = event {
+                        send_contents(&contents, &mime_type, pipe);
+                    }
+                });
+                self.env
+                    .with_data_device(&seat, |device| device.set_selection(&Some(source), serial))
+            }
+            Selection::Primary => {
+                let source = self.env.new_primary_selection_source(mime_types, move |event, _| {
+                    if let primary_selection::PrimarySelectionSourceEvent::Send { mime_type, pipe } = event {
+                        send_contents(&contents, &mime_type, pipe);
+                    }
+                });
+                self.env
+                    .with_primary_selection(&seat, |device| device.set_selection(&Some(source), serial))
+            }
+        };
+        if found.is_err() {
+            eprintln!("No {:?} device for seat", selection);
+        }
+        self.flush();
+    }
+
+    pub fn write_text(&self, selection: Selection, text: String) {
+        self.write(
+            selection,
+            TEXT_MIME_TYPES
+                .iter()
+                .map(|t| (t.to_string(), text.as_bytes().to_vec()))
+                .collect(),
+        );
+    }
+}
+
+fn send_contents(contents: &[(String, Vec<u8>)], mime_type: &str, pipe: data_device::WritePipe) {
+    if let Some((_, data)) = contents.iter().find(|(t, _)| t == mime_type) {
+        write_pipe(pipe, data.clone());
+    }
+}
+
+/// Writes to a pipe on the glib loop, as much as fits per wakeup, and closes it when done.
+/// Never blocks, since the reader might be this very process (pasting into our own surface).
+fn write_pipe(mut pipe: data_device::WritePipe, data: Vec<u8>) {
+    let ok = unsafe { glib::ffi::g_unix_set_fd_nonblocking(pipe.as_raw_fd(), glib::ffi::GTRUE, std::ptr::null_mut()) };
+    if ok == glib::ffi::GFALSE {
+        eprintln!("Could not make the selection pipe non-blocking");
+        return;
+    }
+    let mut written = 0;
+    glib::source::unix_fd_add_local(
+        pipe.as_raw_fd(),
+        glib::IOCondition::OUT | glib::IOCondition::ERR | glib::IOCondition::HUP,
+        move |_fd, _ioc| match pipe.write(&data[written..]) {
+            Ok(n) => {
+                written += n;
+                glib::Continue(written < data.len())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted || e.kind() == std::io::ErrorKind::WouldBlock => {
+                glib::Continue(true)
+            }
+            Err(e) => {
+                eprintln!("Could not send selection data: {:?}", e);
+                glib::Continue(false)
+            }
+        },
+    );
+}
+
+/// Reads a pipe to the end on the glib loop, one read per wakeup so it never blocks.
+pub(crate) async fn read_pipe(mut pipe: data_device::ReadPipe) -> Option<Vec<u8>> {
+    let (tx, rx) = oneshot::channel();
+    let mut tx = Some(tx);
+    let mut data = Vec::new();
+    glib::source::unix_fd_add_local(
+        pipe.as_raw_fd(),
+        glib::IOCondition::IN | glib::IOCondition::HUP,
+        move |_fd, _ioc| {
+            let mut buf = [0; 4096];
+            match pipe.read(&mut buf) {
+                Ok(0) => {
+                    let _ = tx.take().unwrap().send(Some(std::mem::take(&mut data)));
+                    glib::Continue(false)
+                }
+                Ok(n) => {
+                    data.extend_from_slice(&buf[..n]);
+                    glib::Continue(true)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => glib::Continue(true),
+                Err(e) => {
+                    eprintln!("Could not read from the selection pipe: {:?}", e);
+                    let _ = tx.take().unwrap().send(None);
+                    glib::Continue(false)
+                }
+            }
+        },
+    );
+    rx.await.ok().flatten()
+}
@@ -6,15 +6,21 @@ use futures::{
 };
 use smithay_client_toolkit::{
     reexports::client::{
-        protocol::{wl_keyboard, wl_seat},
-        Attached, EventQueue, Interface, Main, MessageGroup, Proxy, ProxyMap,
+        protocol::{wl_callback, wl_keyboard, wl_seat},
+        Attached, Display, EventQueue, Interface, Main, MessageGroup, Proxy, ProxyMap, QueueToken,
     },
     seat,
 };
-use std::{marker::Unpin, pin::Pin};
+use std::{cell::RefCell, marker::Unpin, pin::Pin};
+
+thread_local! {
+    /// The queue dispatched by glib_add_wayland, for creating objects from the plain Display
+    static QUEUE_TOKEN: RefCell<Option<QueueToken>> = RefCell::new(None);
+}
 
 /// Enables Wayland event dispatch on the glib event loop. Requires 'static :(
 pub fn glib_add_wayland(event_queue: &'static mut EventQueue) {
+    QUEUE_TOKEN.with(|t| *t.borrow_mut() = Some(event_queue.token()));
     let fd = event_queue.display().get_connection_fd();
     glib::source::unix_fd_add_local(fd, glib::IOCondition::IN, move |_fd, _ioc| {
         if let Some(guard) = event_queue.prepare_read() {
@@ -147,6 +153,17 @@ where
     rx
 }
 
+/// Sends a wl_display.sync, the result resolves once the compositor has processed all previous requests.
+/// Flushes by itself, since nothing else would before the next event arrives.
+pub fn wayland_sync(display: &Display) -> oneshot::Receiver<wl_callback::Event> {
+    let token = QUEUE_TOKEN.with(|t| t.borrow().clone().expect("wayland_sync before glib_add_wayland"));
+    let rx = wayland_event_chan_oneshot(&(**display).clone().attach(token).sync());
+    if let Err(e) = display.flush() {
+        eprintln!("Error flushing the wayland socket: {:?}", e);
+    }
+    rx
+}
+
 /// Creates a mpsc channel for a Wayland object's events.
 pub fn wayland_keyboard_chan(
     seat: &Attached<wl_seat::WlSeat>,
@@ -0,0 +1,252 @@
+//! Tinting outputs through their gamma ramps (wlr-gamma-control), e.g. for a warmer night light.
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    rc::Rc,
+};
+
+use crate::surfaces::*;
+pub use smithay_client_toolkit::reexports::protocols::wlr::unstable::gamma_control::v1::client::{
+    zwlr_gamma_control_manager_v1 as gamma_control_manager, zwlr_gamma_control_v1 as gamma_control,
+};
+
+/// The colour temperature (in Kelvin) that leaves the output as it is
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// Approximate RGB of a blackbody at the given temperature (Tanner Helland's fit), each in 0..1
+fn blackbody(kelvin: u32) -> [f64; 3] {
+    let t = kelvin.max(1000).min(40000) as f64 / 100.0;
+    let r = if t <= 66.0 {
+        1.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592) / 255.0
+    };
+    let g = if t <= 66.0 {
+        (99.4708025861 * t.ln() - 161.1195681661) / 255.0
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492) / 255.0
+    };
+    let b = if t >= 66.0 {
+        1.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        (138.5177312231 * (t - 10.0).ln() - 305.0447927307) / 255.0
+    };
+    [r.max(0.0).min(1.0), g.max(0.0).min(1.0), b.max(0.0).min(1.0)]
+}
+
+/// The red, green and blue ramps one after another, as native endian u16s
+fn ramps(size: u32, kelvin: u32) -> Vec<u8> {
+    let (white, neutral) = (blackbody(kelvin), blackbody(NEUTRAL_TEMPERATURE));
+    let mut result = Vec::with_capacity(size as usize * 3 * 2);
+    for channel in 0..3 {
+        let scale = (white[channel] / neutral[channel]).min(1.0);
+        for i in 0..size {
+            let value = i as f64 / (size.max(2) - 1) as f64 * scale;
+            result.extend_from_slice(&((value * 65535.0).round() as u16).to_ne_bytes());
+        }
+    }
+    result
+}
+
+fn set_ramps(control: &gamma_control::ZwlrGammaControlV1, size: u32, kelvin: u32) {
+    // the fd is duplicated when the request is sent, the file itself doesn't need a name
+    let mut file = match glib::file_open_tmp(Some("waysmoke-gamma-XXXXXX")) {
+        Ok((fd, path)) => {
+            let _ = std::fs::remove_file(path);
+            unsafe { File::from_raw_fd(fd) }
+        }
+        Err(e) => {
+            eprintln!("Could not create a file for gamma ramps: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = file
+        .write_all(&ramps(size, kelvin))
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+    {
+        eprintln!("Could not write gamma ramps: {:?}", e);
+        return;
+    }
+    control.set_gamma(file.as_raw_fd());
+}
+
+struct OutputGamma {
+    output: wl_output::WlOutput,
+    control: Main<gamma_control::ZwlrGammaControlV1>,
+    /// Number of elements in each ramp, only known after the compositor sends it
+    size: Option<u32>,
+}
+
+impl Drop for OutputGamma {
+    fn drop(&mut self) {
+        // restores the original ramps
+        if self.control.as_ref().is_alive() {
+            self.control.destroy();
+        }
+    }
+}
+
+struct GammaState {
+    outputs: Vec<OutputGamma>,
+    temperature: u32,
+}
+
+/// Applies a colour temperature to all outputs, including ones connected later.
+/// Outputs go back to their original gamma when the service is dropped.
+pub struct GammaService {
+    _osl: output::OutputStatusListener,
+    state: Rc<RefCell<GammaState>>,
+    display: Display,
+}
+
+impl GammaService {
+    /// None if the compositor doesn't support gamma control
+    pub fn new(env: &Environment<Env>, display: Display) -> Option<GammaService> {
+        let manager = env.get_global::<gamma_control_manager::ZwlrGammaControlManagerV1>()?;
+        let state = Rc::new(RefCell::new(GammaState {
+            outputs: Vec::new(),
+            temperature: NEUTRAL_TEMPERATURE,
+        }));
+
+        let add_output = {
+            let state = state.clone();
+            let display = display.clone();
+            move |output: wl_output::WlOutput| {
+                let control = manager.get_gamma_control(&output);
+                let weak_state = Rc::downgrade(&state);
+                let display = display.clone();
+                let key = output.clone();
+                control.quick_assign(move |control, event, _| {
+                    let state = match weak_state.upgrade() {
+                        Some(state) => state,
+                        None => return,
+                    };
+                    match event {
+                        gamma_control::Event::GammaSize { size } => {
+                            let mut state = state.borrow_mut();
+                            let temperature = state.temperature;
+                            if let Some(o) = state.outputs.iter_mut().find(|o| o.output == key) {
+                                o.size = Some(size);
+                            }
+                            set_ramps(&control, size, temperature);
+                            if let Err(e) = display.flush() {
+                                eprintln!("Error flushing the wayland socket: {:?}", e);
+                            }
+                        }
+                        gamma_control::Event::Failed => {
+                            // e.g. another client (like gammastep) already controls the output
+                            eprintln!("WARN: gamma control failed for an output");
+                            if let Some(o) = state.borrow_mut().outputs.iter_mut().find(|o| o.output == key) {
+                                o.size = None;
+                            }
+                            control.destroy();
+                        }
+                        x => eprintln!("WARN: unknown gamma control event {:?}", x),
+                    }
+                });
+                state.borrow_mut().outputs.push(OutputGamma {
+                    output,
+                    control,
+                    size: None,
+                });
+            }
+        };
+
+        for output in env.get_all_outputs() {
+            add_output(output);
+        }
+
+        // also called for every info change of existing outputs, and for removed ones (obsolete)
+        let _osl = {
+            let state = state.clone();
+            env.listen_for_outputs(move |output, info, _| {
+                if info.obsolete {
+                    state.borrow_mut().outputs.retain(|o| o.output != output);
+                } else if !state.borrow().outputs.iter().any(|o| o.output == output) {
+                    add_output(output);
+                }
+            })
+        };
+
+        Some(GammaService { _osl, state, display })
+    }
+
+    pub fn temperature(&self) -> u32 {
+        self.state.borrow().temperature
+    }
+
+    /// Sets the colour temperature in Kelvin, `NEUTRAL_TEMPERATURE` turns the tint off
+    pub fn set_temperature(&self, kelvin: u32) {
+        let mut state = self.state.borrow_mut();
+        if state.temperature == kelvin {
+            return;
+        }
+        state.temperature = kelvin;
+        for o in state.outputs.iter() {
+            if let Some(size) = o.size {
+                set_ramps(&o.control, size, kelvin);
+            }
+        }
+        if let Err(e) = self.display.flush() {
+            eprintln!("Error flushing the wayland socket: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The i-th value of all the ramps one after another
+    fn value(ramps: &[u8], i: usize) -> u16 {
+        u16::from_ne_bytes([ramps[i * 2], ramps[i * 2 + 1]])
+    }
+
+    /// The last (brightest) value of each channel's ramp
+    fn ends(ramps: &[u8], size: usize) -> [u16; 3] {
+        [
+            value(ramps, size - 1),
+            value(ramps, size * 2 - 1),
+            value(ramps, size * 3 - 1),
+        ]
+    }
+
+    #[test]
+    fn blackbody_is_clamped() {
+        assert_eq!(blackbody(100), blackbody(1000));
+        assert_eq!(blackbody(100_000), blackbody(40000));
+        for kelvin in (1000..=40000).step_by(500) {
+            assert!(blackbody(kelvin).iter().all(|c| (0.0..=1.0).contains(c)), "{}", kelvin);
+        }
+    }
+
+    #[test]
+    fn neutral_ramps_are_identity() {
+        let ramps = ramps(256, NEUTRAL_TEMPERATURE);
+        assert_eq!(ramps.len(), 256 * 3 * 2);
+        for start in [0, 256, 512].iter() {
+            assert_eq!(value(&ramps, *start), 0);
+            assert_eq!(value(&ramps, start + 255), 65535);
+            assert!((1..256).all(|i| value(&ramps, start + i) > value(&ramps, start + i - 1)));
+        }
+    }
+
+    #[test]
+    fn warm_ramps_dim_blue_most() {
+        let [r, g, b] = ends(&ramps(256, 3000), 256);
+        assert_eq!(r, 65535);
+        assert!(b < g && g < r, "{} {} {}", r, g, b);
+    }
+
+    #[test]
+    fn tiny_ramps() {
+        assert!(ramps(0, 3000).is_empty());
+        assert_eq!(ramps(1, NEUTRAL_TEMPERATURE), vec![0; 6]);
+        assert_eq!(ends(&ramps(2, NEUTRAL_TEMPERATURE), 2), [65535; 3]);
+    }
+}
@@ -0,0 +1,837 @@
+//! Offscreen rendering of any IcedSurface without a Wayland connection,
+//! for golden-image and interaction tests.
+//! Synthetic input goes through the same IcedUi path as the decoded Wayland events do.
+//! Text uses the font bundled with iced, so that renders don't depend on the installed fonts.
+
+use iced_native::{keyboard, mouse, Size};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clipboard::Paste,
+    iced::*,
+    ime::{Preedit, TextField},
+    popup::PopupAnchor,
+    render::{self, software},
+    surfaces::wl_pointer,
+};
+
+/// In-memory stand-in for the Wayland clipboard, answering widget reads synchronously.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClipboard(pub Option<String>);
+
+impl iced_native::Clipboard for MemoryClipboard {
+    fn read(&self) -> Option<String> {
+        self.0.clone()
+    }
+
+    fn write(&mut self, contents: String) {
+        self.0 = Some(contents);
+    }
+}
+
+struct HeadlessPopup {
+    wanted: (PopupAnchor, Size),
+    canvas: software::Canvas,
+}
+
+pub struct Headless<T: IcedSurface> {
+    ui: IcedUi<T>,
+    canvas: software::Canvas,
+    popup: Option<HeadlessPopup>,
+    input_role: SurfaceRole,
+    last_mouse_interaction: mouse::Interaction,
+    /// Frame time, only moved by `advance`, so that animations render the same every run
+    now: Instant,
+    pub clipboard: MemoryClipboard,
+}
+
+impl<T: IcedSurface> Headless<T> {
+    /// Creates a harness for a surface of the given logical size and (possibly fractional) scale.
+    pub fn new(surface: T, width: u32, height: u32, scale: f64) -> Headless<T> {
+        let ui = IcedUi::new(
+            surface,
+            render::Renderer::new(render::Backend::Software(software::Backend::with_bundled_font())),
+        );
+        let mut headless = Headless {
+            ui,
+            canvas: software::Canvas::new(0, 0),
+            popup: None,
+            input_role: SurfaceRole::Main,
+            last_mouse_interaction: mouse::Interaction::Idle,
+            now: Instant::now(),
+            clipboard: MemoryClipboard::default(),
+        };
+        headless.resize(width, height, scale);
+        headless
+    }
+
+    pub fn surface(&self) -> &T {
+        &self.ui.surface
+    }
+
+    pub fn surface_mut(&mut self) -> &mut T {
+        &mut self.ui.surface
+    }
+
+    /// Like a layer surface configure or a scale change.
+    pub fn resize(&mut self, width: u32, height: u32, scale: f64) {
+        self.ui.main.size = Size::new(width as f32, height as f32);
+        self.ui.main.scale = scale;
+        let (width, height) = self.ui.main.physical_size();
+        self.canvas = software::Canvas::new(width, height);
+        self.ui.main.force_damage();
+        if let (Some(popup), Some(ui)) = (self.popup.as_mut(), self.ui.popup.as_mut()) {
+            ui.scale = scale;
+            let (width, height) = ui.physical_size();
+            popup.canvas = software::Canvas::new(width, height);
+            ui.force_damage();
+        }
+    }
+
+    /// The time the next frame is drawn at, to set animation targets with
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// Moves the frame clock forward, e.g. to halfway through or past the end of an animation.
+    pub fn advance(&mut self, by: Duration) {
+        self.now += by;
+    }
+
+    /// Directs the following synthetic input to the main surface or to the popup.
+    pub fn input_to(&mut self, role: SurfaceRole) {
+        self.input_role = role;
+    }
+
+    /// Opens or closes the popup like `IcedInstance` does, with the compositor accepting the requested size.
+    fn update_popup(&mut self) {
+        let wanted = self.ui.wanted_popup();
+        if wanted == self.popup.as_ref().map(|p| p.wanted) {
+            return;
+        }
+        self.ui.close_popup();
+        self.popup = match wanted {
+            Some((anchor, size)) => {
+                self.ui.open_popup(size);
+                let (width, height) = self.ui.popup.as_ref().unwrap().physical_size();
+                Some(HeadlessPopup {
+                    wanted: (anchor, size),
+                    canvas: software::Canvas::new(width, height),
+                })
+            }
+            None => None,
+        };
+    }
+
+    /// Processes queued input and redraws if anything changed, like a wl_pointer/wl_touch frame.
+    pub async fn frame(&mut self) {
+        self.ui.tick(self.now);
+        if let Some(output) = self.ui.draw(SurfaceRole::Main, &mut self.clipboard).await {
+            let viewport = self.ui.main.viewport();
+            let damage = self.ui.main.take_buffer_damage();
+            if let render::Backend::Software(backend) = self.ui.renderer.backend_mut() {
+                let inter = backend.draw(&mut self.canvas, &viewport, &output, &damage);
+                if self.input_role == SurfaceRole::Main {
+                    self.last_mouse_interaction = inter;
+                }
+            }
+        }
+        self.update_popup();
+        if self.popup.is_none() {
+            return;
+        }
+        if let Some(output) = self.ui.draw(SurfaceRole::Popup, &mut self.clipboard).await {
+            if let (Some(popup), Some(ui), render::Backend::Software(backend)) = (
+                self.popup.as_mut(),
+                self.ui.popup.as_mut(),
+                self.ui.renderer.backend_mut(),
+            ) {
+                let damage = ui.take_buffer_damage();
+                let inter = backend.draw(&mut popup.canvas, &ui.viewport(), &output, &damage);
+                if self.input_role == SurfaceRole::Popup {
+                    self.last_mouse_interaction = inter;
+                }
+            }
+        }
+    }
+
+    /// The current contents as straight-alpha RGBA, at physical size.
+    pub fn snapshot(&self) -> image::RgbaImage {
+        unpremultiply(&self.canvas)
+    }
+
+    /// Same as `snapshot`, for the popup if it's open.
+    pub fn popup_snapshot(&self) -> Option<image::RgbaImage> {
+        self.popup.as_ref().map(|p| unpremultiply(&p.canvas))
+    }
+
+    /// Where the open popup is anchored.
+    pub fn popup_anchor(&self) -> Option<PopupAnchor> {
+        self.popup.as_ref().map(|p| p.wanted.0)
+    }
+
+    /// Dismisses the popup, like the compositor would on a click outside of it.
+    pub async fn popup_done(&mut self) {
+        self.ui.close_popup();
+        self.popup = None;
+        self.ui.surface.on_popup_done().await;
+        self.frame().await;
+    }
+
+    pub async fn render(&mut self) -> image::RgbaImage {
+        self.frame().await;
+        self.snapshot()
+    }
+
+    /// The cursor the surface receiving input asked for in the last drawn frame.
+    pub fn mouse_interaction(&self) -> mouse::Interaction {
+        self.last_mouse_interaction
+    }
+
+    /// The input region the surface would set at the current size.
+    pub fn input_region(&self) -> Option<Vec<iced_native::Rectangle<u32>>> {
+        self.ui.input_region()
+    }
+
+    pub async fn pointer_enter(&mut self, x: f64, y: f64) {
+        self.ui.pointer_enter(self.input_role).await;
+        self.ui.pointer_motion(self.input_role, x, y);
+        self.frame().await;
+    }
+
+    /// Leaves immediately, without the debounce timeout.
+    pub async fn pointer_leave(&mut self) {
+        self.ui.pointer_leave(self.input_role);
+        self.ui.pointer_leave_settled().await;
+        self.frame().await;
+    }
+
+    pub async fn pointer_motion(&mut self, x: f64, y: f64) {
+        self.ui.pointer_motion(self.input_role, x, y);
+        self.frame().await;
+    }
+
+    /// Button codes are evdev ones, like in wl_pointer (0x110 is left).
+    pub async fn pointer_button(&mut self, button: u32, pressed: bool) {
+        self.ui.pointer_button(self.input_role, button, pressed);
+        self.frame().await;
+    }
+
+    pub async fn pointer_axis(&mut self, axis: wl_pointer::Axis, value: f64) {
+        self.ui.pointer_axis(self.input_role, axis, value);
+        self.frame().await;
+    }
+
+    /// Moves, presses and releases the left button, one frame each.
+    pub async fn click(&mut self, x: f64, y: f64) {
+        self.pointer_motion(x, y).await;
+        self.pointer_button(0x110, true).await;
+        self.pointer_button(0x110, false).await;
+    }
+
+    pub async fn touch_down(&mut self, id: i32, x: f64, y: f64) {
+        self.ui.touch_down(self.input_role, id, x, y).await;
+        self.frame().await;
+    }
+
+    pub async fn touch_motion(&mut self, id: i32, x: f64, y: f64) {
+        self.ui.touch_motion(self.input_role, id, x, y);
+        self.frame().await;
+    }
+
+    /// Like the long press timeout running out while the touch is still down
+    pub async fn touch_hold(&mut self) {
+        if self.ui.touch_hold(self.input_role) {
+            self.frame().await;
+        }
+    }
+
+    pub async fn touch_up(&mut self, id: i32) {
+        self.ui.touch_up(self.input_role, id);
+        self.frame().await;
+        if self.ui.touch_frame_done().await {
+            self.frame().await;
+        }
+    }
+
+    pub async fn keyboard_enter(&mut self) {
+        self.ui.keyboard_enter(self.input_role);
+        self.frame().await;
+    }
+
+    pub async fn keyboard_leave(&mut self) {
+        self.ui.keyboard_leave(self.input_role);
+        self.frame().await;
+    }
+
+    pub async fn modifiers(&mut self, modifiers: keyboard::Modifiers) {
+        self.ui.keyboard_modifiers(self.input_role, modifiers);
+        self.frame().await;
+    }
+
+    /// Keysyms are XKB ones, `utf8` is what xkb would produce for a press.
+    pub async fn key(&mut self, keysym: u32, pressed: bool, utf8: Option<&str>) {
+        if self
+            .ui
+            .keyboard_key(self.input_role, keysym, pressed, utf8.map(|s| s.to_owned()))
+        {
+            self.frame().await;
+        }
+    }
+
+    /// What the surface would tell input methods about its text field
+    pub fn text_field(&self) -> Option<TextField> {
+        self.ui.surface.text_field()
+    }
+
+    /// Acts like an input method finishing a change: deleting characters around the cursor, committing text,
+    /// then setting the preedit
+    pub async fn ime_done(&mut self, delete: (usize, usize), commit: Option<&str>, preedit: Option<Preedit>) {
+        self.ui
+            .ime_input(self.input_role, delete.0, delete.1, commit.map(|s| s.to_owned()));
+        self.ui.surface.on_preedit(preedit).await;
+        self.frame().await;
+    }
+
+    /// Delivers a completed clipboard read, like the ClipboardService would.
+    pub async fn paste(&mut self, paste: Paste) {
+        if self.ui.paste(paste) {
+            self.frame().await;
+        }
+    }
+
+    /// Presses and releases a key producing the given text.
+    pub async fn type_key(&mut self, keysym: u32, utf8: &str) {
+        self.key(keysym, true, Some(utf8)).await;
+        self.key(keysym, false, None).await;
+    }
+}
+
+fn unpremultiply(canvas: &software::Canvas) -> image::RgbaImage {
+    let mut data = canvas.data().to_vec();
+    for px in data.chunks_exact_mut(4) {
+        let a = px[3] as u32;
+        if a != 0 {
+            px[0] = (px[0] as u32 * 255 / a).min(255) as u8;
+            px[1] = (px[1] as u32 * 255 / a).min(255) as u8;
+            px[2] = (px[2] as u32 * 255 / a).min(255) as u8;
+        }
+    }
+    image::RgbaImage::from_raw(canvas.width(), canvas.height(), data).unwrap()
+}
+
+/// How far a channel can be off before a pixel counts as different, for float rounding across machines
+const REFERENCE_TOLERANCE: u8 = 2;
+
+/// Compares a render against a reference PNG checked in with the tests.
+/// A missing reference gets written instead, and so do all of them with `WSTK_BLESS=1` in the environment,
+/// to be looked at and checked in. On a mismatch, the render goes next to the reference as `*.actual.png`.
+pub fn assert_matches_reference(img: &image::RgbaImage, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    if std::env::var_os("WSTK_BLESS").is_some() || !path.exists() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        img.save(path).unwrap();
+        eprintln!("Wrote reference image {}", path.display());
+        return;
+    }
+    let reference = image::open(path).unwrap().to_rgba8();
+    let differing = if reference.dimensions() == img.dimensions() {
+        reference
+            .pixels()
+            .zip(img.pixels())
+            .filter(|(r, i)| {
+                r.0.iter()
+                    .zip(i.0.iter())
+                    .any(|(&r, &i)| r.max(i) - r.min(i) > REFERENCE_TOLERANCE)
+            })
+            .count()
+    } else {
+        (img.width() * img.height()) as usize
+    };
+    if differing > 0 {
+        let actual = path.with_extension("actual.png");
+        img.save(&actual).unwrap();
+        panic!(
+            "{} pixels differ from {} (the render is in {})",
+            differing,
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        animation::{Easing, Tween},
+        clipboard::Selection,
+        iced::{async_trait, Action, Element, IcedSurface, ImageHandle, SurfaceRole},
+        popup::PopupAnchor,
+        widgets::{addeventlistener, AddEventListener},
+    };
+    use futures::executor::block_on;
+    use iced_graphics::container;
+    use iced_native::{button, text_input, Background, Button, Color, Container, Length, Row, Space, TextInput};
+
+    struct Fill(Color);
+
+    impl container::StyleSheet for Fill {
+        fn style(&self) -> container::Style {
+            container::Style {
+                background: Some(Background::Color(self.0)),
+                ..container::Style::default()
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Msg {
+        Pressed,
+        PopupPressed,
+    }
+
+    /// A red button over the left half, nothing on the right, and a button in the popup when it's anchored
+    #[derive(Default)]
+    struct TestSurface {
+        button: button::State,
+        popup_button: button::State,
+        popup: Option<PopupAnchor>,
+        messages: Vec<Msg>,
+    }
+
+    #[async_trait(?Send)]
+    impl IcedSurface for TestSurface {
+        type Message = Msg;
+
+        fn view(&mut self) -> Element<'_, Msg> {
+            let fill = Container::new(Space::new(Length::Fill, Length::Fill))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .style(Fill(Color::from_rgb(1.0, 0.0, 0.0)));
+            Row::new()
+                .push(
+                    Button::new(&mut self.button, fill)
+                        .width(Length::Units(10))
+                        .height(Length::Fill)
+                        .padding(0)
+                        .on_press(Msg::Pressed),
+                )
+                .push(Space::new(Length::Fill, Length::Fill))
+                .into()
+        }
+
+        fn retained_images(&mut self) -> Vec<ImageHandle> {
+            Vec::new()
+        }
+
+        async fn update(&mut self, message: Msg) {
+            self.messages.push(message);
+        }
+
+        async fn run(&mut self) -> Action {
+            Action::DoNothing
+        }
+
+        fn popup_anchor(&self) -> Option<PopupAnchor> {
+            self.popup
+        }
+
+        fn popup_view(&mut self) -> Option<Element<'_, Msg>> {
+            Some(
+                Button::new(&mut self.popup_button, Space::new(Length::Units(8), Length::Units(6)))
+                    .padding(0)
+                    .on_press(Msg::PopupPressed)
+                    .into(),
+            )
+        }
+
+        async fn on_popup_done(&mut self) {
+            self.popup = None;
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum FieldMsg {
+        Changed(String),
+        Paste(String),
+    }
+
+    /// A single text input over the whole surface
+    #[derive(Default)]
+    struct FieldSurface {
+        input: text_input::State,
+        value: String,
+        preedit: Option<Preedit>,
+    }
+
+    #[async_trait(?Send)]
+    impl IcedSurface for FieldSurface {
+        type Message = FieldMsg;
+
+        fn view(&mut self) -> Element<'_, FieldMsg> {
+            TextInput::new(&mut self.input, "", &self.value, FieldMsg::Changed)
+                .padding(0)
+                .into()
+        }
+
+        fn retained_images(&mut self) -> Vec<ImageHandle> {
+            Vec::new()
+        }
+
+        async fn update(&mut self, message: FieldMsg) {
+            match message {
+                FieldMsg::Changed(value) => self.value = value,
+                FieldMsg::Paste(text) => self.value.push_str(&text),
+            }
+        }
+
+        async fn run(&mut self) -> Action {
+            Action::DoNothing
+        }
+
+        fn on_paste(&mut self, paste: Paste) -> Option<FieldMsg> {
+            paste.text().map(FieldMsg::Paste)
+        }
+
+        fn text_field(&self) -> Option<TextField> {
+            use iced_native::text_input::{cursor, Value};
+
+            if !self.input.is_focused() {
+                return None;
+            }
+            let value = Value::new(&self.value);
+            let (cursor, anchor) = match self.input.cursor().state(&value) {
+                cursor::State::Index(i) => (i, i),
+                cursor::State::Selection { start, end } => (end, start),
+            };
+            let byte_offset = |i| value.until(i).to_string().len();
+            Some(TextField {
+                surrounding: self.value.clone(),
+                cursor: byte_offset(cursor),
+                anchor: byte_offset(anchor),
+                cursor_rect: Default::default(),
+                password: false,
+            })
+        }
+
+        async fn on_preedit(&mut self, preedit: Option<Preedit>) {
+            self.preedit = preedit;
+        }
+    }
+
+    /// Red fading in over 100ms
+    struct FadeSurface {
+        opacity: Tween,
+        now: Instant,
+    }
+
+    impl FadeSurface {
+        fn new() -> FadeSurface {
+            FadeSurface {
+                opacity: Tween::new(0.0, Duration::from_millis(100), Easing::Linear),
+                now: Instant::now(),
+            }
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl IcedSurface for FadeSurface {
+        type Message = ();
+
+        fn view(&mut self) -> Element<'_, ()> {
+            Container::new(Space::new(Length::Fill, Length::Fill))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .style(Fill(Color::from_rgba(1.0, 0.0, 0.0, self.opacity.value(self.now))))
+                .into()
+        }
+
+        fn retained_images(&mut self) -> Vec<ImageHandle> {
+            Vec::new()
+        }
+
+        async fn update(&mut self, _message: ()) {}
+
+        async fn run(&mut self) -> Action {
+            Action::DoNothing
+        }
+
+        fn on_frame(&mut self, now: Instant) {
+            self.now = now;
+        }
+
+        fn is_animating(&self) -> bool {
+            self.opacity.is_running(self.now)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum ListenerMsg {
+        Clicked,
+        Secondary(f32, f32),
+        Middle,
+        LongPress(f32, f32),
+        Scrolled(f32),
+    }
+
+    /// A button over the left half with every AddEventListener hook, nothing on the right
+    #[derive(Default)]
+    struct ListenerSurface {
+        button: button::State,
+        evl: addeventlistener::State,
+        messages: Vec<ListenerMsg>,
+    }
+
+    #[async_trait(?Send)]
+    impl IcedSurface for ListenerSurface {
+        type Message = ListenerMsg;
+
+        fn view(&mut self) -> Element<'_, ListenerMsg> {
+            let button = Button::new(&mut self.button, Space::new(Length::Units(20), Length::Units(10)))
+                .padding(0)
+                .on_press(ListenerMsg::Clicked);
+            let listener = AddEventListener::new(&mut self.evl, button)
+                .on_secondary_click(|info| ListenerMsg::Secondary(info.position.x, info.position.y))
+                .on_middle_click(|_| ListenerMsg::Middle)
+                .on_long_press(|info| ListenerMsg::LongPress(info.position.x, info.position.y))
+                .on_scroll(|delta, _| match delta {
+                    mouse::ScrollDelta::Pixels { y, .. } | mouse::ScrollDelta::Lines { y, .. } => {
+                        ListenerMsg::Scrolled(y)
+                    }
+                });
+            Row::new()
+                .push(listener)
+                .push(Space::new(Length::Fill, Length::Fill))
+                .into()
+        }
+
+        fn retained_images(&mut self) -> Vec<ImageHandle> {
+            Vec::new()
+        }
+
+        async fn update(&mut self, message: ListenerMsg) {
+            self.messages.push(message);
+        }
+
+        async fn run(&mut self) -> Action {
+            Action::DoNothing
+        }
+    }
+
+    #[test]
+    fn renders_at_physical_size() {
+        block_on(async {
+            let mut h = Headless::new(TestSurface::default(), 20, 10, 2.0);
+            let img = h.render().await;
+            assert_eq!(img.dimensions(), (40, 20));
+            assert_eq!(img.get_pixel(10, 10).0, [255, 0, 0, 255]);
+            assert_eq!(img.get_pixel(30, 10).0, [0, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn compares_against_reference_images() {
+        block_on(async {
+            let dir = std::env::temp_dir().join(format!("wstk-reference-{}", std::process::id()));
+            let path = dir.join("button.png");
+            let mut h = Headless::new(TestSurface::default(), 20, 10, 1.0);
+            let img = h.render().await;
+            assert_matches_reference(&img, &path);
+            assert!(path.exists());
+            assert_matches_reference(&img, &path);
+
+            h.resize(20, 10, 2.0);
+            let bigger = h.render().await;
+            let mismatch = std::panic::catch_unwind(|| assert_matches_reference(&bigger, &path));
+            assert!(mismatch.is_err());
+            assert!(dir.join("button.actual.png").exists());
+            std::fs::remove_dir_all(dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn renders_at_fractional_scale() {
+        block_on(async {
+            let mut h = Headless::new(TestSurface::default(), 20, 10, 1.5);
+            let img = h.render().await;
+            assert_eq!(img.dimensions(), (30, 15));
+            assert_eq!(img.get_pixel(7, 7).0, [255, 0, 0, 255]);
+            assert_eq!(img.get_pixel(22, 7).0, [0, 0, 0, 0]);
+
+            h.resize(20, 10, 1.25);
+            let img = h.render().await;
+            assert_eq!(img.dimensions(), (25, 13));
+            assert_eq!(img.get_pixel(6, 6).0, [255, 0, 0, 255]);
+
+            // input stays in logical coordinates
+            h.pointer_enter(9.0, 5.0).await;
+            h.click(9.0, 5.0).await;
+            assert_eq!(h.surface().messages, vec![Msg::Pressed]);
+        });
+    }
+
+    #[test]
+    fn pointer_and_touch_press_buttons() {
+        block_on(async {
+            let mut h = Headless::new(TestSurface::default(), 20, 10, 1.0);
+            h.render().await;
+            h.pointer_enter(15.0, 5.0).await;
+            h.click(15.0, 5.0).await;
+            assert!(h.surface().messages.is_empty());
+            h.click(5.0, 5.0).await;
+            assert_eq!(h.surface().messages, vec![Msg::Pressed]);
+            h.pointer_leave().await;
+
+            h.touch_down(0, 15.0, 5.0).await;
+            h.touch_up(0).await;
+            assert_eq!(h.surface().messages, vec![Msg::Pressed]);
+            h.touch_down(0, 5.0, 5.0).await;
+            h.touch_up(0).await;
+            assert_eq!(h.surface().messages, vec![Msg::Pressed, Msg::Pressed]);
+        });
+    }
+
+    #[test]
+    fn popup_opens_and_takes_input() {
+        block_on(async {
+            let mut h = Headless::new(TestSurface::default(), 20, 10, 2.0);
+            h.render().await;
+            assert!(h.popup_snapshot().is_none());
+
+            let anchor = PopupAnchor {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 10,
+                grab: false,
+            };
+            h.surface_mut().popup = Some(anchor);
+            h.frame().await;
+            assert_eq!(h.popup_anchor(), Some(anchor));
+            assert_eq!(h.popup_snapshot().unwrap().dimensions(), (16, 12));
+
+            h.input_to(SurfaceRole::Popup);
+            h.pointer_enter(4.0, 3.0).await;
+            h.click(4.0, 3.0).await;
+            assert_eq!(h.surface().messages, vec![Msg::PopupPressed]);
+
+            h.popup_done().await;
+            assert!(h.popup_snapshot().is_none());
+            assert!(h.surface().popup.is_none());
+        });
+    }
+
+    #[test]
+    fn input_method_and_paste_edit_text_fields() {
+        block_on(async {
+            let mut h = Headless::new(FieldSurface::default(), 100, 30, 1.0);
+            h.render().await;
+            assert!(h.text_field().is_none());
+            h.pointer_enter(10.0, 10.0).await;
+            h.click(10.0, 10.0).await;
+            h.keyboard_enter().await;
+            assert_eq!(h.text_field().map(|f| f.surrounding), Some(String::new()));
+
+            let preedit = Preedit {
+                text: "h".to_owned(),
+                cursor: Some((1, 1)),
+            };
+            h.ime_done((0, 0), None, Some(preedit.clone())).await;
+            assert_eq!(h.surface().preedit, Some(preedit));
+            h.ime_done((0, 0), Some("hello"), None).await;
+            assert_eq!(h.surface().value, "hello");
+            assert_eq!(h.surface().preedit, None);
+            h.ime_done((2, 0), Some("p"), None).await;
+            assert_eq!(h.surface().value, "help");
+            let field = h.text_field().unwrap();
+            assert_eq!((field.cursor, field.anchor), (4, 4));
+
+            h.paste(Paste {
+                selection: Selection::Clipboard,
+                mime_type: "text/plain".to_owned(),
+                data: b"!".to_vec(),
+            })
+            .await;
+            assert_eq!(h.surface().value, "help!");
+
+            // keys only go to the surface with keyboard focus
+            // (the paste went through the surface, the cursor is still after "help")
+            h.type_key(0x0061, "a").await;
+            assert_eq!(h.surface().value, "helpa!");
+            h.keyboard_leave().await;
+            h.type_key(0x0062, "b").await;
+            assert_eq!(h.surface().value, "helpa!");
+        });
+    }
+
+    #[test]
+    fn animations_follow_the_frame_clock() {
+        block_on(async {
+            let mut h = Headless::new(FadeSurface::new(), 4, 4, 1.0);
+            assert_eq!(h.render().await.get_pixel(1, 1).0, [0, 0, 0, 0]);
+
+            let now = h.now();
+            h.surface_mut().opacity.set(1.0, now);
+            h.advance(Duration::from_millis(50));
+            let half = h.render().await.get_pixel(1, 1).0;
+            assert!(h.surface().is_animating());
+            assert_eq!(half[0], 255);
+            assert!((126..=129).contains(&half[3]), "{:?}", half);
+
+            h.advance(Duration::from_millis(100));
+            assert_eq!(h.render().await.get_pixel(1, 1).0, [255, 0, 0, 255]);
+            assert!(!h.surface().is_animating());
+        });
+    }
+
+    #[test]
+    fn event_listener_clicks_scrolls_and_long_presses() {
+        block_on(async {
+            let mut h = Headless::new(ListenerSurface::default(), 40, 10, 1.0);
+            h.render().await;
+            h.pointer_enter(5.0, 4.0).await;
+            h.pointer_button(0x111, true).await;
+            h.pointer_button(0x111, false).await;
+            h.pointer_button(0x112, true).await;
+            h.pointer_button(0x112, false).await;
+            h.pointer_axis(wl_pointer::Axis::VerticalScroll, 10.0).await;
+            h.click(5.0, 4.0).await;
+            assert_eq!(
+                h.surface().messages,
+                vec![
+                    ListenerMsg::Secondary(5.0, 4.0),
+                    ListenerMsg::Middle,
+                    ListenerMsg::Scrolled(-10.0),
+                    ListenerMsg::Clicked,
+                ]
+            );
+
+            // nothing outside of the listener
+            h.surface_mut().messages.clear();
+            h.pointer_motion(30.0, 4.0).await;
+            h.pointer_button(0x111, true).await;
+            h.pointer_button(0x111, false).await;
+            h.pointer_axis(wl_pointer::Axis::VerticalScroll, 10.0).await;
+            assert!(h.surface().messages.is_empty());
+            h.pointer_leave().await;
+
+            // a held touch is a long press instead of a click
+            h.touch_down(0, 6.0, 3.0).await;
+            h.touch_hold().await;
+            h.touch_up(0).await;
+            assert_eq!(h.surface().messages, vec![ListenerMsg::LongPress(6.0, 3.0)]);
+            h.touch_down(0, 6.0, 3.0).await;
+            h.touch_up(0).await;
+            assert_eq!(
+                h.surface().messages,
+                vec![ListenerMsg::LongPress(6.0, 3.0), ListenerMsg::Clicked]
+            );
+        });
+    }
+}
@@ -1,87 +1,48 @@
-use iced_graphics::window::Compositor;
 pub use iced_native::Rectangle;
-use iced_native::{keyboard, mouse, Cache, Damage, Point, Size, UserInterface};
-use iced_wgpu::window::Compositor as WgpuCompositor;
+use iced_native::{keyboard, mouse, Cache, Damage, Length, Point, Size, UserInterface};
 
 use std::{
-    cell::RefCell,
-    io::{Read, Write},
     pin::Pin,
-    sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 pub use async_trait::async_trait;
+use futures::channel::oneshot;
 pub use futures::{channel::mpsc, future, prelude::*};
 
-use crate::{event_loop::*, run::*, surfaces::*};
+use crate::{
+    clipboard::*,
+    event_loop::*,
+    ime::*,
+    keys::{self, KeyboardEvent},
+    popup::*,
+    render,
+    run::*,
+    seats::*,
+    session_lock::SessionLock,
+    surface_registry::SurfaceEvent,
+    surfaces::*,
+    widgets::LONG_PRESS_BUTTON,
+};
 
-pub struct Clipboard {
-    env: Environment<Env>,
-    seat: wl_seat::WlSeat,
-    last_enter_serial: u32,
-    received: Arc<RefCell<Option<String>>>,
-    paste_inject_tx: Arc<mpsc::UnboundedSender<()>>,
-}
+/// Popups are measured with this as the maximum height, the compositor shrinks them if they don't fit.
+const POPUP_MAX_HEIGHT: f32 = 4096.0;
+/// How long a touch has to be held in place to be a long press, and how far (in logical pixels) it can wander
+const LONG_PRESS_DURATION: Duration = Duration::from_millis(500);
+const LONG_PRESS_SLOP: f32 = 12.0;
+
+/// Adapter for iced widgets. Reading is asynchronous, so `read` only starts it,
+/// and the text arrives later through `IcedSurface::on_paste`.
+pub struct Clipboard(ClipboardService);
 
 impl iced_native::Clipboard for Clipboard {
-    // This really should be asynchronous >_<
-    // As a workaround this code initiates on the first call and actually returns on the second call.
-    // To make the second call happen automatically, the callback injects a Ctrl-V keystroke :D
     fn read(&self) -> Option<String> {
-        if let Some(result) = self.received.borrow_mut().take() {
-            return Some(result);
-        }
-        self.env
-            .with_data_device(&self.seat, |device| {
-                device.with_selection(|offer| {
-                    let offer = match offer {
-                        Some(offer) => offer,
-                        None => {
-                            return;
-                        }
-                    };
-
-                    let has_text = offer.with_mime_types(|types| types.iter().any(|t| t == "text/plain;charset=utf-8"));
-                    if !has_text {
-                        return;
-                    }
-                    if let Ok(mut reader) = offer.receive("text/plain;charset=utf-8".into()) {
-                        use std::os::unix::io::AsRawFd;
-                        let received = self.received.clone();
-                        let inject_tx = self.paste_inject_tx.clone();
-                        glib::source::unix_fd_add_local(reader.as_raw_fd(), glib::IOCondition::IN, move |_fd, _ioc| {
-                            let mut txt = String::new();
-                            reader.read_to_string(&mut txt).unwrap();
-                            received.borrow_mut().replace(txt);
-                            inject_tx.unbounded_send(()).unwrap();
-                            glib::Continue(false)
-                        });
-                    }
-                });
-            })
-            .unwrap();
+        self.0.request_text(Selection::Clipboard);
         None
     }
 
     fn write(&mut self, contents: String) {
-        let data_source = self.env.new_data_source(
-            vec!["text/plain;charset=utf-8".into(), "UTF8_STRING".into()],
-            move |event, _| match event {
-                data_device::DataSourceEvent::Send { mut pipe, .. } => {
-                    if let Err(x) = write!(pipe, "{}", contents) {
-                        eprintln!("Could not send clipboard text: {:?}", x);
-                    }
-                }
-                _ => (),
-            },
-        );
-
-        self.env
-            .with_data_device(&self.seat, |device| {
-                device.set_selection(&Some(data_source), self.last_enter_serial);
-            })
-            .unwrap();
+        self.0.write_text(Selection::Clipboard, contents);
     }
 }
 
@@ -98,7 +59,18 @@ pub enum ImageHandle {
     Raster(iced_native::image::Handle),
 }
 
-pub type Element<'a, Message> = iced_native::Element<'a, Message, iced_wgpu::Renderer>;
+/// Which of an instance's wl_surfaces something is happening on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceRole {
+    /// The layer surface, showing `IcedSurface::view`
+    Main,
+    /// The xdg_popup, showing `IcedSurface::popup_view`
+    Popup,
+}
+
+const ROLES: [SurfaceRole; 2] = [SurfaceRole::Main, SurfaceRole::Popup];
+
+pub type Element<'a, Message> = iced_native::Element<'a, Message, render::Renderer>;
 
 #[async_trait(?Send)]
 pub trait IcedSurface {
@@ -117,42 +89,606 @@ pub trait IcedSurface {
     async fn on_pointer_leave(&mut self) {}
     async fn on_touch_enter(&mut self) {}
     async fn on_touch_leave(&mut self) {}
+
+    /// Called once the instance is created, keep the service to read or write arbitrary types.
+    fn attach_clipboard(&mut self, _clipboard: &ClipboardService) {}
+    /// Turns a completed read of the selection into a message.
+    /// Reads are started by widgets (e.g. Ctrl-V in a text input) or by `ClipboardService::request`.
+    fn on_paste(&mut self, _paste: Paste) -> Option<Self::Message> {
+        None
+    }
+    /// Text currently selected in the surface (e.g. in a text input), which becomes the primary selection.
+    /// Pasting it with a middle click arrives through `on_paste` with `Selection::Primary`.
+    fn primary_selection(&self) -> Option<String> {
+        None
+    }
+    /// The focused text field, if any, which input methods can type into.
+    /// Checked after every render like `primary_selection`. The cursor rectangle is relative to
+    /// the surface with keyboard focus, so a field in the popup is described in popup coordinates.
+    fn text_field(&self) -> Option<TextField> {
+        None
+    }
+    /// Text the input method is composing, to be shown at the cursor of the text field
+    /// until it's committed and arrives as regular typing. None clears it.
+    async fn on_preedit(&mut self, _preedit: Option<Preedit>) {}
+    /// Called with the seat whose input is about to be processed,
+    /// e.g. to activate toplevels with the seat that actually clicked.
+    fn set_input_seat(&mut self, _seat: &wl_seat::WlSeat) {}
+
+    /// Where the popup should be, relative to the main surface. Checked after every render,
+    /// the popup is (re)created whenever this or the size of `popup_view` changes.
+    fn popup_anchor(&self) -> Option<PopupAnchor> {
+        None
+    }
+    /// Contents of the popup, the popup surface is sized to fit them.
+    fn popup_view(&mut self) -> Option<Element<'_, Self::Message>> {
+        None
+    }
+    /// The compositor dismissed the popup (e.g. a click outside of a grabbing one).
+    /// It won't be shown again until the anchor changes.
+    async fn on_popup_done(&mut self) {}
+
+    /// Called before each frame is drawn with the time it's for, to sample animations (see `Tween`) at.
+    fn on_frame(&mut self, _now: Instant) {}
+    /// Whether anything is still moving, checked after every render.
+    /// While it is, the surface keeps drawing a new frame whenever the compositor asks for one.
+    fn is_animating(&self) -> bool {
+        false
+    }
 }
 
-pub struct IcedInstance<T: IcedSurface> {
-    parent: DesktopInstance,
-    surface: T,
+fn view_for<T: IcedSurface>(surface: &mut T, role: SurfaceRole) -> Element<'_, T::Message> {
+    match role {
+        SurfaceRole::Main => surface.view(),
+        SurfaceRole::Popup => surface
+            .popup_view()
+            .unwrap_or_else(|| iced_native::Space::new(Length::Fill, Length::Fill).into()),
+    }
+}
 
-    // wayland state
-    ptr_active: bool,
-    kb_active: bool,
-    scale: i32,
-    leave_timeout: Option<future::Fuse<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>>,
-    prev_input_region: Option<Vec<Rectangle<u32>>>,
-    touch_point: Option<i32>,
-    touch_leave: bool,
-    themed_ptr: Option<pointer::ThemedPointer>,
-    last_ptr_serial: Option<u32>,
-    keyboard_handle: Option<Main<wl_keyboard::WlKeyboard>>,
-    keyboard_events: mpsc::UnboundedReceiver<seat::keyboard::Event>,
-    ptr: Option<AsyncMain<wl_pointer::WlPointer>>,
-    touch: Option<AsyncMain<wl_touch::WlTouch>>,
+/// The iced state of one of an instance's surfaces.
+pub(crate) struct SurfaceUi {
+    // input state
+    pub(crate) ptr_active: bool,
+    pub(crate) kb_active: bool,
+    pub(crate) touch_point: Option<i32>,
+    /// Where the tracked point went down, until it moves too far away to become a long press
+    touch_origin: Option<Point>,
+    /// The tracked point became a long press, so lifting it isn't a tap
+    touch_held: bool,
 
     // iced render state
+    pub(crate) scale: f64,
+    pub(crate) size: Size,
     cache: Cache,
-    size: Size,
     cursor_position: Point,
     keyboard_mods: keyboard::Modifiers,
-    paste_inject_rx: mpsc::UnboundedReceiver<()>,
-    compositor: WgpuCompositor,
-    renderer: <WgpuCompositor as Compositor>::Renderer,
-    gpu_surface: <WgpuCompositor as Compositor>::Surface,
-    swap_chain: Option<<WgpuCompositor as Compositor>::SwapChain>,
     prev_prim: iced_graphics::Primitive,
+    /// What the last draw changed, in logical coordinates
+    damage: Vec<Rectangle>,
+    /// Whether the whole buffer has to be redrawn, e.g. because it was just (re)created
+    full_damage: bool,
     queue: Vec<iced_native::Event>,
+}
+
+impl SurfaceUi {
+    fn new(scale: f64, size: Size) -> SurfaceUi {
+        SurfaceUi {
+            ptr_active: false,
+            kb_active: false,
+            touch_point: None,
+            touch_origin: None,
+            touch_held: false,
+            scale,
+            size,
+            cache: Cache::new(),
+            cursor_position: Point::default(),
+            keyboard_mods: Default::default(),
+            prev_prim: iced_graphics::Primitive::None,
+            damage: Vec::new(),
+            full_damage: true,
+            queue: Vec::new(),
+        }
+    }
+
+    /// Buffer size for the current logical size and (possibly fractional) scale
+    pub(crate) fn physical_size(&self) -> (u32, u32) {
+        (
+            (self.size.width as f64 * self.scale).round() as u32,
+            (self.size.height as f64 * self.scale).round() as u32,
+        )
+    }
+
+    pub(crate) fn viewport(&self) -> iced_graphics::Viewport {
+        let (width, height) = self.physical_size();
+        iced_graphics::Viewport::with_physical_size(iced_graphics::Size::new(width, height), self.scale)
+    }
+
+    /// Forgets the previous frame, so that the next draw is not skipped as undamaged
+    pub(crate) fn force_damage(&mut self) {
+        self.prev_prim = iced_graphics::Primitive::None;
+        self.full_damage = true;
+    }
+
+    /// Takes what the last draw changed, in buffer pixels.
+    /// Rectangles are grown to whole pixels, plus one for antialiased edges that spill over.
+    pub(crate) fn take_buffer_damage(&mut self) -> Vec<Rectangle<u32>> {
+        let (width, height) = self.physical_size();
+        let damage = std::mem::replace(&mut self.damage, Vec::new());
+        if std::mem::replace(&mut self.full_damage, false) {
+            return vec![Rectangle {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }];
+        }
+        damage
+            .into_iter()
+            .filter_map(|r| {
+                let x0 = ((r.x as f64 * self.scale).floor() as i64 - 1).max(0) as u32;
+                let y0 = ((r.y as f64 * self.scale).floor() as i64 - 1).max(0) as u32;
+                let x1 = (((r.x + r.width) as f64 * self.scale).ceil() as i64 + 1).min(width as i64) as u32;
+                let y1 = (((r.y + r.height) as f64 * self.scale).ceil() as i64 + 1).min(height as i64) as u32;
+                if x0 >= x1 || y0 >= y1 {
+                    return None;
+                }
+                Some(Rectangle {
+                    x: x0,
+                    y: y0,
+                    width: x1 - x0,
+                    height: y1 - y0,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The Wayland-independent part of an instance: iced state, input translation and drawing.
+/// Wayland events are decoded by `IcedInstance` and fed here, headless tests feed it directly.
+pub struct IcedUi<T: IcedSurface> {
+    pub(crate) surface: T,
+    pub(crate) main: SurfaceUi,
+    pub(crate) popup: Option<SurfaceUi>,
+    touch_leave: bool,
+    /// Shared by all surfaces, so that e.g. glyph caches don't have to be built twice
+    pub(crate) renderer: render::Renderer,
     messages: Vec<T::Message>,
+}
+
+impl<T: IcedSurface> IcedUi<T> {
+    pub(crate) fn new(surface: T, renderer: render::Renderer) -> IcedUi<T> {
+        IcedUi {
+            surface,
+            main: SurfaceUi::new(1.0, Size::new(0.0, 0.0)),
+            popup: None,
+            touch_leave: false,
+            renderer,
+            messages: Vec::new(),
+        }
+    }
+
+    fn ui_mut(&mut self, role: SurfaceRole) -> Option<&mut SurfaceUi> {
+        match role {
+            SurfaceRole::Main => Some(&mut self.main),
+            SurfaceRole::Popup => self.popup.as_mut(),
+        }
+    }
+
+    pub(crate) fn touch_point(&self, role: SurfaceRole) -> Option<i32> {
+        match role {
+            SurfaceRole::Main => self.main.touch_point,
+            SurfaceRole::Popup => self.popup.as_ref().and_then(|ui| ui.touch_point),
+        }
+    }
+
+    pub(crate) fn input_region(&self) -> Option<Vec<Rectangle<u32>>> {
+        self.surface
+            .input_region(self.main.size.width as _, self.main.size.height as _)
+    }
+
+    /// What the surface wants its popup to be now: the anchor, and the size its contents need
+    pub(crate) fn wanted_popup(&mut self) -> Option<(PopupAnchor, Size)> {
+        let anchor = self.surface.popup_anchor()?;
+        if self.main.size.width < 1.0 {
+            return None;
+        }
+        let limits = iced_native::layout::Limits::new(Size::ZERO, Size::new(self.main.size.width, POPUP_MAX_HEIGHT));
+        let size = self.surface.popup_view()?.layout(&self.renderer, &limits).size();
+        if size.width < 1.0 || size.height < 1.0 {
+            return None;
+        }
+        Some((anchor, Size::new(size.width.ceil(), size.height.ceil())))
+    }
+
+    pub(crate) fn open_popup(&mut self, size: Size) {
+        self.close_popup();
+        self.popup = Some(SurfaceUi::new(self.main.scale, size));
+    }
+
+    /// Advances the surface's animations to the time of the frame about to be drawn
+    pub(crate) fn tick(&mut self, now: Instant) {
+        self.surface.on_frame(now);
+    }
+
+    pub(crate) fn close_popup(&mut self) {
+        if let Some(ui) = self.popup.take() {
+            if ui.touch_point.is_some() {
+                self.touch_leave = true;
+            }
+        }
+    }
+
+    /// Processes the queued events (and the messages they produce) of one surface,
+    /// returning its new output if anything has changed since the last draw.
+    pub(crate) async fn draw(
+        &mut self,
+        role: SurfaceRole,
+        clipboard: &mut dyn iced_native::Clipboard,
+    ) -> Option<(iced_graphics::Primitive, mouse::Interaction)> {
+        for h in self.surface.retained_images() {
+            match h {
+                ImageHandle::Raster(h) => self.renderer.backend_mut().retain_raster(&h),
+                ImageHandle::Vector(h) => self.renderer.backend_mut().retain_vector(&h),
+            }
+        }
+
+        let ui = match role {
+            SurfaceRole::Main => &mut self.main,
+            SurfaceRole::Popup => self.popup.as_mut()?,
+        };
+        let mut user_interface = UserInterface::build(
+            view_for(&mut self.surface, role),
+            ui.size,
+            ui.cache.clone(),
+            &mut self.renderer,
+        );
+        user_interface.update(
+            &ui.queue.drain(..).collect::<Vec<_>>(),
+            ui.cursor_position,
+            &mut self.renderer,
+            clipboard,
+            &mut self.messages,
+        );
+
+        if !self.messages.is_empty() {
+            // iced-winit says we are forced to rebuild twice
+            let temp_cache = user_interface.into_cache();
+
+            for message in self.messages.drain(..) {
+                self.surface.update(message).await;
+            }
+
+            user_interface = UserInterface::build(
+                view_for(&mut self.surface, role),
+                ui.size,
+                temp_cache,
+                &mut self.renderer,
+            );
+        }
+
+        let (primitive, mi) = user_interface.draw(&mut self.renderer, ui.cursor_position);
+        ui.cache = user_interface.into_cache();
+        let dmg = ui.prev_prim.damage(&primitive);
+        ui.prev_prim = primitive.clone();
+        match dmg {
+            Some(rects) if !rects.is_empty() => ui.damage = rects,
+            _ => return None,
+        }
+        Some((primitive, mi))
+    }
+
+    pub(crate) async fn pointer_enter(&mut self, role: SurfaceRole) {
+        if let Some(ui) = self.ui_mut(role) {
+            ui.ptr_active = true;
+        }
+        self.surface.on_pointer_enter().await;
+    }
+
+    pub(crate) fn pointer_leave(&mut self, role: SurfaceRole) {
+        if let Some(ui) = self.ui_mut(role) {
+            ui.ptr_active = false;
+        }
+    }
+
+    /// The delayed part of the leave, so that going over a gap between input regions
+    /// (or from the main surface to the popup) doesn't flicker
+    pub(crate) async fn pointer_leave_settled(&mut self) {
+        self.surface.on_pointer_leave().await;
+    }
+
+    pub(crate) fn pointer_button(&mut self, role: SurfaceRole, button: u32, pressed: bool) {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.ptr_active => ui,
+            _ => return,
+        };
+        let btn = match button {
+            0x110 => mouse::Button::Left,
+            0x111 => mouse::Button::Right,
+            0x112 => mouse::Button::Middle,
+            x if x > 0x110 => mouse::Button::Other((x - 0x110) as u8),
+            _ => panic!("low button event code"),
+        };
+        ui.queue.push(iced_native::Event::Mouse(if pressed {
+            mouse::Event::ButtonPressed(btn)
+        } else {
+            mouse::Event::ButtonReleased(btn)
+        }));
+    }
+
+    pub(crate) fn pointer_motion(&mut self, role: SurfaceRole, x: f64, y: f64) {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.ptr_active => ui,
+            _ => return,
+        };
+        ui.cursor_position = Point::new(x as _, y as _);
+        ui.queue.push(iced_native::Event::Mouse(mouse::Event::CursorMoved {
+            position: Point { x: x as _, y: y as _ },
+        }));
+    }
+
+    pub(crate) fn pointer_axis(&mut self, role: SurfaceRole, axis: wl_pointer::Axis, value: f64) {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.ptr_active => ui,
+            _ => return,
+        };
+        ui.queue.push(iced_native::Event::Mouse(mouse::Event::WheelScrolled {
+            delta: mouse::ScrollDelta::Pixels {
+                x: if axis == wl_pointer::Axis::HorizontalScroll {
+                    -value as _
+                } else {
+                    0.0
+                },
+                y: if axis == wl_pointer::Axis::VerticalScroll {
+                    -value as _
+                } else {
+                    0.0
+                },
+            },
+        }));
+    }
+
+    /// Returns false if the touch was ignored because another point is already tracked
+    pub(crate) async fn touch_down(&mut self, role: SurfaceRole, id: i32, x: f64, y: f64) -> bool {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.touch_point.is_none() => ui,
+            _ => return false,
+        };
+        ui.touch_point = Some(id);
+        ui.touch_origin = Some(Point::new(x as _, y as _));
+        ui.touch_held = false;
+        ui.ptr_active = true;
+        ui.cursor_position = Point::new(x as _, y as _);
+        ui.queue.push(iced_native::Event::Mouse(mouse::Event::CursorMoved {
+            position: Point { x: x as _, y: y as _ },
+        }));
+        self.surface.on_touch_enter().await;
+        true
+    }
+
+    pub(crate) fn touch_motion(&mut self, role: SurfaceRole, id: i32, x: f64, y: f64) {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.touch_point == Some(id) => ui,
+            _ => return,
+        };
+        ui.cursor_position = Point::new(x as _, y as _);
+        if let Some(origin) = ui.touch_origin {
+            if origin.distance(ui.cursor_position) > LONG_PRESS_SLOP {
+                ui.touch_origin = None;
+            }
+        }
+        ui.queue.push(iced_native::Event::Mouse(mouse::Event::CursorMoved {
+            position: Point { x: x as _, y: y as _ },
+        }));
+    }
+
+    pub(crate) fn touch_up(&mut self, role: SurfaceRole, id: i32) {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.touch_point == Some(id) => ui,
+            _ => return,
+        };
+        ui.touch_point = None;
+        ui.touch_origin = None;
+        if !ui.touch_held {
+            ui.queue.push(iced_native::Event::Mouse(mouse::Event::ButtonPressed(
+                mouse::Button::Left,
+            )));
+            ui.queue.push(iced_native::Event::Mouse(mouse::Event::ButtonReleased(
+                mouse::Button::Left,
+            )));
+        }
+        self.touch_leave = true;
+    }
+
+    /// Turns the tracked point into a long press if it's still where it went down, once it has been held long enough.
+    /// Returns true if it did (and a render is needed).
+    pub(crate) fn touch_hold(&mut self, role: SurfaceRole) -> bool {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.touch_point.is_some() && ui.touch_origin.is_some() && !ui.touch_held => ui,
+            _ => return false,
+        };
+        ui.touch_held = true;
+        ui.queue.push(iced_native::Event::Mouse(mouse::Event::ButtonPressed(
+            LONG_PRESS_BUTTON,
+        )));
+        ui.queue.push(iced_native::Event::Mouse(mouse::Event::ButtonReleased(
+            LONG_PRESS_BUTTON,
+        )));
+        true
+    }
+
+    /// Forgets the tracked point without a click, when the compositor or the device takes it away
+    pub(crate) fn touch_cancel(&mut self, role: SurfaceRole) {
+        if let Some(ui) = self.ui_mut(role) {
+            ui.touch_origin = None;
+            if ui.touch_point.take().is_some() {
+                self.touch_leave = true;
+            }
+        }
+    }
+
+    /// Delivers the touch leave after the frame containing the up was rendered.
+    /// Returns true if there was one (and another render is needed).
+    pub(crate) async fn touch_frame_done(&mut self) -> bool {
+        if !self.touch_leave {
+            return false;
+        }
+        self.surface.on_touch_leave().await;
+        self.touch_leave = false;
+        true
+    }
+
+    pub(crate) fn keyboard_enter(&mut self, role: SurfaceRole) {
+        if let Some(ui) = self.ui_mut(role) {
+            ui.kb_active = true;
+        }
+    }
+
+    pub(crate) fn keyboard_leave(&mut self, role: SurfaceRole) {
+        if let Some(ui) = self.ui_mut(role) {
+            ui.kb_active = false;
+        }
+    }
+
+    pub(crate) fn keyboard_modifiers(&mut self, role: SurfaceRole, modifiers: keyboard::Modifiers) {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.kb_active => ui,
+            _ => return,
+        };
+        ui.keyboard_mods = modifiers;
+        ui.queue
+            .push(iced_native::Event::Keyboard(keyboard::Event::ModifiersChanged(
+                ui.keyboard_mods,
+            )));
+    }
+
+    /// Queues a key event, returns false if it was ignored (no keyboard focus)
+    pub(crate) fn keyboard_key(&mut self, role: SurfaceRole, keysym: u32, pressed: bool, utf8: Option<String>) -> bool {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.kb_active => ui,
+            _ => return false,
+        };
+        if let Some(key_code) = convert_key(keysym) {
+            ui.queue.push(iced_native::Event::Keyboard(if pressed {
+                keyboard::Event::KeyPressed {
+                    key_code,
+                    modifiers: ui.keyboard_mods,
+                }
+            } else {
+                keyboard::Event::KeyReleased {
+                    key_code,
+                    modifiers: ui.keyboard_mods,
+                }
+            }));
+        }
+        if !pressed {
+            return true;
+        }
+        if let Some(ustr) = utf8 {
+            // XXX: iced-winit filters out private use chars here
+            for c in ustr.chars() {
+                ui.queue
+                    .push(iced_native::Event::Keyboard(keyboard::Event::CharacterReceived(c)));
+            }
+        }
+        true
+    }
+
+    /// Queues what an input method did to the focused field: deleting characters around the cursor
+    /// (as Backspace and Delete presses, the only way to do it with iced widgets), then typing the commit string
+    pub(crate) fn ime_input(
+        &mut self,
+        role: SurfaceRole,
+        delete_before: usize,
+        delete_after: usize,
+        commit: Option<String>,
+    ) -> bool {
+        let ui = match self.ui_mut(role) {
+            Some(ui) if ui.kb_active => ui,
+            _ => return false,
+        };
+        let deletes = std::iter::repeat(keyboard::KeyCode::Backspace)
+            .take(delete_before)
+            .chain(std::iter::repeat(keyboard::KeyCode::Delete).take(delete_after));
+        for key_code in deletes {
+            let modifiers = keyboard::Modifiers::default();
+            ui.queue.push(iced_native::Event::Keyboard(keyboard::Event::KeyPressed {
+                key_code,
+                modifiers,
+            }));
+            ui.queue
+                .push(iced_native::Event::Keyboard(keyboard::Event::KeyReleased {
+                    key_code,
+                    modifiers,
+                }));
+        }
+        for c in commit.iter().flat_map(|s| s.chars()).filter(|c| !c.is_control()) {
+            ui.queue
+                .push(iced_native::Event::Keyboard(keyboard::Event::CharacterReceived(c)));
+        }
+        true
+    }
+
+    /// Queues the message for a completed paste, returns false if the surface ignored it
+    pub(crate) fn paste(&mut self, paste: Paste) -> bool {
+        if let Some(message) = self.surface.on_paste(paste) {
+            self.messages.push(message);
+            return true;
+        }
+        false
+    }
+}
+
+/// The popup surface of an instance and where it renders to
+struct PopupInstance {
+    popup: Popup,
+    target: render::Target,
+    /// What it was created for, as returned by `IcedUi::wanted_popup`
+    wanted: (PopupAnchor, Size),
+    configured: bool,
+    last_mouse_interaction: mouse::Interaction,
+}
+
+/// A held key, sent again until it's released
+struct KeyRepeat {
+    seat: wl_seat::WlSeat,
+    keysym: u32,
+    utf8: Option<String>,
+    timeout: future::Fuse<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>,
+}
+
+pub struct IcedInstance<T: IcedSurface> {
+    parent: DesktopInstance,
+    ui: IcedUi<T>,
+
+    // wayland state
+    leave_timeout: Option<future::Fuse<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>>,
+    /// Started by a touch down on the surface, cancelled by its up
+    long_press_timeout: Option<(
+        SurfaceRole,
+        future::Fuse<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>,
+    )>,
+    key_repeat: Option<KeyRepeat>,
+    prev_input_region: Option<Vec<Rectangle<u32>>>,
+    prev_primary_selection: Option<String>,
+    seats: Vec<SeatInput>,
+    seat_tx: SeatEventSender,
+    seat_events: mpsc::UnboundedReceiver<(wl_seat::WlSeat, SeatEvent)>,
+    seat_changes: mpsc::UnboundedReceiver<(Attached<wl_seat::WlSeat>, Option<SeatCaps>)>,
+    _seat_listener: seat::SeatListener,
+    /// The latest click, key press or touch, which popups can grab with
+    input_serial: Option<(wl_seat::WlSeat, u32)>,
+
+    target: render::Target,
     last_mouse_interaction: mouse::Interaction,
     clipboard: Clipboard,
+
+    popup: Option<PopupInstance>,
+    dismissed_popup: Option<PopupAnchor>,
+
+    /// Done when the compositor is ready for the next frame, renders wait for it
+    frame_callback: Option<oneshot::Receiver<wl_callback::Event>>,
+    render_pending: bool,
 }
 
 impl<T: DesktopSurface + IcedSurface> IcedInstance<T> {
@@ -162,86 +698,140 @@ impl<T: DesktopSurface + IcedSurface> IcedInstance<T> {
         display: Display,
         output: wl_output::WlOutput,
     ) -> IcedInstance<T> {
-        let parent = DesktopInstance::new(&surface, env.clone(), display, &output);
-        let rwh = parent.raw_handle();
+        let parent = DesktopInstance::new(&surface, env, display, &output);
+        IcedInstance::with_parent(surface, parent).await
+    }
 
-        let mut compositor = WgpuCompositor::request(
-            iced_wgpu::Settings {
-                ..iced_wgpu::Settings::default()
-            },
-            Some(&rwh),
-        )
-        .await
-        .unwrap();
-        let renderer = iced_wgpu::Renderer::new(compositor.create_backend());
-        let gpu_surface = compositor.create_surface(&rwh);
-        parent.wl_surface.commit();
-        parent.flush();
+    /// An instance covering the output while the session is locked
+    pub async fn new_locked(
+        surface: T,
+        lock: &SessionLock,
+        env: Environment<Env>,
+        display: Display,
+        output: wl_output::WlOutput,
+    ) -> IcedInstance<T> {
+        let parent = DesktopInstance::new_locked(lock, env, display, &output);
+        IcedInstance::with_parent(surface, parent).await
+    }
 
-        let seat = &parent.env.get_all_seats()[0];
-        let (keyboard_events, keyboard_handle) = if with_seat_data(seat, |d| d.has_keyboard).unwrap() {
-            let (hdl, evs) = wayland_keyboard_chan(&seat);
-            (evs, Some(hdl))
-        } else {
-            (futures::channel::mpsc::unbounded().1, None)
-        };
-        let (ptr, themed_ptr) = if with_seat_data(seat, |d| d.has_pointer).unwrap() {
-            (
-                Some(AsyncMain::new(seat.get_pointer(), Some(|p| p.release()))),
-                Some(parent.theme_mgr.theme_pointer(seat.get_pointer().detach())),
-            )
-        } else {
-            (None, None)
-        };
-        let touch = if with_seat_data(seat, |d| d.has_touch).unwrap() {
-            Some(AsyncMain::new(seat.get_touch(), Some(|p| p.release())))
-        } else {
-            None
-        };
+    async fn with_parent(mut surface: T, parent: DesktopInstance) -> IcedInstance<T> {
+        surface.attach_clipboard(&parent.clipboard);
+        let (target, renderer) = render::Target::new(render::RendererKind::from_env(), &parent).await;
+        // layer surfaces are configured in response to the first commit,
+        // lock surfaces are configured right away and must not be committed before that
+        if let ShellSurface::Layer(_) = parent.shell_surface {
+            parent.wl_surface.commit();
+        }
+        parent.flush();
 
-        let (paste_inject_tx, paste_inject_rx) = futures::channel::mpsc::unbounded();
+        let (seat_tx, seat_events) = mpsc::unbounded();
+        let seats = parent
+            .env
+            .get_all_seats()
+            .iter()
+            .map(|seat| {
+                SeatInput::new(
+                    seat,
+                    &parent.theme_mgr,
+                    parent
+                        .env
+                        .get_global::<text_input_manager::ZwpTextInputManagerV3>()
+                        .as_ref(),
+                    &seat_tx,
+                )
+            })
+            .collect();
+        let (changes_tx, seat_changes) = mpsc::unbounded();
+        let _seat_listener = parent.env.listen_for_seats(move |seat, data, _| {
+            let caps = if data.defunct {
+                None
+            } else {
+                Some(SeatCaps::from_data(data))
+            };
+            let _ = changes_tx.unbounded_send((seat, caps));
+        });
 
         IcedInstance {
-            parent,
-            surface,
-            ptr_active: false,
-            kb_active: false,
-            scale: 1,
+            ui: IcedUi::new(surface, renderer),
             leave_timeout: None,
+            long_press_timeout: None,
+            key_repeat: None,
             prev_input_region: None,
-            touch_point: None,
-            touch_leave: false,
-            themed_ptr,
-            last_ptr_serial: None,
-            keyboard_handle,
-            keyboard_events,
-            ptr,
-            touch,
-            cache: Cache::new(),
-            size: Size::new(0.0, 0.0),
-            cursor_position: Point::default(),
-            keyboard_mods: Default::default(),
-            compositor,
-            renderer,
-            gpu_surface,
-            swap_chain: None,
-            prev_prim: iced_graphics::Primitive::None,
-            queue: Vec::new(),
-            messages: Vec::new(),
+            prev_primary_selection: None,
+            seats,
+            seat_tx,
+            seat_events,
+            seat_changes,
+            _seat_listener,
+            input_serial: None,
+            target,
             last_mouse_interaction: mouse::Interaction::Idle,
-            clipboard: Clipboard {
-                env,
-                seat: seat.detach(),
-                last_enter_serial: 0,
-                received: Arc::new(RefCell::new(None)),
-                paste_inject_tx: Arc::new(paste_inject_tx),
-            },
-            paste_inject_rx,
+            clipboard: Clipboard(parent.clipboard.clone()),
+            popup: None,
+            dismissed_popup: None,
+            frame_callback: None,
+            render_pending: false,
+            parent,
+        }
+    }
+
+    /// The main surface, e.g. for attaching an `IdleInhibitor` to
+    pub fn wl_surface(&self) -> &Attached<wl_surface::WlSurface> {
+        &self.parent.wl_surface
+    }
+
+    fn role_of(&self, surface: &wl_surface::WlSurface) -> Option<SurfaceRole> {
+        if self.parent.wl_surface.detach() == *surface {
+            return Some(SurfaceRole::Main);
+        }
+        match self.popup {
+            Some(ref p) if p.popup.wl_surface.detach() == *surface => Some(SurfaceRole::Popup),
+            _ => None,
+        }
+    }
+
+    /// Whether any seat's pointer, keyboard and touch are on the given surface
+    fn focus(&self, role: SurfaceRole) -> (bool, bool, bool) {
+        (
+            self.seats.iter().any(|s| s.ptr_inside == Some(role)),
+            self.seats.iter().any(|s| s.kb_focused == Some(role)),
+            self.seats.iter().any(|s| s.touching == Some(role)),
+        )
+    }
+
+    /// Starts the debounced leave once no pointer is on any of the surfaces
+    fn start_leave_timeout(&mut self) {
+        if !self.seats.iter().any(|s| s.ptr_inside.is_some()) {
+            self.leave_timeout = Some(glib::timeout_future(Duration::from_millis(200)).fuse());
+        }
+    }
+
+    /// Schedules the first repeat of a just pressed key, replacing any other repeating key
+    fn start_key_repeat(&mut self, i: usize, keysym: u32, utf8: Option<String>) {
+        let info = self.seats[i].repeat_info;
+        self.key_repeat = if info.rate > 0 {
+            Some(KeyRepeat {
+                seat: self.seats[i].seat.detach(),
+                keysym,
+                utf8,
+                timeout: glib::timeout_future(info.delay).fuse(),
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Drops the repeat if its seat no longer has keyboard focus
+    fn check_key_repeat(&mut self) {
+        if let Some(ref rep) = self.key_repeat {
+            if !self.seats.iter().any(|s| s.is(&rep.seat) && s.kb_focused.is_some()) {
+                self.key_repeat = None;
+            }
         }
     }
 
     fn update_input_region(&mut self) {
-        let reg = self.surface.input_region(self.size.width as _, self.size.height as _);
+        let reg = self.ui.input_region();
         if reg != self.prev_input_region {
             if let Some(ref rects) = reg {
                 let wlreg = self.parent.create_region();
@@ -263,332 +853,539 @@ impl<T: DesktopSurface + IcedSurface> IcedInstance<T> {
         self.prev_input_region = reg;
     }
 
-    fn apply_mouse_interaction(&mut self, interaction: mouse::Interaction) {
-        if let Some(ref tptr) = self.themed_ptr {
-            use iced_native::mouse::Interaction::*;
-            let _ = tptr.set_cursor(
-                match interaction {
-                    Idle => "default",
-                    Pointer => "pointer",
-                    Grab => "dnd-ask",
-                    Text => "text",
-                    Crosshair => "cross",
-                    Working => "wait",
-                    Grabbing => "dnd-move",
-                    ResizingHorizontally => "col-resize",
-                    ResizingVertically => "row-resize",
-                },
-                self.last_ptr_serial,
-            );
-            self.last_mouse_interaction = interaction;
+    /// Moves or reconfigures the layer surface when the surface wants different properties
+    fn update_layer_props(&mut self) {
+        let props = self.ui.surface.layer_props();
+        if self.parent.set_layer_props(props) {
+            self.parent.wl_surface.commit();
         }
     }
 
-    async fn render(&mut self) {
-        if self.swap_chain.is_none() {
-            eprintln!("WARN: render attempted without swapchain");
-            return;
+    /// Claims the primary selection when the surface selects something new.
+    /// Deselecting doesn't clear it, same as everywhere else.
+    fn update_primary_selection(&mut self) {
+        let sel = self.ui.surface.primary_selection();
+        if sel != self.prev_primary_selection {
+            if let Some(ref text) = sel {
+                self.parent.clipboard.write_text(Selection::Primary, text.clone());
+            }
+            self.prev_primary_selection = sel;
         }
+    }
 
-        for h in self.surface.retained_images() {
-            match h {
-                ImageHandle::Raster(h) => self.renderer.backend_mut().retain_raster(&h),
-                ImageHandle::Vector(h) => self.renderer.backend_mut().retain_vector(&h),
+    /// Enables, updates or disables the input methods of the seats focused on us to match the text field
+    fn update_text_input(&mut self) {
+        let field = self.ui.surface.text_field();
+        for seat in self.seats.iter_mut().filter(|s| s.ime_focused.is_some()) {
+            if let Some(ref mut text_input) = seat.text_input {
+                text_input.update(field.as_ref());
             }
         }
+        self.parent.flush();
+    }
 
-        let swap_chain = self.swap_chain.as_mut().unwrap();
-
-        let mut user_interface =
-            UserInterface::build(self.surface.view(), self.size, self.cache.clone(), &mut self.renderer);
-        user_interface.update(
-            &self.queue.drain(..).collect::<Vec<_>>(),
-            self.cursor_position,
-            &mut self.renderer,
-            &mut self.clipboard,
-            &mut self.messages,
-        );
-        let viewport = iced_graphics::Viewport::with_physical_size(
-            iced_graphics::Size::new(
-                self.size.width as u32 * self.scale as u32,
-                self.size.height as u32 * self.scale as u32,
-            ),
-            self.scale as _,
-        );
-
-        if self.messages.is_empty() {
-            let (primitive, mi) = user_interface.draw(&mut self.renderer, self.cursor_position);
-            let dmg = self.prev_prim.damage(&primitive);
-            self.prev_prim = primitive.clone();
-            if dmg == None || dmg.map(|x| x.len()).unwrap_or(0) == 0 {
-                self.cache = user_interface.into_cache();
-                self.update_input_region();
-                return;
+    /// Opens, replaces or closes the popup to match what the surface wants now
+    fn update_popup(&mut self) {
+        let wanted = match (self.ui.wanted_popup(), self.dismissed_popup) {
+            (Some((anchor, _)), Some(dismissed)) if anchor == dismissed => None,
+            (wanted, _) => {
+                self.dismissed_popup = None;
+                wanted
             }
-            let inter = self.compositor.draw::<String>(
-                &mut self.renderer,
-                swap_chain,
-                &viewport,
-                iced_core::Color::TRANSPARENT,
-                &(primitive, mi),
-                &[],
-            );
-            self.cache = user_interface.into_cache();
-            self.apply_mouse_interaction(inter);
+        };
+        if wanted == self.popup.as_ref().map(|p| p.wanted) {
+            return;
+        }
+        self.close_popup();
+        let (anchor, size) = match wanted {
+            Some(wanted) => wanted,
+            None => return,
+        };
+        let grab = if anchor.grab {
+            self.input_serial.as_ref().map(|(seat, serial)| (seat, *serial))
         } else {
-            // iced-winit says we are forced to rebuild twice
-            let temp_cache = user_interface.into_cache();
+            None
+        };
+        match Popup::new(&self.parent, anchor, size.width as _, size.height as _, grab) {
+            Some(popup) => {
+                let target = self
+                    .target
+                    .for_surface(&self.parent.env, &self.parent.display, &popup.wl_surface);
+                self.ui.open_popup(size);
+                self.popup = Some(PopupInstance {
+                    popup,
+                    target,
+                    wanted: (anchor, size),
+                    configured: false,
+                    last_mouse_interaction: mouse::Interaction::Idle,
+                });
+            }
+            // don't retry on every render
+            None => self.dismissed_popup = Some(anchor),
+        }
+        self.parent.flush();
+    }
 
-            for message in self.messages.drain(..) {
-                self.surface.update(message).await;
+    fn close_popup(&mut self) {
+        if self.popup.take().is_none() {
+            return;
+        }
+        self.ui.close_popup();
+        let had_ptr = self.focus(SurfaceRole::Popup).0;
+        for seat in self.seats.iter_mut() {
+            if seat.ptr_inside == Some(SurfaceRole::Popup) {
+                seat.ptr_inside = None;
             }
-            self.parent.flush();
+            if seat.kb_focused == Some(SurfaceRole::Popup) {
+                seat.kb_focused = None;
+            }
+            if seat.touching == Some(SurfaceRole::Popup) {
+                seat.touching = None;
+            }
+        }
+        if had_ptr {
+            self.start_leave_timeout();
+        }
+        self.parent.flush();
+    }
 
-            let mut user_interface =
-                UserInterface::build(self.surface.view(), self.size, temp_cache, &mut self.renderer);
-            let (primitive, mi) = user_interface.draw(&mut self.renderer, self.cursor_position);
-            let dmg = self.prev_prim.damage(&primitive);
-            self.prev_prim = primitive.clone();
-            if dmg == None || dmg.map(|x| x.len()).unwrap_or(0) == 0 {
-                self.cache = user_interface.into_cache();
-                return;
+    fn apply_mouse_interaction(&mut self, role: SurfaceRole, interaction: mouse::Interaction) {
+        use iced_native::mouse::Interaction::*;
+        let name = match interaction {
+            Idle => "default",
+            Pointer => "pointer",
+            Grab => "dnd-ask",
+            Text => "text",
+            Crosshair => "cross",
+            Working => "wait",
+            Grabbing => "dnd-move",
+            ResizingHorizontally => "col-resize",
+            ResizingVertically => "row-resize",
+        };
+        for seat in self.seats.iter().filter(|s| s.ptr_inside == Some(role)) {
+            if let Some(ref tptr) = seat.themed_ptr {
+                let _ = tptr.set_cursor(name, seat.last_ptr_serial);
             }
-            let inter = self.compositor.draw::<String>(
-                &mut self.renderer,
-                swap_chain,
-                &viewport,
-                iced_core::Color::TRANSPARENT,
-                &(primitive, mi),
-                &[],
-            );
-            self.cache = user_interface.into_cache();
-            self.apply_mouse_interaction(inter);
+        }
+        match role {
+            SurfaceRole::Main => self.last_mouse_interaction = interaction,
+            SurfaceRole::Popup => {
+                if let Some(ref mut popup) = self.popup {
+                    popup.last_mouse_interaction = interaction;
+                }
+            }
+        }
+    }
+
+    fn last_mouse_interaction(&self, role: SurfaceRole) -> mouse::Interaction {
+        match role {
+            SurfaceRole::Main => self.last_mouse_interaction,
+            SurfaceRole::Popup => self
+                .popup
+                .as_ref()
+                .map(|p| p.last_mouse_interaction)
+                .unwrap_or(mouse::Interaction::Idle),
+        }
+    }
+
+    /// Renders both surfaces on the next frame, or right away if the compositor isn't busy showing the last one
+    async fn render(&mut self) {
+        if self.frame_callback.is_some() {
+            self.render_pending = true;
+            return;
+        }
+        self.render_now().await;
+    }
+
+    /// Renders both surfaces, since messages from either can change what the other shows
+    async fn render_now(&mut self) {
+        if !self.target.is_ready() {
+            eprintln!("WARN: render attempted without swapchain");
+            return;
+        }
+
+        self.ui.tick(Instant::now());
+        let output = self.ui.draw(SurfaceRole::Main, &mut self.clipboard).await;
+        let animating = self.ui.surface.is_animating();
+        // only ask for a frame when something will be committed, otherwise it never comes
+        if (output.is_some() || animating) && self.frame_callback.is_none() {
+            self.frame_callback = Some(wayland_event_chan_oneshot(&self.parent.wl_surface.frame()));
+        }
+        self.render_pending = animating;
+        self.parent.flush();
+        if let Some(output) = output {
+            let viewport = self.ui.main.viewport();
+            let damage = self.ui.main.take_buffer_damage();
+            let inter = self.target.draw(&mut self.ui.renderer, &viewport, &output, &damage);
+            self.apply_mouse_interaction(SurfaceRole::Main, inter);
+        } else if animating {
+            // nothing changed this time, but the frame callback has to be committed
+            self.parent.wl_surface.commit();
+            self.parent.flush();
         }
         self.update_input_region();
+        self.update_layer_props();
+        self.update_primary_selection();
+        self.update_text_input();
+        self.update_popup();
+        self.render_popup().await;
+    }
+
+    async fn render_popup(&mut self) {
+        match self.popup {
+            Some(ref popup) if popup.configured && popup.target.is_ready() => (),
+            _ => return,
+        }
+        let output = self.ui.draw(SurfaceRole::Popup, &mut self.clipboard).await;
+        self.parent.flush();
+        if let (Some(output), Some(popup), Some(ui)) = (output, self.popup.as_mut(), self.ui.popup.as_mut()) {
+            let viewport = ui.viewport();
+            let damage = ui.take_buffer_damage();
+            let inter = popup.target.draw(&mut self.ui.renderer, &viewport, &output, &damage);
+            self.apply_mouse_interaction(SurfaceRole::Popup, inter);
+        }
     }
 
     fn create_swap_chain(&mut self) {
-        self.swap_chain = Some(self.compositor.create_swap_chain(
-            &self.gpu_surface,
-            self.size.width as u32 * self.scale as u32,
-            self.size.height as u32 * self.scale as u32,
-        ));
-        self.parent.wl_surface.set_buffer_scale(self.scale);
-        self.prev_prim = iced_graphics::Primitive::None; // force damage
+        let (width, height) = self.ui.main.physical_size();
+        self.target.resize(width, height);
+        self.parent.set_scale(
+            self.ui.main.scale,
+            self.ui.main.size.width as _,
+            self.ui.main.size.height as _,
+        );
+        self.ui.main.force_damage();
+        self.create_popup_swap_chain();
+    }
+
+    fn create_popup_swap_chain(&mut self) {
+        if let (Some(popup), Some(ui)) = (self.popup.as_mut(), self.ui.popup.as_mut()) {
+            if !popup.configured {
+                return;
+            }
+            ui.scale = self.ui.main.scale;
+            let (width, height) = ui.physical_size();
+            popup.target.resize(width, height);
+            popup.popup.set_scale(ui.scale, ui.size.width as _, ui.size.height as _);
+            ui.force_damage();
+        }
     }
 
-    async fn on_scale(&mut self, scale: i32) {
-        if scale == self.scale {
+    async fn on_scale(&mut self, scale: f64) {
+        if scale == self.ui.main.scale {
             return;
         }
-        self.scale = scale;
+        self.ui.main.scale = scale;
         self.create_swap_chain();
         self.render().await;
     }
 
-    async fn on_layer_event(&mut self, event: layer_surface::Event) -> bool {
+    async fn on_shell_event(&mut self, event: ShellEvent) -> bool {
         match event {
-            layer_surface::Event::Configure { serial, width, height } => {
-                self.parent.layer_surface.ack_configure(serial);
+            ShellEvent::Configure { serial, width, height } => {
+                self.parent.shell_surface.ack_configure(serial);
 
-                self.scale = get_surface_scale_factor(&self.parent.wl_surface);
-                self.size = Size::new(width as f32, height as f32);
+                if !self.parent.is_fractional() {
+                    self.ui.main.scale = self.parent.integer_scale() as f64;
+                }
+                self.ui.main.size = Size::new(width as f32, height as f32);
                 self.create_swap_chain();
-                self.render().await;
+                // the compositor waits for a buffer of the new size
+                self.render_now().await;
                 true
             }
-            layer_surface::Event::Closed { .. } => false,
-            _ => {
-                eprintln!("unknown lsh event");
+            ShellEvent::Closed => false,
+            ShellEvent::Unknown => {
+                eprintln!("unknown shell surface event");
                 true
             }
         }
     }
 
-    async fn on_keyboard_event(&mut self, event: seat::keyboard::Event) {
+    async fn on_popup_event(&mut self, event: PopupEvent) {
         match event {
-            seat::keyboard::Event::Enter { surface, serial, .. } => {
-                if self.parent.wl_surface.detach() != surface {
-                    return;
+            PopupEvent::Configured { width, height } => {
+                if let (Some(popup), Some(ui)) = (self.popup.as_mut(), self.ui.popup.as_mut()) {
+                    popup.configured = true;
+                    ui.size = Size::new(width as f32, height as f32);
                 }
-                self.kb_active = true;
-                self.clipboard.last_enter_serial = serial;
+                self.create_popup_swap_chain();
+                self.render_popup().await;
             }
-            seat::keyboard::Event::Leave { surface, .. } => {
-                if self.parent.wl_surface.detach() != surface {
-                    return;
-                }
-                self.kb_active = true;
+            PopupEvent::Done => {
+                self.dismissed_popup = self.popup.as_ref().map(|p| p.wanted.0);
+                self.close_popup();
+                self.ui.surface.on_popup_done().await;
+                self.render().await;
             }
-            seat::keyboard::Event::Modifiers { modifiers, .. } => {
-                if !self.kb_active {
-                    return;
-                }
-                self.keyboard_mods = keyboard::Modifiers {
-                    shift: modifiers.shift,
-                    control: modifiers.ctrl,
-                    alt: modifiers.alt,
-                    logo: modifiers.logo,
+        }
+    }
+
+    async fn on_paste(&mut self, paste: Paste) {
+        if self.ui.paste(paste) {
+            self.render().await;
+        }
+    }
+
+    /// Handles seats appearing, disappearing, or getting devices plugged in or out
+    async fn on_seat_change(&mut self, seat: Attached<wl_seat::WlSeat>, caps: Option<SeatCaps>) {
+        let before: Vec<_> = ROLES.iter().map(|&role| self.focus(role)).collect();
+
+        match (self.seats.iter().position(|s| s.is(&seat.detach())), caps) {
+            (Some(i), Some(caps)) => self.seats[i].update_caps(caps, &self.parent.theme_mgr, &self.seat_tx),
+            (Some(i), None) => {
+                self.seats.remove(i);
+            }
+            (None, Some(_)) => self.seats.push(SeatInput::new(
+                &seat,
+                &self.parent.theme_mgr,
+                self.parent
+                    .env
+                    .get_global::<text_input_manager::ZwpTextInputManagerV3>()
+                    .as_ref(),
+                &self.seat_tx,
+            )),
+            (None, None) => (),
+        }
+        self.parent.flush();
+
+        for (&role, &(had_ptr, had_kb, had_touch)) in ROLES.iter().zip(before.iter()) {
+            let (has_ptr, has_kb, has_touch) = self.focus(role);
+            if had_touch && !has_touch {
+                self.ui.touch_cancel(role);
+            }
+            if had_ptr && !has_ptr {
+                self.ui.pointer_leave(role);
+            }
+            if had_kb && !has_kb {
+                self.ui.keyboard_leave(role);
+            }
+        }
+        if before.iter().any(|&(had_ptr, _, _)| had_ptr) {
+            self.start_leave_timeout();
+        }
+        self.check_key_repeat();
+        if self.ui.touch_frame_done().await {
+            self.render().await;
+        }
+    }
+
+    async fn on_seat_event(&mut self, seat: wl_seat::WlSeat, event: SeatEvent) {
+        let i = match self.seats.iter().position(|s| s.is(&seat)) {
+            Some(i) => i,
+            None => return, // events queued before the seat was removed
+        };
+        match event {
+            SeatEvent::Keyboard(ev) => self.on_keyboard_event(i, ev).await,
+            SeatEvent::Pointer(ev) => self.on_pointer_event(i, ev).await,
+            SeatEvent::Touch(ev) => self.on_touch_event(i, ev).await,
+            SeatEvent::TextInput(ev) => self.on_text_input_event(i, ev).await,
+        }
+    }
+
+    async fn on_text_input_event(&mut self, i: usize, event: ImeEvent) {
+        match event {
+            ImeEvent::Enter(surface) => {
+                let role = match self.role_of(&surface) {
+                    Some(role) => role,
+                    None => return,
                 };
-                self.queue
-                    .push(iced_native::Event::Keyboard(keyboard::Event::ModifiersChanged(
-                        self.keyboard_mods,
-                    )));
+                self.seats[i].ime_focused = Some(role);
+                self.update_text_input();
             }
-            seat::keyboard::Event::Key {
-                keysym, state, utf8, ..
-            } => {
-                if !self.kb_active {
-                    return;
+            ImeEvent::Leave(surface) => {
+                match self.role_of(&surface) {
+                    Some(role) if self.seats[i].ime_focused == Some(role) => (),
+                    _ => return,
                 }
-                if let Some(key_code) = convert_key(keysym) {
-                    self.queue.push(iced_native::Event::Keyboard(match state {
-                        seat::keyboard::KeyState::Pressed => keyboard::Event::KeyPressed {
-                            key_code,
-                            modifiers: self.keyboard_mods,
-                        },
-                        seat::keyboard::KeyState::Released => keyboard::Event::KeyReleased {
-                            key_code,
-                            modifiers: self.keyboard_mods,
-                        },
-                        _ => panic!("new button state?"),
-                    }));
+                self.seats[i].ime_focused = None;
+                if let Some(ref mut text_input) = self.seats[i].text_input {
+                    text_input.reset();
                 }
-                if state == seat::keyboard::KeyState::Released {
-                    self.render().await;
-                    return;
+                self.ui.surface.on_preedit(None).await;
+                self.render().await;
+            }
+            ImeEvent::Done {
+                delete_surrounding: (before, after),
+                commit,
+                preedit,
+            } => {
+                let role = match self.seats[i].ime_focused {
+                    Some(role) => role,
+                    None => return,
+                };
+                let (delete_before, delete_after) = self.seats[i]
+                    .text_input
+                    .as_ref()
+                    .and_then(|t| t.current())
+                    .map(|field| field.chars_around_cursor(before as _, after as _))
+                    .unwrap_or((0, 0));
+                self.ui.surface.set_input_seat(&self.seats[i].seat);
+                self.ui.ime_input(role, delete_before, delete_after, commit);
+                self.ui.surface.on_preedit(preedit).await;
+                self.render().await;
+            }
+        }
+    }
+
+    async fn on_keyboard_event(&mut self, i: usize, event: KeyboardEvent) {
+        match event {
+            KeyboardEvent::Enter { surface, serial, .. } => {
+                let role = match self.role_of(&surface) {
+                    Some(role) => role,
+                    None => return,
+                };
+                self.seats[i].kb_focused = Some(role);
+                self.ui.keyboard_enter(role);
+                self.parent.clipboard.set_serial(&self.seats[i].seat, serial);
+            }
+            KeyboardEvent::Leave { surface, .. } => {
+                let role = match self.role_of(&surface) {
+                    Some(role) if self.seats[i].kb_focused == Some(role) => role,
+                    _ => return,
+                };
+                self.seats[i].kb_focused = None;
+                self.check_key_repeat();
+                if !self.focus(role).1 {
+                    self.ui.keyboard_leave(role);
+                }
+            }
+            KeyboardEvent::Modifiers { modifiers } => {
+                if let Some(role) = self.seats[i].kb_focused {
+                    self.ui.keyboard_modifiers(role, convert_modifiers(modifiers));
                 }
-                if let Some(ustr) = utf8 {
-                    // XXX: iced-winit filters out private use chars here
-                    for c in ustr.chars() {
-                        self.queue
-                            .push(iced_native::Event::Keyboard(keyboard::Event::CharacterReceived(c)));
+            }
+            KeyboardEvent::Key {
+                keysym,
+                pressed,
+                utf8,
+                serial,
+                repeats,
+                ..
+            } => {
+                let role = match self.seats[i].kb_focused {
+                    Some(role) => role,
+                    None => return,
+                };
+                if pressed {
+                    self.input_serial = Some((self.seats[i].seat.detach(), serial));
+                    if repeats {
+                        self.start_key_repeat(i, keysym, utf8.clone());
+                    }
+                } else if let Some(ref rep) = self.key_repeat {
+                    if rep.keysym == keysym && self.seats[i].is(&rep.seat) {
+                        self.key_repeat = None;
                     }
                 }
-                self.render().await;
+                self.ui.surface.set_input_seat(&self.seats[i].seat);
+                if self.ui.keyboard_key(role, keysym, pressed, utf8) {
+                    self.render().await;
+                }
             }
-            _ => (),
+            KeyboardEvent::RepeatInfo(info) => self.seats[i].repeat_info = info,
         }
     }
 
-    async fn inject_paste(&mut self) {
-        if !self.kb_active {
-            return;
-        }
-        let modifiers = keyboard::Modifiers {
-            shift: false,
-            control: true,
-            alt: false,
-            logo: false,
+    async fn on_key_repeat(&mut self) {
+        self.check_key_repeat();
+        let (i, keysym, utf8) = match self.key_repeat {
+            Some(ref rep) => (
+                self.seats.iter().position(|s| s.is(&rep.seat)).unwrap(),
+                rep.keysym,
+                rep.utf8.clone(),
+            ),
+            None => return,
         };
-        self.queue
-            .push(iced_native::Event::Keyboard(keyboard::Event::ModifiersChanged(
-                modifiers,
-            )));
-        // release first because the actual user V might still be held
-        self.queue
-            .push(iced_native::Event::Keyboard(keyboard::Event::KeyReleased {
-                key_code: keyboard::KeyCode::V,
-                modifiers,
-            }));
-        self.queue
-            .push(iced_native::Event::Keyboard(keyboard::Event::KeyPressed {
-                key_code: keyboard::KeyCode::V,
-                modifiers,
-            }));
-        self.queue
-            .push(iced_native::Event::Keyboard(keyboard::Event::KeyReleased {
-                key_code: keyboard::KeyCode::V,
-                modifiers,
-            }));
-        self.queue
-            .push(iced_native::Event::Keyboard(keyboard::Event::ModifiersChanged(
-                self.keyboard_mods,
-            )));
-        self.render().await;
+        let role = self.seats[i].kb_focused.unwrap();
+        let interval = match self.seats[i].repeat_info.interval() {
+            Some(interval) => interval,
+            None => {
+                self.key_repeat = None;
+                return;
+            }
+        };
+        if let Some(ref mut rep) = self.key_repeat {
+            rep.timeout = glib::timeout_future(interval).fuse();
+        }
+        self.ui.surface.set_input_seat(&self.seats[i].seat);
+        if self.ui.keyboard_key(role, keysym, true, utf8) {
+            self.render().await;
+        }
     }
 
-    async fn on_pointer_event(&mut self, event: wl_pointer::Event) {
+    async fn on_pointer_event(&mut self, i: usize, event: wl_pointer::Event) {
         match event {
             wl_pointer::Event::Enter { surface, serial, .. } => {
-                if self.parent.wl_surface.detach() != surface {
-                    return;
-                }
-                self.ptr_active = true;
+                let role = match self.role_of(&surface) {
+                    Some(role) => role,
+                    None => return,
+                };
                 self.leave_timeout = None;
-                self.surface.on_pointer_enter().await;
-                self.last_ptr_serial = Some(serial);
-                self.clipboard.last_enter_serial = serial;
-                self.apply_mouse_interaction(self.last_mouse_interaction);
+                self.seats[i].ptr_inside = Some(role);
+                self.seats[i].last_ptr_serial = Some(serial);
+                self.ui.surface.set_input_seat(&self.seats[i].seat);
+                self.ui.pointer_enter(role).await;
+                self.parent.clipboard.set_serial(&self.seats[i].seat, serial);
+                self.apply_mouse_interaction(role, self.last_mouse_interaction(role));
             }
             wl_pointer::Event::Leave { surface, serial, .. } => {
-                if self.parent.wl_surface.detach() != surface {
-                    return;
+                let role = match self.role_of(&surface) {
+                    Some(role) if self.seats[i].ptr_inside == Some(role) => role,
+                    _ => return,
+                };
+                self.seats[i].ptr_inside = None;
+                self.seats[i].last_ptr_serial = Some(serial);
+                // other seats' pointers still count as hovering
+                if !self.focus(role).0 {
+                    self.ui.pointer_leave(role);
                 }
-                self.ptr_active = false;
-                self.leave_timeout = Some(glib::timeout_future(Duration::from_millis(200)).fuse());
-                self.last_ptr_serial = Some(serial);
+                self.start_leave_timeout();
             }
             wl_pointer::Event::Button {
                 button, state, serial, ..
             } => {
-                if !self.ptr_active {
-                    return;
-                }
-                let btn = match button {
-                    0x110 => mouse::Button::Left,
-                    0x111 => mouse::Button::Right,
-                    0x112 => mouse::Button::Middle,
-                    x if x > 0x110 => mouse::Button::Other((x - 0x110) as u8),
-                    _ => panic!("low button event code"),
+                let role = match self.seats[i].ptr_inside {
+                    Some(role) => role,
+                    None => return,
                 };
-                self.queue.push(iced_native::Event::Mouse(match state {
-                    wl_pointer::ButtonState::Pressed => mouse::Event::ButtonPressed(btn),
-                    wl_pointer::ButtonState::Released => mouse::Event::ButtonReleased(btn),
+                let pressed = match state {
+                    wl_pointer::ButtonState::Pressed => true,
+                    wl_pointer::ButtonState::Released => false,
                     _ => panic!("new button state?"),
-                }));
-                self.last_ptr_serial = Some(serial);
+                };
+                self.ui.surface.set_input_seat(&self.seats[i].seat);
+                self.ui.pointer_button(role, button, pressed);
+                self.seats[i].last_ptr_serial = Some(serial);
+                self.parent.clipboard.set_serial(&self.seats[i].seat, serial);
+                if pressed {
+                    self.input_serial = Some((self.seats[i].seat.detach(), serial));
+                }
+                // only into text fields, elsewhere middle clicks can mean something else (see AddEventListener)
+                if button == 0x112 && pressed && self.ui.surface.text_field().is_some() {
+                    self.parent.clipboard.request_text(Selection::Primary);
+                }
             }
             wl_pointer::Event::Motion {
                 surface_x, surface_y, ..
             } => {
-                if !self.ptr_active {
-                    return;
-                }
-                self.cursor_position = Point::new(surface_x as _, surface_y as _);
-                self.queue.push(iced_native::Event::Mouse(mouse::Event::CursorMoved {
-                    position: Point {
-                        x: surface_x as _,
-                        y: surface_y as _,
-                    },
-                }));
-                self.last_ptr_serial = None;
+                let role = match self.seats[i].ptr_inside {
+                    Some(role) => role,
+                    None => return,
+                };
+                self.ui.pointer_motion(role, surface_x, surface_y);
+                self.seats[i].last_ptr_serial = None;
             }
             wl_pointer::Event::Axis { axis, value, .. } => {
-                if !self.ptr_active {
-                    return;
+                if let Some(role) = self.seats[i].ptr_inside {
+                    self.ui.pointer_axis(role, axis, value)
                 }
-                self.queue.push(iced_native::Event::Mouse(mouse::Event::WheelScrolled {
-                    delta: mouse::ScrollDelta::Pixels {
-                        x: if axis == wl_pointer::Axis::HorizontalScroll {
-                            -value as _
-                        } else {
-                            0.0
-                        },
-                        y: if axis == wl_pointer::Axis::VerticalScroll {
-                            -value as _
-                        } else {
-                            0.0
-                        },
-                    },
-                }));
             }
             wl_pointer::Event::AxisSource { .. } => {}
             wl_pointer::Event::AxisStop { .. } => {}
             wl_pointer::Event::AxisDiscrete { .. } => {}
             wl_pointer::Event::Frame { .. } => {
                 self.render().await;
-                self.last_ptr_serial = None;
+                self.seats[i].last_ptr_serial = None;
             }
             _ => {
                 eprintln!("unhandled pointer event");
@@ -596,54 +1393,57 @@ impl<T: DesktopSurface + IcedSurface> IcedInstance<T> {
         }
     }
 
-    async fn on_touch_event(&mut self, event: wl_touch::Event) {
+    async fn on_touch_event(&mut self, i: usize, event: wl_touch::Event) {
         match event {
-            wl_touch::Event::Down { surface, id, x, y, .. } => {
-                if self.parent.wl_surface.detach() != surface {
-                    return;
-                }
-                if self.touch_point.is_some() {
-                    return;
+            wl_touch::Event::Down {
+                surface,
+                id,
+                x,
+                y,
+                serial,
+                ..
+            } => {
+                let role = match self.role_of(&surface) {
+                    Some(role) => role,
+                    None => return,
+                };
+                self.ui.surface.set_input_seat(&self.seats[i].seat);
+                if self.ui.touch_down(role, id, x, y).await {
+                    self.seats[i].touching = Some(role);
+                    self.input_serial = Some((self.seats[i].seat.detach(), serial));
+                    self.leave_timeout = None;
+                    self.long_press_timeout = Some((role, glib::timeout_future(LONG_PRESS_DURATION).fuse()));
                 }
-                self.touch_point = Some(id);
-                self.ptr_active = true;
-                self.leave_timeout = None;
-                self.cursor_position = Point::new(x as _, y as _);
-                self.queue.push(iced_native::Event::Mouse(mouse::Event::CursorMoved {
-                    position: Point { x: x as _, y: y as _ },
-                }));
-                self.surface.on_touch_enter().await;
             }
             wl_touch::Event::Motion { id, x, y, .. } => {
-                if self.touch_point != Some(id) {
-                    return;
+                if let Some(role) = self.seats[i].touching {
+                    self.ui.touch_motion(role, id, x, y)
                 }
-                self.cursor_position = Point::new(x as _, y as _);
-                self.queue.push(iced_native::Event::Mouse(mouse::Event::CursorMoved {
-                    position: Point { x: x as _, y: y as _ },
-                }));
             }
             wl_touch::Event::Up { id, .. } => {
-                if self.touch_point != Some(id) {
-                    return;
+                if let Some(role) = self.seats[i].touching {
+                    self.ui.touch_up(role, id);
+                    if self.ui.touch_point(role).is_none() {
+                        self.seats[i].touching = None;
+                        self.long_press_timeout = None;
+                    }
                 }
-                self.touch_point = None;
-                self.queue.push(iced_native::Event::Mouse(mouse::Event::ButtonPressed(
-                    mouse::Button::Left,
-                )));
-                self.queue.push(iced_native::Event::Mouse(mouse::Event::ButtonReleased(
-                    mouse::Button::Left,
-                )));
-                self.touch_leave = true;
             }
             wl_touch::Event::Frame { .. } => {
                 self.render().await;
-                if self.touch_leave {
-                    self.surface.on_touch_leave().await;
-                    self.touch_leave = false;
+                if self.ui.touch_frame_done().await {
                     self.render().await;
                 }
             }
+            wl_touch::Event::Cancel { .. } => {
+                if let Some(role) = self.seats[i].touching.take() {
+                    self.ui.touch_cancel(role);
+                    self.long_press_timeout = None;
+                    if self.ui.touch_frame_done().await {
+                        self.render().await;
+                    }
+                }
+            }
             e => eprintln!("{:?}", e),
         }
     }
@@ -652,18 +1452,32 @@ impl<T: DesktopSurface + IcedSurface> IcedInstance<T> {
 #[async_trait(?Send)]
 impl<T: DesktopSurface + IcedSurface> Runnable for IcedInstance<T> {
     async fn run(&mut self) -> bool {
-        // TODO: react to seat caps change
         let this = self; // argh macro weirdness
         let mut term = future::Fuse::terminated();
         let mut leave_timeout = this.leave_timeout.as_mut().unwrap_or_else(|| &mut term);
+        let mut term_long_press = future::Fuse::terminated();
+        let mut long_press_timeout = match this.long_press_timeout {
+            Some((_, ref mut timeout)) => timeout,
+            None => &mut term_long_press,
+        };
+        let mut term_repeat = future::Fuse::terminated();
+        let mut repeat_timeout = match this.key_repeat {
+            Some(ref mut rep) => &mut rep.timeout,
+            None => &mut term_repeat,
+        };
         futures::select! {
-            ev = this.parent.layer_surface.next() => if !this.on_layer_event(ev).await { return false },
-            ev = this.keyboard_events.select_next_some() => this.on_keyboard_event(ev).await,
-            ev = MaybeFuture::new(this.ptr.as_mut().map(|p| p.next())) => this.on_pointer_event(ev).await,
-            ev = MaybeFuture::new(this.touch.as_mut().map(|p| p.next())) => this.on_touch_event(ev).await,
-            sc = this.parent.scale_rx.select_next_some() => this.on_scale(sc).await,
-            () = this.paste_inject_rx.select_next_some() => this.inject_paste().await,
-            ac = this.surface.run().fuse() => match ac {
+            ev = this.parent.shell_surface.next_event() => if !this.on_shell_event(ev).await { return false },
+            ev = MaybeFuture::new(this.popup.as_mut().map(|p| p.popup.next().boxed_local().fuse())) =>
+                this.on_popup_event(ev).await,
+            (seat, ev) = this.seat_events.select_next_some() => this.on_seat_event(seat, ev).await,
+            (seat, caps) = this.seat_changes.select_next_some() => this.on_seat_change(seat, caps).await,
+            ev = this.parent.surface_events.select_next_some() => match ev {
+                SurfaceEvent::Scale(sc) => this.on_scale(sc).await,
+                // the layer surface stays on its output, nothing to do yet
+                SurfaceEvent::OutputEnter(_) | SurfaceEvent::OutputLeave(_) => (),
+            },
+            p = this.parent.paste_rx.select_next_some() => this.on_paste(p).await,
+            ac = this.ui.surface.run().fuse() => match ac {
                 Action::DoNothing => (),
                 Action::Rerender => {
                     this.parent.flush();
@@ -673,28 +1487,40 @@ impl<T: DesktopSurface + IcedSurface> Runnable for IcedInstance<T> {
             },
             () = leave_timeout => {
                 this.leave_timeout = None;
-                this.surface.on_pointer_leave().await;
+                this.ui.pointer_leave_settled().await;
                 // not getting a pointer frame after the timeout ;)
                 this.render().await;
             },
+            () = long_press_timeout => {
+                if let Some((role, _)) = this.long_press_timeout.take() {
+                    if this.ui.touch_hold(role) {
+                        this.render().await;
+                    }
+                }
+            },
+            () = repeat_timeout => this.on_key_repeat().await,
+            _ = MaybeFuture::new(this.frame_callback.as_mut()) => {
+                this.frame_callback = None;
+                if this.render_pending {
+                    this.render_now().await;
+                }
+            },
         }
         true
     }
 }
 
-impl<T: IcedSurface> Drop for IcedInstance<T> {
-    fn drop(&mut self) {
-        if let Some(tptr) = self.themed_ptr.take() {
-            tptr.release();
-        }
-        if let Some(kb) = self.keyboard_handle.take() {
-            kb.release();
-        }
+fn convert_modifiers(modifiers: keys::Modifiers) -> keyboard::Modifiers {
+    keyboard::Modifiers {
+        shift: modifiers.shift,
+        control: modifiers.ctrl,
+        alt: modifiers.alt,
+        logo: modifiers.logo,
     }
 }
 
 fn convert_key(keysym: u32) -> Option<keyboard::KeyCode> {
-    use seat::keyboard::keysyms as k;
+    use keys::keysyms as k;
     match keysym {
         k::XKB_KEY_0 => Some(keyboard::KeyCode::Key0),
         k::XKB_KEY_1 => Some(keyboard::KeyCode::Key1),
@@ -746,6 +1572,50 @@ fn convert_key(keysym: u32) -> Option<keyboard::KeyCode> {
         k::XKB_KEY_F10 => Some(keyboard::KeyCode::F10),
         k::XKB_KEY_F11 => Some(keyboard::KeyCode::F11),
         k::XKB_KEY_F12 => Some(keyboard::KeyCode::F12),
+        k::XKB_KEY_F13 => Some(keyboard::KeyCode::F13),
+        k::XKB_KEY_F14 => Some(keyboard::KeyCode::F14),
+        k::XKB_KEY_F15 => Some(keyboard::KeyCode::F15),
+        k::XKB_KEY_F16 => Some(keyboard::KeyCode::F16),
+        k::XKB_KEY_F17 => Some(keyboard::KeyCode::F17),
+        k::XKB_KEY_F18 => Some(keyboard::KeyCode::F18),
+        k::XKB_KEY_F19 => Some(keyboard::KeyCode::F19),
+        k::XKB_KEY_F20 => Some(keyboard::KeyCode::F20),
+        k::XKB_KEY_F21 => Some(keyboard::KeyCode::F21),
+        k::XKB_KEY_F22 => Some(keyboard::KeyCode::F22),
+        k::XKB_KEY_F23 => Some(keyboard::KeyCode::F23),
+        k::XKB_KEY_F24 => Some(keyboard::KeyCode::F24),
+
+        k::XKB_KEY_KP_0 => Some(keyboard::KeyCode::Numpad0),
+        k::XKB_KEY_KP_1 => Some(keyboard::KeyCode::Numpad1),
+        k::XKB_KEY_KP_2 => Some(keyboard::KeyCode::Numpad2),
+        k::XKB_KEY_KP_3 => Some(keyboard::KeyCode::Numpad3),
+        k::XKB_KEY_KP_4 => Some(keyboard::KeyCode::Numpad4),
+        k::XKB_KEY_KP_5 | k::XKB_KEY_KP_Begin => Some(keyboard::KeyCode::Numpad5),
+        k::XKB_KEY_KP_6 => Some(keyboard::KeyCode::Numpad6),
+        k::XKB_KEY_KP_7 => Some(keyboard::KeyCode::Numpad7),
+        k::XKB_KEY_KP_8 => Some(keyboard::KeyCode::Numpad8),
+        k::XKB_KEY_KP_9 => Some(keyboard::KeyCode::Numpad9),
+        // keypad without Num Lock
+        k::XKB_KEY_KP_Insert => Some(keyboard::KeyCode::Insert),
+        k::XKB_KEY_KP_End => Some(keyboard::KeyCode::End),
+        k::XKB_KEY_KP_Down => Some(keyboard::KeyCode::Down),
+        k::XKB_KEY_KP_Page_Down => Some(keyboard::KeyCode::PageDown),
+        k::XKB_KEY_KP_Left => Some(keyboard::KeyCode::Left),
+        k::XKB_KEY_KP_Right => Some(keyboard::KeyCode::Right),
+        k::XKB_KEY_KP_Home => Some(keyboard::KeyCode::Home),
+        k::XKB_KEY_KP_Up => Some(keyboard::KeyCode::Up),
+        k::XKB_KEY_KP_Page_Up => Some(keyboard::KeyCode::PageUp),
+        k::XKB_KEY_KP_Add => Some(keyboard::KeyCode::NumpadAdd),
+        k::XKB_KEY_KP_Subtract => Some(keyboard::KeyCode::NumpadSubtract),
+        k::XKB_KEY_KP_Multiply => Some(keyboard::KeyCode::NumpadMultiply),
+        k::XKB_KEY_KP_Divide => Some(keyboard::KeyCode::NumpadDivide),
+        k::XKB_KEY_KP_Decimal => Some(keyboard::KeyCode::NumpadDecimal),
+        k::XKB_KEY_KP_Delete => Some(keyboard::KeyCode::Delete),
+        k::XKB_KEY_KP_Separator => Some(keyboard::KeyCode::NumpadComma),
+        k::XKB_KEY_KP_Equal => Some(keyboard::KeyCode::NumpadEquals),
+        k::XKB_KEY_KP_Enter => Some(keyboard::KeyCode::NumpadEnter),
+        k::XKB_KEY_KP_Tab => Some(keyboard::KeyCode::Tab),
+        k::XKB_KEY_KP_Space => Some(keyboard::KeyCode::Space),
 
         k::XKB_KEY_space => Some(keyboard::KeyCode::Space),
         k::XKB_KEY_slash => Some(keyboard::KeyCode::Slash),
@@ -783,12 +1653,48 @@ fn convert_key(keysym: u32) -> Option<keyboard::KeyCode> {
         k::XKB_KEY_XF86Copy => Some(keyboard::KeyCode::Copy),
         k::XKB_KEY_XF86Cut => Some(keyboard::KeyCode::Cut),
         k::XKB_KEY_XF86Paste => Some(keyboard::KeyCode::Paste),
+        k::XKB_KEY_Print => Some(keyboard::KeyCode::Snapshot),
+        k::XKB_KEY_Sys_Req => Some(keyboard::KeyCode::Sysrq),
+        k::XKB_KEY_Scroll_Lock => Some(keyboard::KeyCode::Scroll),
+        k::XKB_KEY_Pause | k::XKB_KEY_Break => Some(keyboard::KeyCode::Pause),
+        k::XKB_KEY_Num_Lock => Some(keyboard::KeyCode::Numlock),
+        k::XKB_KEY_Caps_Lock => Some(keyboard::KeyCode::Capital),
+        k::XKB_KEY_Multi_key => Some(keyboard::KeyCode::Compose),
+        k::XKB_KEY_Menu => Some(keyboard::KeyCode::Apps),
+
+        k::XKB_KEY_XF86AudioPlay | k::XKB_KEY_XF86AudioPause => Some(keyboard::KeyCode::PlayPause),
+        k::XKB_KEY_XF86AudioStop => Some(keyboard::KeyCode::MediaStop),
+        k::XKB_KEY_XF86AudioNext => Some(keyboard::KeyCode::NextTrack),
+        k::XKB_KEY_XF86AudioPrev => Some(keyboard::KeyCode::PrevTrack),
+        k::XKB_KEY_XF86AudioMute => Some(keyboard::KeyCode::Mute),
+        k::XKB_KEY_XF86AudioLowerVolume => Some(keyboard::KeyCode::VolumeDown),
+        k::XKB_KEY_XF86AudioRaiseVolume => Some(keyboard::KeyCode::VolumeUp),
+        k::XKB_KEY_XF86AudioMedia => Some(keyboard::KeyCode::MediaSelect),
+        k::XKB_KEY_XF86Calculator => Some(keyboard::KeyCode::Calculator),
+        k::XKB_KEY_XF86Mail => Some(keyboard::KeyCode::Mail),
+        k::XKB_KEY_XF86MyComputer => Some(keyboard::KeyCode::MyComputer),
+        k::XKB_KEY_XF86Back => Some(keyboard::KeyCode::WebBack),
+        k::XKB_KEY_XF86Forward => Some(keyboard::KeyCode::WebForward),
+        k::XKB_KEY_XF86HomePage => Some(keyboard::KeyCode::WebHome),
+        k::XKB_KEY_XF86Refresh => Some(keyboard::KeyCode::WebRefresh),
+        k::XKB_KEY_XF86Search => Some(keyboard::KeyCode::WebSearch),
+        k::XKB_KEY_XF86Stop => Some(keyboard::KeyCode::WebStop),
+        k::XKB_KEY_XF86Favorites => Some(keyboard::KeyCode::WebFavorites),
+        k::XKB_KEY_XF86PowerOff => Some(keyboard::KeyCode::Power),
+        k::XKB_KEY_XF86Sleep => Some(keyboard::KeyCode::Sleep),
+        k::XKB_KEY_XF86WakeUp => Some(keyboard::KeyCode::Wake),
+
+        k::XKB_KEY_yen => Some(keyboard::KeyCode::Yen),
+        k::XKB_KEY_Henkan => Some(keyboard::KeyCode::Convert),
+        k::XKB_KEY_Muhenkan => Some(keyboard::KeyCode::NoConvert),
+        k::XKB_KEY_Kanji => Some(keyboard::KeyCode::Kanji),
+        k::XKB_KEY_Katakana | k::XKB_KEY_Hiragana_Katakana => Some(keyboard::KeyCode::Kana),
 
-        k::XKB_KEY_Alt_L => Some(keyboard::KeyCode::LAlt),
+        k::XKB_KEY_Alt_L | k::XKB_KEY_Meta_L => Some(keyboard::KeyCode::LAlt),
         k::XKB_KEY_Control_L => Some(keyboard::KeyCode::LControl),
         k::XKB_KEY_Shift_L => Some(keyboard::KeyCode::LShift),
         k::XKB_KEY_Super_L => Some(keyboard::KeyCode::LWin),
-        k::XKB_KEY_Alt_R => Some(keyboard::KeyCode::RAlt),
+        k::XKB_KEY_Alt_R | k::XKB_KEY_Meta_R | k::XKB_KEY_ISO_Level3_Shift => Some(keyboard::KeyCode::RAlt),
         k::XKB_KEY_Control_R => Some(keyboard::KeyCode::RControl),
         k::XKB_KEY_Shift_R => Some(keyboard::KeyCode::RShift),
         k::XKB_KEY_Super_R => Some(keyboard::KeyCode::RWin),
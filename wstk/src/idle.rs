@@ -0,0 +1,245 @@
+//! Noticing that the user is away (ext-idle-notify-v1), keeping the session awake while something
+//! worth watching is on (idle-inhibit) and powering outputs down (wlr-output-power-management).
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+pub use crate::protocols::ext_idle_notify_v1::client::{
+    ext_idle_notification_v1 as idle_notification, ext_idle_notifier_v1 as idle_notifier,
+};
+use crate::surfaces::*;
+pub use smithay_client_toolkit::reexports::protocols::{
+    unstable::idle_inhibit::v1::client::{
+        zwp_idle_inhibit_manager_v1 as idle_inhibit_manager, zwp_idle_inhibitor_v1 as idle_inhibitor,
+    },
+    wlr::unstable::output_power_management::v1::client::{
+        zwlr_output_power_manager_v1 as output_power_manager, zwlr_output_power_v1 as output_power,
+    },
+};
+
+pub struct IdleService {
+    env: Environment<Env>,
+    notifier: Attached<idle_notifier::ExtIdleNotifierV1>,
+}
+
+impl IdleService {
+    /// Watches all seats, including ones that appear later.
+    /// None if the compositor doesn't support idle notifications.
+    pub fn new(env: &Environment<Env>) -> Option<IdleService> {
+        Some(IdleService {
+            env: env.clone(),
+            notifier: env.get_global::<idle_notifier::ExtIdleNotifierV1>()?,
+        })
+    }
+
+    /// Becomes idle after every seat has had no activity for the given time
+    pub fn timeout(&self, timeout: Duration) -> IdleTimeout {
+        let seats = Rc::new(RefCell::new(Vec::<SeatIdle>::new()));
+        let changed = Rc::new(event_listener::Event::new());
+
+        let add_seat = {
+            let notifier = self.notifier.clone();
+            let seats = seats.clone();
+            let changed = changed.clone();
+            move |seat: &Attached<wl_seat::WlSeat>| {
+                let notification = notifier.get_idle_notification(timeout.as_millis() as u32, seat);
+                let weak_seats = Rc::downgrade(&seats);
+                let key = seat.detach();
+                let changed_ = changed.clone();
+                notification.quick_assign(move |_, event, _| {
+                    let seats = match weak_seats.upgrade() {
+                        Some(seats) => seats,
+                        None => return,
+                    };
+                    let idle = match event {
+                        idle_notification::Event::Idled => true,
+                        idle_notification::Event::Resumed => false,
+                        x => {
+                            eprintln!("WARN: unknown idle notification event {:?}", x);
+                            return;
+                        }
+                    };
+                    if let Some(s) = seats.borrow_mut().iter_mut().find(|s| s.seat == key) {
+                        s.idle = idle;
+                    }
+                    changed_.notify(usize::MAX);
+                });
+                seats.borrow_mut().push(SeatIdle {
+                    seat: seat.detach(),
+                    notification,
+                    idle: false,
+                });
+                changed.notify(usize::MAX);
+            }
+        };
+
+        for seat in self.env.get_all_seats() {
+            add_seat(&seat);
+        }
+
+        // also called for every capability change of existing seats, and for removed ones (defunct)
+        let _seat_listener = {
+            let seats = seats.clone();
+            let changed = changed.clone();
+            self.env.listen_for_seats(move |seat, data, _| {
+                let known = seats.borrow().iter().any(|s| s.seat == *seat);
+                if data.defunct && known {
+                    seats.borrow_mut().retain(|s| {
+                        if s.seat == *seat {
+                            s.notification.destroy();
+                        }
+                        s.seat != *seat
+                    });
+                    changed.notify(usize::MAX);
+                } else if !data.defunct && !known {
+                    add_seat(&seat);
+                }
+            })
+        };
+
+        IdleTimeout {
+            _seat_listener,
+            seats,
+            changed,
+        }
+    }
+}
+
+struct SeatIdle {
+    seat: wl_seat::WlSeat,
+    notification: Main<idle_notification::ExtIdleNotificationV1>,
+    idle: bool,
+}
+
+pub struct IdleTimeout {
+    _seat_listener: seat::SeatListener,
+    seats: Rc<RefCell<Vec<SeatIdle>>>,
+    changed: Rc<event_listener::Event>,
+}
+
+impl IdleTimeout {
+    /// Never without any seat, nobody could have been away from it
+    pub fn is_idle(&self) -> bool {
+        let seats = self.seats.borrow();
+        !seats.is_empty() && seats.iter().all(|s| s.idle)
+    }
+
+    /// Waits until the idle state differs from the one given, returns the new one
+    pub async fn changed(&self, from: bool) -> bool {
+        while self.is_idle() == from {
+            let listener = self.changed.listen();
+            if self.is_idle() != from {
+                break;
+            }
+            listener.await;
+        }
+        self.is_idle()
+    }
+}
+
+impl Drop for IdleTimeout {
+    fn drop(&mut self) {
+        for s in self.seats.borrow().iter() {
+            s.notification.destroy();
+        }
+    }
+}
+
+/// Keeps the session from going idle while it exists and the surface is visible.
+/// The compositor ignores it once the surface is destroyed, so it has to follow the surface.
+pub struct IdleInhibitor {
+    inhibitor: Main<idle_inhibitor::ZwpIdleInhibitorV1>,
+    surface: wl_surface::WlSurface,
+}
+
+impl IdleInhibitor {
+    /// None if the compositor doesn't support idle inhibition
+    pub fn new(env: &Environment<Env>, surface: &wl_surface::WlSurface) -> Option<IdleInhibitor> {
+        let manager = env.get_global::<idle_inhibit_manager::ZwpIdleInhibitManagerV1>()?;
+        Some(IdleInhibitor {
+            inhibitor: manager.create_inhibitor(surface),
+            surface: surface.clone(),
+        })
+    }
+
+    pub fn surface(&self) -> &wl_surface::WlSurface {
+        &self.surface
+    }
+}
+
+impl Drop for IdleInhibitor {
+    fn drop(&mut self) {
+        self.inhibitor.destroy();
+    }
+}
+
+fn power_off(
+    manager: &output_power_manager::ZwlrOutputPowerManagerV1,
+    output: &wl_output::WlOutput,
+) -> Main<output_power::ZwlrOutputPowerV1> {
+    let power = manager.get_output_power(output);
+    power.quick_assign(|power, event, _| match event {
+        output_power::Event::Mode { .. } => (),
+        // e.g. the output went away, or another client controls its power
+        output_power::Event::Failed => power.destroy(),
+        x => eprintln!("WARN: unknown output power event {:?}", x),
+    });
+    power.set_mode(output_power::Mode::Off);
+    power
+}
+
+/// Turns all outputs off while it exists (DPMS off), including ones connected meanwhile
+pub struct OutputsOff {
+    _osl: output::OutputStatusListener,
+    powers: Rc<RefCell<Vec<(wl_output::WlOutput, Main<output_power::ZwlrOutputPowerV1>)>>>,
+}
+
+impl OutputsOff {
+    /// None if the compositor doesn't support output power management
+    pub fn new(env: &Environment<Env>, display: Display) -> Option<OutputsOff> {
+        let manager = env.get_global::<output_power_manager::ZwlrOutputPowerManagerV1>()?;
+        let powers = Rc::new(RefCell::new(
+            env.get_all_outputs()
+                .into_iter()
+                .map(|output| {
+                    let power = power_off(&manager, &output);
+                    (output, power)
+                })
+                .collect::<Vec<_>>(),
+        ));
+
+        // also called for every info change of existing outputs, and for removed ones (obsolete)
+        let _osl = {
+            let powers = powers.clone();
+            env.listen_for_outputs(move |output, info, _| {
+                let mut powers = powers.borrow_mut();
+                if info.obsolete {
+                    powers.retain(|(o, power)| {
+                        if *o == output && power.as_ref().is_alive() {
+                            power.destroy();
+                        }
+                        *o != output
+                    });
+                } else if !powers.iter().any(|(o, _)| *o == output) {
+                    let power = power_off(&manager, &output);
+                    powers.push((output, power));
+                    if let Err(e) = display.flush() {
+                        eprintln!("Error flushing the wayland socket: {:?}", e);
+                    }
+                }
+            })
+        };
+
+        Some(OutputsOff { _osl, powers })
+    }
+}
+
+impl Drop for OutputsOff {
+    fn drop(&mut self) {
+        for (_, power) in self.powers.borrow_mut().drain(..) {
+            if power.as_ref().is_alive() {
+                power.set_mode(output_power::Mode::On);
+                power.destroy();
+            }
+        }
+    }
+}
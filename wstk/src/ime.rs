@@ -0,0 +1,177 @@
+//! Input methods through text-input-v3: the surface describes its focused text field,
+//! and the input method sends back preedit (text being composed) and committed text.
+
+pub use smithay_client_toolkit::reexports::protocols::unstable::text_input::v3::client::{
+    zwp_text_input_manager_v3 as text_input_manager, zwp_text_input_v3 as text_input,
+};
+
+use crate::{iced::Rectangle, surfaces::*};
+
+/// Surrounding text longer than this (in bytes) can't be sent in one request.
+const SURROUNDING_MAX: usize = 4000;
+
+/// The text field that currently has focus inside a surface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextField {
+    /// The text of the field (or the part of it around the cursor)
+    pub surrounding: String,
+    /// Byte offsets into `surrounding`, equal when nothing is selected
+    pub cursor: usize,
+    pub anchor: usize,
+    /// Where the cursor is on the surface, so that the input method can place its candidate window next to it
+    pub cursor_rect: Rectangle<u32>,
+    /// Passwords are not shown to the input method and don't get suggestions
+    pub password: bool,
+}
+
+impl TextField {
+    /// Converts byte lengths before and after the cursor to character counts
+    pub fn chars_around_cursor(&self, before: usize, after: usize) -> (usize, usize) {
+        let count = |start: usize, end: usize| self.surrounding.get(start..end).map_or(0, |s| s.chars().count());
+        (
+            count(self.cursor.saturating_sub(before), self.cursor),
+            count(self.cursor, (self.cursor + after).min(self.surrounding.len())),
+        )
+    }
+}
+
+/// Text being composed by the input method, shown at the cursor until it's committed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preedit {
+    pub text: String,
+    /// Byte offsets of the cursor (or selection) inside `text`, None if the cursor should be hidden
+    pub cursor: Option<(usize, usize)>,
+}
+
+/// Events of a text input, delivered once the input method is done with a change.
+#[derive(Debug, Clone)]
+pub enum ImeEvent {
+    Enter(wl_surface::WlSurface),
+    Leave(wl_surface::WlSurface),
+    /// Applied in order: delete around the cursor (in bytes), insert the commit string, replace the preedit
+    Done {
+        delete_surrounding: (u32, u32),
+        commit: Option<String>,
+        preedit: Option<Preedit>,
+    },
+}
+
+#[derive(Default)]
+struct Pending {
+    delete_surrounding: (u32, u32),
+    commit: Option<String>,
+    preedit: Option<Preedit>,
+}
+
+/// A seat's zwp_text_input_v3, remembering what was last told to the compositor.
+pub struct TextInput {
+    text_input: Main<text_input::ZwpTextInputV3>,
+    /// The field the input method was enabled for
+    current: Option<TextField>,
+}
+
+impl TextInput {
+    pub fn new(
+        manager: &Attached<text_input_manager::ZwpTextInputManagerV3>,
+        seat: &wl_seat::WlSeat,
+        mut callback: impl FnMut(ImeEvent) + 'static,
+    ) -> TextInput {
+        let text_input = manager.get_text_input(seat);
+        let mut pending = Pending::default();
+        text_input.quick_assign(move |_, event, _| match event {
+            text_input::Event::Enter { surface } => callback(ImeEvent::Enter(surface)),
+            text_input::Event::Leave { surface } => callback(ImeEvent::Leave(surface)),
+            text_input::Event::PreeditString {
+                text,
+                cursor_begin,
+                cursor_end,
+            } => {
+                pending.preedit = text.filter(|t| !t.is_empty()).map(|text| Preedit {
+                    text,
+                    cursor: if cursor_begin < 0 || cursor_end < 0 {
+                        None
+                    } else {
+                        Some((cursor_begin as _, cursor_end as _))
+                    },
+                })
+            }
+            text_input::Event::CommitString { text } => pending.commit = text.filter(|t| !t.is_empty()),
+            text_input::Event::DeleteSurroundingText {
+                before_length,
+                after_length,
+            } => pending.delete_surrounding = (before_length, after_length),
+            text_input::Event::Done { .. } => {
+                // the changes apply even when the serial is out of date, only our state would be stale,
+                // and that's fixed by the next update after rendering
+                let Pending {
+                    delete_surrounding,
+                    commit,
+                    preedit,
+                } = std::mem::take(&mut pending);
+                callback(ImeEvent::Done {
+                    delete_surrounding,
+                    commit,
+                    preedit,
+                })
+            }
+            _ => (),
+        });
+        TextInput {
+            text_input,
+            current: None,
+        }
+    }
+
+    /// The field the input method is currently enabled for
+    pub fn current(&self) -> Option<&TextField> {
+        self.current.as_ref()
+    }
+
+    /// After a leave, the compositor forgets everything, so the next update starts over
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// Enables, updates or disables the input method to match the focused field.
+    pub fn update(&mut self, field: Option<&TextField>) {
+        if field == self.current.as_ref() {
+            return;
+        }
+        match field {
+            Some(field) => {
+                if self.current.is_none() {
+                    self.text_input.enable();
+                }
+                if !field.password && field.surrounding.len() <= SURROUNDING_MAX {
+                    self.text_input.set_surrounding_text(
+                        field.surrounding.clone(),
+                        field.cursor as _,
+                        field.anchor as _,
+                    );
+                }
+                let (hint, purpose) = if field.password {
+                    (
+                        text_input::ContentHint::HiddenText | text_input::ContentHint::SensitiveData,
+                        text_input::ContentPurpose::Password,
+                    )
+                } else {
+                    (text_input::ContentHint::empty(), text_input::ContentPurpose::Normal)
+                };
+                self.text_input.set_content_type(hint, purpose);
+                let rect = field.cursor_rect;
+                self.text_input
+                    .set_cursor_rectangle(rect.x as _, rect.y as _, rect.width as _, rect.height as _);
+            }
+            None => self.text_input.disable(),
+        }
+        self.text_input.commit();
+        self.current = field.cloned();
+    }
+}
+
+impl Drop for TextInput {
+    fn drop(&mut self) {
+        self.text_input.quick_assign(|_, _, _| ());
+        self.text_input.destroy();
+    }
+}
@@ -0,0 +1,252 @@
+//! wl_keyboard decoding with xkbcommon: keymaps, modifiers, compose sequences and repeat settings.
+//! The repeating itself is timed by the instance, so that it stops as soon as focus goes away.
+
+use std::{ffi::OsString, fs::File, os::unix::fs::FileExt, os::unix::io::FromRawFd, time::Duration};
+use xkbcommon::xkb;
+
+use crate::surfaces::*;
+
+pub use smithay_client_toolkit::seat::keyboard::keysyms;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// Key repeat settings from `wl_keyboard.repeat_info`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepeatInfo {
+    /// Repeats per second, 0 means no repeat
+    pub rate: u32,
+    /// Time between the press and the first repeat
+    pub delay: Duration,
+}
+
+impl Default for RepeatInfo {
+    /// What most compositors default to, used until the compositor says otherwise
+    fn default() -> RepeatInfo {
+        RepeatInfo {
+            rate: 25,
+            delay: Duration::from_millis(600),
+        }
+    }
+}
+
+impl RepeatInfo {
+    pub fn interval(&self) -> Option<Duration> {
+        if self.rate == 0 {
+            return None;
+        }
+        Some(Duration::from_secs(1) / self.rate)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum KeyboardEvent {
+    Enter {
+        surface: wl_surface::WlSurface,
+        serial: u32,
+    },
+    Leave {
+        surface: wl_surface::WlSurface,
+        serial: u32,
+    },
+    Key {
+        serial: u32,
+        time: u32,
+        keysym: u32,
+        pressed: bool,
+        /// Text produced by a press, after compose. None while in the middle of a compose sequence.
+        utf8: Option<String>,
+        /// Whether holding the key should repeat it, according to the keymap
+        repeats: bool,
+    },
+    Modifiers {
+        modifiers: Modifiers,
+    },
+    RepeatInfo(RepeatInfo),
+}
+
+struct XkbState {
+    context: xkb::Context,
+    keymap: Option<xkb::Keymap>,
+    state: Option<xkb::State>,
+    compose: Option<xkb::compose::State>,
+}
+
+/// The locale the compose table is looked up for, same order as in libc
+fn compose_locale() -> OsString {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .find(|l| !l.is_empty())
+        .unwrap_or_else(|| "C".into())
+}
+
+impl XkbState {
+    fn new() -> XkbState {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let locale = compose_locale();
+        let compose = xkb::compose::Table::new_from_locale(&context, &locale, xkb::compose::COMPILE_NO_FLAGS)
+            .map_err(|_| eprintln!("WARN: no compose table for locale {:?}", locale))
+            .ok()
+            .map(|table| xkb::compose::State::new(&table, xkb::compose::STATE_NO_FLAGS));
+        XkbState {
+            context,
+            keymap: None,
+            state: None,
+            compose,
+        }
+    }
+
+    fn set_keymap(&mut self, file: File, size: u32) {
+        // the fd can be shared with other clients, so don't touch its offset
+        let mut buf = vec![0; size as usize];
+        if let Err(e) = file.read_exact_at(&mut buf, 0) {
+            eprintln!("Could not read the keymap: {:?}", e);
+            return;
+        }
+        let text = String::from_utf8_lossy(&buf).trim_end_matches('\0').to_owned();
+        match xkb::Keymap::new_from_string(
+            &self.context,
+            text,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        ) {
+            Some(keymap) => {
+                self.state = Some(xkb::State::new(&keymap));
+                self.keymap = Some(keymap);
+            }
+            None => eprintln!("WARN: could not compile the keymap"),
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        let state = match self.state {
+            Some(ref state) => state,
+            None => return Modifiers::default(),
+        };
+        let active = |name: &str| state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+        Modifiers {
+            ctrl: active(xkb::MOD_NAME_CTRL),
+            alt: active(xkb::MOD_NAME_ALT),
+            shift: active(xkb::MOD_NAME_SHIFT),
+            logo: active(xkb::MOD_NAME_LOGO),
+            caps_lock: active(xkb::MOD_NAME_CAPS),
+            num_lock: active(xkb::MOD_NAME_NUM),
+        }
+    }
+
+    /// Text for a key press, fed through the compose state first
+    fn press_utf8(&mut self, keycode: u32, keysym: u32) -> Option<String> {
+        if let Some(ref mut compose) = self.compose {
+            if let xkb::compose::FeedResult::Accepted = compose.feed(keysym) {
+                match compose.status() {
+                    xkb::compose::Status::Composing => return None,
+                    xkb::compose::Status::Composed => {
+                        let text = compose.utf8();
+                        compose.reset();
+                        return text;
+                    }
+                    xkb::compose::Status::Cancelled => {
+                        compose.reset();
+                        return None;
+                    }
+                    xkb::compose::Status::Nothing => (),
+                }
+            }
+        }
+        let text = self.state.as_ref()?.key_get_utf8(keycode);
+        if text.is_empty() {
+            None
+        } else {
+            Some(text)
+        }
+    }
+
+    fn handle(&mut self, event: wl_keyboard::Event) -> Option<KeyboardEvent> {
+        match event {
+            wl_keyboard::Event::Keymap { format, fd, size } => {
+                // owning it makes sure it's closed
+                let file = unsafe { File::from_raw_fd(fd) };
+                if format == wl_keyboard::KeymapFormat::XkbV1 {
+                    self.set_keymap(file, size);
+                } else {
+                    eprintln!("WARN: unsupported keymap format {:?}", format);
+                }
+                None
+            }
+            wl_keyboard::Event::Enter { serial, surface, .. } => {
+                if let Some(ref mut compose) = self.compose {
+                    compose.reset();
+                }
+                Some(KeyboardEvent::Enter { surface, serial })
+            }
+            wl_keyboard::Event::Leave { serial, surface } => Some(KeyboardEvent::Leave { surface, serial }),
+            wl_keyboard::Event::Key {
+                serial,
+                time,
+                key,
+                state,
+            } => {
+                // evdev to xkb
+                let keycode = key + 8;
+                let keysym = self.state.as_ref()?.key_get_one_sym(keycode);
+                let pressed = state == wl_keyboard::KeyState::Pressed;
+                let utf8 = if pressed {
+                    self.press_utf8(keycode, keysym)
+                } else {
+                    None
+                };
+                let repeats = self.keymap.as_ref().map(|k| k.key_repeats(keycode)).unwrap_or(false);
+                Some(KeyboardEvent::Key {
+                    serial,
+                    time,
+                    keysym,
+                    pressed,
+                    utf8,
+                    repeats,
+                })
+            }
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                self.state
+                    .as_mut()?
+                    .update_mask(mods_depressed, mods_latched, mods_locked, 0, 0, group);
+                Some(KeyboardEvent::Modifiers {
+                    modifiers: self.modifiers(),
+                })
+            }
+            wl_keyboard::Event::RepeatInfo { rate, delay } => Some(KeyboardEvent::RepeatInfo(RepeatInfo {
+                rate: rate.max(0) as _,
+                delay: Duration::from_millis(delay.max(0) as _),
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Gets the seat's keyboard, calling back with decoded events.
+pub fn map_keyboard(
+    seat: &Attached<wl_seat::WlSeat>,
+    mut callback: impl FnMut(KeyboardEvent) + 'static,
+) -> Main<wl_keyboard::WlKeyboard> {
+    let keyboard = seat.get_keyboard();
+    let mut xkb = XkbState::new();
+    keyboard.quick_assign(move |_, event, _| {
+        if let Some(event) = xkb.handle(event) {
+            callback(event)
+        }
+    });
+    keyboard
+}
@@ -8,18 +8,66 @@ pub use event_loop::*;
 pub mod toplevels;
 pub use toplevels::*;
 
+#[macro_use]
+pub mod output_config;
+pub use output_config::*;
+
 pub mod run;
 pub use run::*;
 
 pub mod multimonitor;
 pub use multimonitor::*;
 
+pub mod protocols;
+
 pub mod surfaces;
 pub use surfaces::*;
 
+pub mod session_lock;
+pub use session_lock::{LockState, SessionLock};
+
+pub mod capture;
+pub use capture::{CaptureService, CapturedFrame};
+
+pub mod idle;
+pub use idle::{IdleInhibitor, IdleService, IdleTimeout, OutputsOff};
+
+pub mod gamma;
+pub use gamma::{GammaService, NEUTRAL_TEMPERATURE};
+
+pub mod surface_registry;
+pub use surface_registry::{SurfaceEvent, SurfaceRegistry};
+
+pub mod popup;
+pub use popup::{Popup, PopupAnchor, PopupEvent};
+
+pub mod clipboard;
+pub use clipboard::*;
+
+pub mod ime;
+pub use ime::{Preedit, TextField};
+
+pub mod password_prompt;
+pub use password_prompt::{finish_prompt, prompt_echo, PasswordPrompt, PromptElement};
+
+pub mod keys;
+pub use keys::{KeyboardEvent, RepeatInfo};
+
+pub mod seats;
+pub use seats::*;
+
 pub mod iced;
 pub use iced::*;
 
+pub mod animation;
+pub use animation::{Easing, Tween};
+
+pub mod render;
+pub use render::{Renderer, RendererKind};
+
+pub mod headless;
+pub use headless::{assert_matches_reference, Headless, MemoryClipboard};
+
 pub mod widgets;
 pub use widgets::*;
 
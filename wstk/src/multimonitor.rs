@@ -5,11 +5,28 @@ use crate::{
 use async_trait::async_trait;
 use futures::{channel::mpsc, future::LocalBoxFuture, FutureExt, StreamExt};
 
+/// Decides which outputs get an instance, checked again whenever an output's info changes.
+pub type OutputFilter<'a> = Box<dyn 'a + Fn(&wl_output::WlOutput, &output::OutputInfo) -> bool>;
+
+/// Identifies an output across info changes: the name (e.g. "DP-1") when the compositor sends one,
+/// otherwise the description or make and model.
+pub fn output_key(info: &output::OutputInfo) -> String {
+    if !info.name.is_empty() {
+        info.name.clone()
+    } else if !info.description.is_empty() {
+        info.description.clone()
+    } else {
+        format!("{} {} #{}", info.make, info.model, info.id)
+    }
+}
+
+/// Runs an instance per output, creating and destroying them as outputs come and go.
 pub struct MultiMonitor<'a, T> {
     _osl: output::OutputStatusListener,
     rx: mpsc::UnboundedReceiver<(wl_output::WlOutput, output::OutputInfo)>,
-    instances: Vec<T>,
+    instances: Vec<(String, wl_output::WlOutput, T)>,
     mk: Box<dyn 'a + Fn(wl_output::WlOutput, output::OutputInfo) -> LocalBoxFuture<'a, T>>,
+    filter: OutputFilter<'a>,
 }
 
 impl<'a, T> MultiMonitor<'a, T>
@@ -19,14 +36,25 @@ where
     pub async fn new(
         mk: Box<dyn 'a + Fn(wl_output::WlOutput, output::OutputInfo) -> LocalBoxFuture<'a, T>>,
         env: &'a Environment<Env>,
+    ) -> MultiMonitor<'a, T> {
+        MultiMonitor::with_filter(
+            mk,
+            Box::new(|_: &wl_output::WlOutput, _: &output::OutputInfo| true),
+            env,
+        )
+        .await
+    }
+
+    /// Only creates instances on the outputs the filter accepts
+    pub async fn with_filter(
+        mk: Box<dyn 'a + Fn(wl_output::WlOutput, output::OutputInfo) -> LocalBoxFuture<'a, T>>,
+        filter: OutputFilter<'a>,
+        env: &'a Environment<Env>,
     ) -> MultiMonitor<'a, T> {
         let (tx, rx) = mpsc::unbounded();
-        let mut instances = Vec::new();
 
+        // also called for every info change of existing outputs, and for removed ones (obsolete)
         let _osl = env.listen_for_outputs(move |output, info, _| {
-            if info.obsolete {
-                return;
-            }
             if let Err(e) = tx.unbounded_send((output, info.clone())) {
                 if !e.is_disconnected() {
                     panic!("Unexpected send error {:?}", e)
@@ -34,19 +62,47 @@ where
             }
         });
 
+        let mut mm = MultiMonitor {
+            _osl,
+            rx,
+            instances: Vec::new(),
+            mk,
+            filter,
+        };
         for output in env.get_all_outputs() {
             if let Some(info) = output::with_output_info(&output, Clone::clone) {
-                instances.push(mk(output, info).await);
+                mm.on_output(output, info).await;
             } else {
                 eprintln!("Could not get output info?");
             }
         }
+        mm
+    }
 
-        MultiMonitor {
-            _osl,
-            rx,
-            instances,
-            mk,
+    /// Keys of the outputs that currently have instances
+    pub fn outputs(&self) -> impl Iterator<Item = &str> {
+        self.instances.iter().map(|(key, _, _)| key.as_str())
+    }
+
+    pub fn instances(&self) -> impl Iterator<Item = &T> {
+        self.instances.iter().map(|(_, _, x)| x)
+    }
+
+    async fn on_output(&mut self, output: wl_output::WlOutput, info: output::OutputInfo) {
+        let key = output_key(&info);
+        let wanted = !info.obsolete && (self.filter)(&output, &info);
+        // the key can change when e.g. the name arrives late, the output object can't
+        let existing = self.instances.iter().position(|(_, o, _)| *o == output);
+        match (wanted, existing) {
+            (true, None) => {
+                let instance = (self.mk)(output.clone(), info).await;
+                self.instances.push((key, output, instance));
+            }
+            (true, Some(idx)) => self.instances[idx].0 = key,
+            (false, Some(idx)) => {
+                self.instances.remove(idx);
+            }
+            (false, None) => (),
         }
     }
 }
@@ -62,7 +118,7 @@ where
             .instances
             .iter_mut()
             .enumerate()
-            .map(|(i, x)| x.run().map(move |res| (res, i)))
+            .map(|(i, (_, _, x))| x.run().map(move |res| (res, i)))
             .collect::<futures::stream::FuturesUnordered<_>>();
         futures::select! {
             inst_res = run_instances.select_next_some() => {
@@ -77,9 +133,8 @@ where
             },
             (output, info) = this.rx.select_next_some() => {
                 drop(run_instances);
-                this.instances.push(
-                    (this.mk)(output, info).await,
-                );
+                // instances of unplugged outputs go away, but that doesn't end the whole thing
+                this.on_output(output, info).await;
             }
         }
         true
@@ -0,0 +1,473 @@
+//! Monitor configuration through wlr-output-management: the compositor describes its heads
+//! (connected outputs, enabled or not), and whole configurations can be tested and applied.
+
+use event_listener;
+use futures::{channel::oneshot, prelude::*};
+use smithay_client_toolkit::{
+    environment::GlobalHandler,
+    reexports::client::{
+        protocol::{wl_output, wl_registry},
+        Attached, DispatchData,
+    },
+};
+
+pub use smithay_client_toolkit::reexports::protocols::wlr::unstable::output_management::v1::client::{
+    zwlr_output_configuration_head_v1 as output_configuration_head,
+    zwlr_output_configuration_v1 as output_configuration, zwlr_output_head_v1 as output_head,
+    zwlr_output_manager_v1 as output_manager, zwlr_output_mode_v1 as output_mode,
+};
+
+use std::{
+    cell::{Cell, Ref, RefCell},
+    rc::Rc,
+};
+
+#[derive(Debug, Clone)]
+pub struct OutputMode {
+    handle: output_mode::ZwlrOutputModeV1,
+    /// In hardware pixels, before the transform
+    pub width: i32,
+    pub height: i32,
+    /// In mHz, zero if unknown
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
+impl OutputMode {
+    fn new(handle: output_mode::ZwlrOutputModeV1) -> OutputMode {
+        OutputMode {
+            handle,
+            width: 0,
+            height: 0,
+            refresh: 0,
+            preferred: false,
+        }
+    }
+}
+
+impl std::fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.refresh > 0 {
+            write!(
+                f,
+                "{}×{} @ {:.2} Hz",
+                self.width,
+                self.height,
+                self.refresh as f64 / 1000.0
+            )
+        } else {
+            write!(f, "{}×{}", self.width, self.height)
+        }
+    }
+}
+
+/// A connected output, which may be disabled.
+#[derive(Debug, Clone)]
+pub struct OutputHead {
+    handle: output_head::ZwlrOutputHeadV1,
+    /// Unique among the heads, e.g. "DP-1"
+    pub name: String,
+    pub description: String,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    /// In millimeters, zero if unknown
+    pub physical_size: (i32, i32),
+    pub modes: Vec<OutputMode>,
+    /// Index into `modes`, None when disabled or using a custom mode
+    pub current_mode: Option<usize>,
+    pub enabled: bool,
+    /// Only meaningful when enabled
+    pub position: (i32, i32),
+    pub transform: wl_output::Transform,
+    pub scale: f64,
+}
+
+impl OutputHead {
+    fn new(handle: output_head::ZwlrOutputHeadV1) -> OutputHead {
+        OutputHead {
+            handle,
+            name: String::new(),
+            description: String::new(),
+            make: None,
+            model: None,
+            serial_number: None,
+            physical_size: (0, 0),
+            modes: Vec::new(),
+            current_mode: None,
+            enabled: false,
+            position: (0, 0),
+            transform: wl_output::Transform::Normal,
+            scale: 1.0,
+        }
+    }
+
+    /// The head as it's configured right now
+    pub fn config(&self) -> HeadConfig {
+        HeadConfig {
+            name: self.name.clone(),
+            enabled: self.enabled,
+            mode: self.current_mode,
+            position: self.position,
+            transform: self.transform,
+            scale: self.scale,
+        }
+    }
+}
+
+/// What a head should look like, see `OutputConfigService::apply`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadConfig {
+    pub name: String,
+    pub enabled: bool,
+    /// Index into the head's `modes`, None keeps the current (custom) mode
+    pub mode: Option<usize>,
+    pub position: (i32, i32),
+    pub transform: wl_output::Transform,
+    pub scale: f64,
+}
+
+impl HeadConfig {
+    /// Size in the global compositor space, i.e. after the transform and scale.
+    /// Without a mode, that's the head's current one (custom modes are announced as modes too),
+    /// or for a disabled head, the one enabling it picks. Zero if nothing is known.
+    pub fn logical_size(&self, head: &OutputHead) -> (i32, i32) {
+        let mode = match self.mode.or(head.current_mode) {
+            Some(i) => head.modes.get(i),
+            None if !head.enabled => head.modes.iter().find(|m| m.preferred).or_else(|| head.modes.first()),
+            None => None,
+        };
+        let (w, h) = match mode {
+            Some(mode) => (mode.width, mode.height),
+            None => return (0, 0),
+        };
+        let (w, h) = match self.transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => (h, w),
+            _ => (w, h),
+        };
+        (
+            (w as f64 / self.scale).round() as i32,
+            (h as f64 / self.scale).round() as i32,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigResult {
+    Succeeded,
+    Failed,
+    /// The heads changed in the meantime, look at them again and retry
+    Cancelled,
+}
+
+#[derive(Default)]
+struct OutputState {
+    heads: Vec<OutputHead>,
+    /// Sent with every atomic update of the heads, configurations must refer to the latest one
+    serial: Option<u32>,
+}
+
+impl OutputState {
+    fn head(&mut self, handle: &output_head::ZwlrOutputHeadV1) -> Option<&mut OutputHead> {
+        self.heads.iter_mut().find(|h| h.handle == *handle)
+    }
+
+    fn mode(&mut self, handle: &output_mode::ZwlrOutputModeV1) -> Option<&mut OutputMode> {
+        self.heads
+            .iter_mut()
+            .flat_map(|h| h.modes.iter_mut())
+            .find(|m| m.handle == *handle)
+    }
+}
+
+pub struct OutputConfigService {
+    manager: RefCell<Option<Attached<output_manager::ZwlrOutputManagerV1>>>,
+    state: Rc<RefCell<OutputState>>,
+    notifier: Rc<event_listener::Event>,
+    finished: Rc<Cell<bool>>,
+}
+
+impl OutputConfigService {
+    pub fn new() -> OutputConfigService {
+        OutputConfigService {
+            manager: RefCell::new(None),
+            state: Rc::new(RefCell::new(OutputState::default())),
+            notifier: Rc::new(event_listener::Event::new()),
+            finished: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Whether the compositor lets us configure outputs at all
+    pub fn is_available(&self) -> bool {
+        self.manager.borrow().is_some() && !self.finished.get()
+    }
+
+    /// Heads as of the latest update, in the compositor's order
+    pub fn heads(&self) -> Ref<'_, Vec<OutputHead>> {
+        Ref::map(self.state.borrow(), |s| &s.heads)
+    }
+
+    pub fn current_config(&self) -> Vec<HeadConfig> {
+        self.state.borrow().heads.iter().map(OutputHead::config).collect()
+    }
+
+    /// Wakes up whenever the heads change
+    pub fn subscribe(&self) -> impl Future<Output = ()> {
+        self.notifier.listen()
+    }
+
+    /// Checks whether the compositor would accept the configuration without applying it.
+    /// Like `apply`, sends the requests right away, flush the display to get the result.
+    pub fn test(&self, config: &[HeadConfig]) -> impl Future<Output = ConfigResult> {
+        let rx = self.configure(config, false);
+        async move {
+            match rx {
+                Some(rx) => rx.await.unwrap_or(ConfigResult::Cancelled),
+                None => ConfigResult::Failed,
+            }
+        }
+    }
+
+    /// Applies the configuration. Heads missing from it keep their current configuration.
+    pub fn apply(&self, config: &[HeadConfig]) -> impl Future<Output = ConfigResult> {
+        let rx = self.configure(config, true);
+        async move {
+            match rx {
+                Some(rx) => rx.await.unwrap_or(ConfigResult::Cancelled),
+                None => ConfigResult::Failed,
+            }
+        }
+    }
+
+    fn configure(&self, config: &[HeadConfig], apply: bool) -> Option<oneshot::Receiver<ConfigResult>> {
+        let manager = self.manager.borrow();
+        let state = self.state.borrow();
+        let (manager, serial) = match (manager.as_ref(), state.serial) {
+            (Some(manager), Some(serial)) if !self.finished.get() => (manager, serial),
+            _ => {
+                eprintln!("WARN: output configuration is not available");
+                return None;
+            }
+        };
+        let configuration = manager.create_configuration(serial);
+        for head in state.heads.iter() {
+            let current = head.config();
+            let wanted = config.iter().find(|c| c.name == head.name).unwrap_or(&current);
+            if !wanted.enabled {
+                configuration.disable_head(&head.handle);
+                continue;
+            }
+            let conf_head = configuration.enable_head(&head.handle);
+            match wanted.mode.and_then(|i| head.modes.get(i)) {
+                Some(mode) => conf_head.set_mode(&mode.handle),
+                None if !head.enabled => {
+                    // enabling needs some mode, the preferred one is the best guess
+                    if let Some(mode) = head.modes.iter().find(|m| m.preferred).or_else(|| head.modes.first()) {
+                        conf_head.set_mode(&mode.handle);
+                    }
+                }
+                None => (),
+            }
+            conf_head.set_position(wanted.position.0, wanted.position.1);
+            conf_head.set_transform(wanted.transform);
+            conf_head.set_scale(wanted.scale);
+        }
+        let (tx, rx) = oneshot::channel();
+        let mut tx = Some(tx);
+        configuration.quick_assign(move |configuration, event, _| {
+            let result = match event {
+                output_configuration::Event::Succeeded => ConfigResult::Succeeded,
+                output_configuration::Event::Failed => ConfigResult::Failed,
+                output_configuration::Event::Cancelled => ConfigResult::Cancelled,
+                x => {
+                    eprintln!("WARN: unknown output configuration event {:?}", x);
+                    return;
+                }
+            };
+            configuration.destroy();
+            if let Some(tx) = tx.take() {
+                let _ = tx.send(result);
+            }
+        });
+        if apply {
+            configuration.apply();
+        } else {
+            configuration.test();
+        }
+        Some(rx)
+    }
+}
+
+pub struct OutputConfigServiceRc(pub Rc<OutputConfigService>);
+
+impl GlobalHandler<output_manager::ZwlrOutputManagerV1> for OutputConfigServiceRc {
+    fn created(&mut self, registry: Attached<wl_registry::WlRegistry>, id: u32, version: u32, _: DispatchData) {
+        let main = registry.bind::<output_manager::ZwlrOutputManagerV1>(version.min(2), id);
+        let state = self.0.state.clone();
+        let notifier = self.0.notifier.clone();
+        let finished = self.0.finished.clone();
+        main.quick_assign(move |_, event, _| match event {
+            output_manager::Event::Head { head } => {
+                state.borrow_mut().heads.push(OutputHead::new(head.detach()));
+                let state = state.clone();
+                head.quick_assign(move |head, event, _| {
+                    if let output_head::Event::Mode { mode } = event {
+                        if let Some(h) = state.borrow_mut().head(&head) {
+                            h.modes.push(OutputMode::new(mode.detach()));
+                        }
+                        let state = state.clone();
+                        mode.quick_assign(move |mode, event, _| {
+                            let mut state = state.borrow_mut();
+                            if let output_mode::Event::Finished = event {
+                                for h in state.heads.iter_mut() {
+                                    // keep the index of the current mode pointing at the same mode
+                                    let current = h.current_mode.map(|i| h.modes[i].handle.clone());
+                                    h.modes.retain(|m| m.handle != *mode);
+                                    h.current_mode = current.and_then(|c| h.modes.iter().position(|m| m.handle == c));
+                                }
+                                return;
+                            }
+                            let m = match state.mode(&mode) {
+                                Some(m) => m,
+                                None => return,
+                            };
+                            match event {
+                                output_mode::Event::Size { width, height } => {
+                                    m.width = width;
+                                    m.height = height;
+                                }
+                                output_mode::Event::Refresh { refresh } => m.refresh = refresh,
+                                output_mode::Event::Preferred => m.preferred = true,
+                                x => eprintln!("WARN: unknown output mode event {:?}", x),
+                            }
+                        });
+                        return;
+                    }
+                    let mut state = state.borrow_mut();
+                    if let output_head::Event::Finished = event {
+                        state.heads.retain(|h| h.handle != *head);
+                        return;
+                    }
+                    let h = match state.head(&head) {
+                        Some(h) => h,
+                        None => return,
+                    };
+                    match event {
+                        output_head::Event::Name { name } => h.name = name,
+                        output_head::Event::Description { description } => h.description = description,
+                        output_head::Event::PhysicalSize { width, height } => h.physical_size = (width, height),
+                        output_head::Event::Enabled { enabled } => {
+                            h.enabled = enabled != 0;
+                            if !h.enabled {
+                                h.current_mode = None;
+                            }
+                        }
+                        // modes are always announced before they can become current
+                        output_head::Event::CurrentMode { mode } => {
+                            h.current_mode = h.modes.iter().position(|m| m.handle == mode)
+                        }
+                        output_head::Event::Position { x, y } => h.position = (x, y),
+                        output_head::Event::Transform { transform } => h.transform = transform,
+                        output_head::Event::Scale { scale } => h.scale = scale,
+                        output_head::Event::Make { make } => h.make = Some(make),
+                        output_head::Event::Model { model } => h.model = Some(model),
+                        output_head::Event::SerialNumber { serial_number } => h.serial_number = Some(serial_number),
+                        x => eprintln!("WARN: unknown output head event {:?}", x),
+                    }
+                });
+            }
+            output_manager::Event::Done { serial } => {
+                state.borrow_mut().serial = Some(serial);
+                notifier.notify(usize::MAX);
+            }
+            output_manager::Event::Finished => {
+                eprintln!("WARN: the compositor stopped sending output heads");
+                finished.set(true);
+            }
+            x => eprintln!("WARN: unknown output manager event {:?}", x),
+        });
+        *self.0.manager.borrow_mut() = Some((*main).clone())
+    }
+
+    fn get(&self) -> Option<Attached<output_manager::ZwlrOutputManagerV1>> {
+        self.0.manager.borrow().clone()
+    }
+}
+
+pub trait HasOutputConfigService {
+    fn output_config_service(&self) -> Rc<OutputConfigService>;
+}
+
+macro_rules! output_config_handler {
+    ($env:ident, $field:ident) => {
+        impl HasOutputConfigService for $env {
+            fn output_config_service(&self) -> Rc<OutputConfigService> {
+                self.$field.0.clone()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smithay_client_toolkit::reexports::client::{Interface, Proxy};
+
+    /// Protocol objects can't be created without a connection, but dead ones do for plain data
+    fn dead<I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>>() -> I {
+        unsafe { Proxy::<I>::from_c_ptr(std::ptr::null_mut()) }.into()
+    }
+
+    fn mode(width: i32, height: i32, preferred: bool) -> OutputMode {
+        OutputMode {
+            width,
+            height,
+            preferred,
+            ..OutputMode::new(dead())
+        }
+    }
+
+    /// Enabled in the second, non-preferred mode
+    fn head() -> OutputHead {
+        let mut head = OutputHead::new(dead());
+        head.modes = vec![mode(1920, 1080, true), mode(2560, 1440, false)];
+        head.current_mode = Some(1);
+        head.enabled = true;
+        head
+    }
+
+    #[test]
+    fn logical_size_transform_and_scale() {
+        let head = head();
+        let mut conf = head.config();
+        conf.mode = Some(0);
+        assert_eq!(conf.logical_size(&head), (1920, 1080));
+        conf.scale = 1.5;
+        assert_eq!(conf.logical_size(&head), (1280, 720));
+        conf.transform = wl_output::Transform::_90;
+        assert_eq!(conf.logical_size(&head), (720, 1280));
+        conf.transform = wl_output::Transform::Flipped270;
+        assert_eq!(conf.logical_size(&head), (720, 1280));
+        conf.transform = wl_output::Transform::Flipped180;
+        assert_eq!(conf.logical_size(&head), (1280, 720));
+    }
+
+    #[test]
+    fn logical_size_without_mode() {
+        let mut head = head();
+        let mut conf = head.config();
+        conf.mode = None;
+        conf.scale = 2.0;
+        assert_eq!(conf.logical_size(&head), (1280, 720));
+
+        head.enabled = false;
+        head.current_mode = None;
+        assert_eq!(conf.logical_size(&head), (960, 540));
+
+        head.modes.clear();
+        assert_eq!(conf.logical_size(&head), (0, 0));
+    }
+}
@@ -0,0 +1,221 @@
+//! The conversation of an authentication dialog (PAM, polkit): messages, and prompts that are
+//! answered one at a time through a text input, which hides what's typed for passwords.
+
+use std::cell::Cell;
+
+use crate::{
+    iced::{Element, Rectangle},
+    ime::{Preedit, TextField},
+    style,
+    widgets::GetRegion,
+};
+use iced_native::text_input;
+
+pub enum PromptElement {
+    Info(String),
+    Error(String),
+    Prompt { txt: String, echo_on: bool, done: bool },
+}
+
+/// Whether the prompt that is waiting for input shows what's typed (i.e. isn't a password)
+pub fn prompt_echo(elements: &[PromptElement]) -> Option<bool> {
+    elements.iter().find_map(|e| match e {
+        PromptElement::Prompt { echo_on, done, .. } if !done => Some(*echo_on),
+        _ => None,
+    })
+}
+
+/// Marks the latest prompt as answered
+pub fn finish_prompt(elements: &mut [PromptElement]) {
+    for e in elements.iter_mut().rev() {
+        if let PromptElement::Prompt { ref mut done, .. } = e {
+            *done = true;
+            break;
+        }
+    }
+}
+
+/// The input state of a dialog showing a conversation, for a surface's `IcedSurface` methods to delegate to
+pub struct PasswordPrompt {
+    input: text_input::State,
+    value: String,
+    region: Cell<Rectangle>,
+    preedit: Option<Preedit>,
+}
+
+impl Default for PasswordPrompt {
+    fn default() -> Self {
+        PasswordPrompt {
+            input: text_input::State::focused(),
+            value: String::new(),
+            region: Default::default(),
+            preedit: None,
+        }
+    }
+}
+
+impl PasswordPrompt {
+    pub fn new() -> PasswordPrompt {
+        Default::default()
+    }
+
+    /// The elements one below another, with the input next to the prompt that is waiting for it
+    pub fn view<'a, M: Clone + 'static>(
+        &'a mut self,
+        elements: &[PromptElement],
+        on_change: fn(String) -> M,
+        on_submit: M,
+    ) -> Element<'a, M> {
+        use iced_native::*;
+
+        let mut col = Column::new().spacing(16);
+
+        // We're only supposed to have one not-done prompt, but Rust doesn't know.
+        // Enforce this by borrowing outside of the loop here.
+        let mut input_stref = Some(&mut self.input);
+
+        for e in elements.iter() {
+            match e {
+                PromptElement::Info(txt) => {
+                    // TODO: style
+                    col = col.push(Text::new(txt.clone()).size(18));
+                }
+                PromptElement::Error(txt) => {
+                    // TODO: style
+                    col = col.push(Text::new(txt.clone()).size(18));
+                }
+                PromptElement::Prompt { txt, echo_on, done } => {
+                    let mut row = Row::new()
+                        .align_items(Align::Center)
+                        .spacing(8)
+                        .push(Text::new(txt.clone()).size(18));
+                    if !done {
+                        if let Some(input) = input_stref.take() {
+                            let mut input = TextInput::new(input, "", &self.value, on_change)
+                                .on_submit(on_submit.clone())
+                                .width(Length::Fill)
+                                .style(style::Dialog)
+                                .padding(4);
+                            if !echo_on {
+                                input = input.password();
+                            }
+                            row = row.push(GetRegion::new(&self.region, input));
+                            if let Some(ref preedit) = self.preedit {
+                                row = row.push(Text::new(preedit.text.clone()).size(18));
+                            }
+                        }
+                    }
+                    col = col.push(row);
+                }
+            }
+        }
+
+        col.into()
+    }
+
+    pub fn set_value(&mut self, value: String) {
+        self.value = value;
+    }
+
+    /// Appends pasted text, leaving out line breaks and such
+    pub fn paste(&mut self, txt: &str) {
+        self.value.extend(txt.chars().filter(|c| !c.is_control()));
+        self.input.move_cursor_to_end();
+    }
+
+    pub fn set_preedit(&mut self, preedit: Option<Preedit>) {
+        self.preedit = preedit;
+    }
+
+    /// The answer to send, clearing the input for the next prompt
+    pub fn take_response(&mut self) -> String {
+        self.preedit = None;
+        std::mem::replace(&mut self.value, String::new())
+    }
+
+    pub fn primary_selection(&self, elements: &[PromptElement]) -> Option<String> {
+        use iced_native::text_input::{cursor, Value};
+
+        // Never leak passwords into the primary selection
+        if prompt_echo(elements) != Some(true) {
+            return None;
+        }
+        let value = Value::new(&self.value);
+        match self.input.cursor().state(&value) {
+            cursor::State::Selection { start, end } if start != end => {
+                Some(value.select(start.min(end), start.max(end)).to_string())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn text_field(&self, elements: &[PromptElement]) -> Option<TextField> {
+        use iced_native::text_input::{cursor, Value};
+
+        let echo_on = prompt_echo(elements)?;
+        if !self.input.is_focused() {
+            return None;
+        }
+        let value = Value::new(&self.value);
+        let (cursor, anchor) = match self.input.cursor().state(&value) {
+            cursor::State::Index(i) => (i, i),
+            cursor::State::Selection { start, end } => (end, start),
+        };
+        let byte_offset = |i| value.until(i).to_string().len();
+        Some(TextField {
+            surrounding: self.value.clone(),
+            cursor: byte_offset(cursor),
+            anchor: byte_offset(anchor),
+            cursor_rect: self.region.get().snap(),
+            password: !echo_on,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(echo_on: bool) -> Vec<PromptElement> {
+        vec![
+            PromptElement::Info("Hello".to_owned()),
+            PromptElement::Prompt {
+                txt: "Password:".to_owned(),
+                echo_on,
+                done: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn paste_and_answer() {
+        let mut elements = prompt(false);
+        let mut pp = PasswordPrompt::new();
+        pp.set_value("hu".to_owned());
+        pp.paste("n\nter2\t");
+        let field = pp.text_field(&elements).unwrap();
+        assert_eq!(field.surrounding, "hunter2");
+        assert_eq!((field.cursor, field.anchor), (7, 7));
+        assert!(field.password);
+        assert_eq!(pp.primary_selection(&elements), None);
+
+        assert_eq!(pp.take_response(), "hunter2");
+        finish_prompt(&mut elements);
+        assert_eq!(prompt_echo(&elements), None);
+        assert_eq!(pp.text_field(&elements), None);
+    }
+
+    #[test]
+    fn echo_of_waiting_prompt() {
+        let mut elements = prompt(true);
+        assert_eq!(prompt_echo(&elements), Some(true));
+        finish_prompt(&mut elements);
+        elements.push(PromptElement::Prompt {
+            txt: "Token:".to_owned(),
+            echo_on: false,
+            done: false,
+        });
+        assert_eq!(prompt_echo(&elements), Some(false));
+        assert!(!PasswordPrompt::new().text_field(&prompt(true)).unwrap().password);
+    }
+}
@@ -0,0 +1,176 @@
+use futures::future;
+use smithay_client_toolkit::{
+    environment::GlobalHandler,
+    reexports::client::{protocol::wl_registry, DispatchData},
+};
+use std::{cell::RefCell, rc::Rc};
+
+pub use smithay_client_toolkit::reexports::protocols::xdg_shell::client::{
+    xdg_popup, xdg_positioner, xdg_surface, xdg_wm_base,
+};
+
+use crate::{event_loop::AsyncMain, surfaces::*};
+
+/// Binds xdg_wm_base (only used for popups here, toplevels are not our business) and answers pings.
+pub struct WmBaseGlobal(Rc<RefCell<Option<Attached<xdg_wm_base::XdgWmBase>>>>);
+
+impl WmBaseGlobal {
+    pub fn new() -> WmBaseGlobal {
+        WmBaseGlobal(Rc::new(RefCell::new(None)))
+    }
+}
+
+impl GlobalHandler<xdg_wm_base::XdgWmBase> for WmBaseGlobal {
+    fn created(&mut self, registry: Attached<wl_registry::WlRegistry>, id: u32, version: u32, _: DispatchData) {
+        let main = registry.bind::<xdg_wm_base::XdgWmBase>(version.min(3), id);
+        main.quick_assign(|wm_base, event, _| match event {
+            xdg_wm_base::Event::Ping { serial } => wm_base.pong(serial),
+            _ => (),
+        });
+        *self.0.borrow_mut() = Some((*main).clone())
+    }
+
+    fn get(&self) -> Option<Attached<xdg_wm_base::XdgWmBase>> {
+        self.0.borrow().clone()
+    }
+}
+
+/// Where a popup should appear, relative to the main surface.
+/// The popup is placed above the rectangle and centered on it, and slides or flips to stay on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PopupAnchor {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Take an explicit grab (needs a recent click or key press), so that the compositor
+    /// dismisses the popup on clicks outside of it.
+    pub grab: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopupEvent {
+    /// The popup can be drawn at this logical size
+    Configured { width: u32, height: u32 },
+    /// The compositor dismissed the popup, it will never be shown again
+    Done,
+}
+
+/// An xdg_popup parented to a layer surface.
+pub struct Popup {
+    pub wl_surface: Attached<wl_surface::WlSurface>,
+    viewport: Option<Main<wp_viewport::WpViewport>>,
+    xdg_popup: AsyncMain<xdg_popup::XdgPopup>,
+    xdg_surface: AsyncMain<xdg_surface::XdgSurface>,
+    width: u32,
+    height: u32,
+}
+
+impl Popup {
+    pub fn new(
+        parent: &DesktopInstance,
+        anchor: PopupAnchor,
+        width: u32,
+        height: u32,
+        grab: Option<(&wl_seat::WlSeat, u32)>,
+    ) -> Option<Popup> {
+        let layer_surface = match parent.shell_surface {
+            ShellSurface::Layer(ref s) => s,
+            ShellSurface::Lock(_) => {
+                eprintln!("WARN: lock surfaces can't have popups");
+                return None;
+            }
+        };
+        let wm_base = match parent.env.get_global::<xdg_wm_base::XdgWmBase>() {
+            Some(wm_base) => wm_base,
+            None => {
+                eprintln!("WARN: no xdg_wm_base, can't show popups");
+                return None;
+            }
+        };
+        let wl_surface = parent.env.create_surface();
+        let viewport = parent
+            .env
+            .get_global::<wp_viewporter::WpViewporter>()
+            .filter(|_| parent.is_fractional())
+            .map(|viewporter| viewporter.get_viewport(&wl_surface));
+
+        let positioner = wm_base.create_positioner();
+        positioner.set_size(width.max(1) as _, height.max(1) as _);
+        positioner.set_anchor_rect(anchor.x, anchor.y, anchor.width.max(1), anchor.height.max(1));
+        positioner.set_anchor(xdg_positioner::Anchor::Top);
+        positioner.set_gravity(xdg_positioner::Gravity::Top);
+        positioner.set_constraint_adjustment(
+            (xdg_positioner::ConstraintAdjustment::SlideX
+                | xdg_positioner::ConstraintAdjustment::FlipY
+                | xdg_positioner::ConstraintAdjustment::ResizeY)
+                .bits(),
+        );
+
+        let xdg_surface = wm_base.get_xdg_surface(&wl_surface);
+        let xdg_popup = xdg_surface.get_popup(None, &positioner);
+        layer_surface.get_popup(&xdg_popup);
+        positioner.destroy();
+        if let Some((seat, serial)) = grab {
+            xdg_popup.grab(seat, serial);
+        }
+        wl_surface.commit();
+
+        Some(Popup {
+            wl_surface,
+            viewport,
+            xdg_popup: AsyncMain::new(xdg_popup, None),
+            xdg_surface: AsyncMain::new(xdg_surface, None),
+            width,
+            height,
+        })
+    }
+
+    pub async fn next(&mut self) -> PopupEvent {
+        loop {
+            let ev = futures::select! {
+                ev = self.xdg_popup.next() => future::Either::Left(ev),
+                ev = self.xdg_surface.next() => future::Either::Right(ev),
+            };
+            match ev {
+                future::Either::Left(xdg_popup::Event::Configure { width, height, .. }) => {
+                    if width > 0 && height > 0 {
+                        self.width = width as _;
+                        self.height = height as _;
+                    }
+                }
+                future::Either::Left(xdg_popup::Event::PopupDone) => return PopupEvent::Done,
+                future::Either::Right(xdg_surface::Event::Configure { serial }) => {
+                    self.xdg_surface.ack_configure(serial);
+                    return PopupEvent::Configured {
+                        width: self.width,
+                        height: self.height,
+                    };
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Same as `DesktopInstance::set_scale`, popups follow the scale of their parent.
+    pub fn set_scale(&self, scale: f64, width: u32, height: u32) {
+        if let Some(ref viewport) = self.viewport {
+            self.wl_surface.set_buffer_scale(1);
+            viewport.set_destination(width as i32, height as i32);
+        } else {
+            self.wl_surface.set_buffer_scale(scale as i32);
+        }
+    }
+}
+
+impl Drop for Popup {
+    fn drop(&mut self) {
+        // role objects have to go before the surface, and in this order
+        self.xdg_popup.destroy();
+        self.xdg_surface.destroy();
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+        self.wl_surface.destroy();
+    }
+}
@@ -0,0 +1,43 @@
+//! Protocols that are too new for wayland-protocols 0.28,
+//! generated by build.rs from the XML files in `protocols/`.
+
+macro_rules! generated_protocol {
+    ($name:ident, $file:expr, [$($import:ident),*]) => {
+        generated_protocol!($name, $file, [$($import),*], []);
+    };
+    // the second list is for interfaces of other protocols generated here
+    ($name:ident, $file:expr, [$($import:ident),*], [$($dep:ident :: $dep_iface:ident),*]) => {
+        pub mod $name {
+            #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+            #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+            #![allow(missing_docs, clippy::all)]
+
+            pub mod client {
+                pub(crate) use wayland_client::protocol::{$($import),*};
+                $(pub(crate) use crate::protocols::$dep::client::$dep_iface;)*
+                pub(crate) use wayland_client::{sys, AnonymousObject, Attached, Main, Proxy, ProxyMap};
+                pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+                pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+                pub(crate) use wayland_commons::{smallvec, Interface, MessageGroup};
+                include!(concat!(env!("OUT_DIR"), "/", $file, ".rs"));
+            }
+        }
+    };
+}
+
+generated_protocol!(fractional_scale_v1, "fractional-scale-v1", [wl_surface]);
+generated_protocol!(ext_foreign_toplevel_list_v1, "ext-foreign-toplevel-list-v1", []);
+generated_protocol!(ext_session_lock_v1, "ext-session-lock-v1", [wl_surface, wl_output]);
+generated_protocol!(ext_idle_notify_v1, "ext-idle-notify-v1", [wl_seat]);
+generated_protocol!(
+    ext_image_capture_source_v1,
+    "ext-image-capture-source-v1",
+    [wl_output],
+    [ext_foreign_toplevel_list_v1::ext_foreign_toplevel_handle_v1]
+);
+generated_protocol!(
+    ext_image_copy_capture_v1,
+    "ext-image-copy-capture-v1",
+    [wl_buffer, wl_pointer],
+    [ext_image_capture_source_v1::ext_image_capture_source_v1]
+);
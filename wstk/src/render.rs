@@ -0,0 +1,266 @@
+//! Rendering backends: wgpu when there's a working GPU, the CPU rasterizer into wl_shm otherwise.
+
+use iced_graphics::{
+    backend::{self, Image as _, Svg as _, Text as _},
+    window::Compositor,
+    Viewport,
+};
+use iced_native::{image, mouse, svg, Font, Rectangle, Size};
+use iced_wgpu::window::Compositor as WgpuCompositor;
+use smithay_client_toolkit::shm::AutoMemPool;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{handle::ToRWH, surfaces::*};
+
+pub mod software;
+
+/// Which backend an instance should render with.
+/// Selected at runtime with the `WSTK_RENDERER` env var (`wgpu`, `software`, or `auto`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererKind {
+    /// wgpu, falling back to software if no adapter could be found
+    Auto,
+    Wgpu,
+    Software,
+}
+
+impl RendererKind {
+    pub fn from_env() -> RendererKind {
+        match std::env::var("WSTK_RENDERER").as_ref().map(|s| s.as_str()) {
+            Ok("wgpu") => RendererKind::Wgpu,
+            Ok("software") | Ok("soft") | Ok("cpu") => RendererKind::Software,
+            Ok("auto") | Err(_) => RendererKind::Auto,
+            Ok(x) => {
+                eprintln!("WARN: unknown WSTK_RENDERER '{}', using auto", x);
+                RendererKind::Auto
+            }
+        }
+    }
+}
+
+pub enum Backend {
+    Wgpu(iced_wgpu::Renderer),
+    Software(software::Backend),
+}
+
+pub type Renderer = iced_graphics::Renderer<Backend>;
+
+impl Backend {
+    pub fn retain_raster(&mut self, handle: &image::Handle) {
+        match self {
+            Backend::Wgpu(r) => r.backend_mut().retain_raster(handle),
+            Backend::Software(b) => b.retain_raster(handle),
+        }
+    }
+
+    pub fn retain_vector(&mut self, handle: &svg::Handle) {
+        match self {
+            Backend::Wgpu(r) => r.backend_mut().retain_vector(handle),
+            Backend::Software(b) => b.retain_vector(handle),
+        }
+    }
+}
+
+impl iced_graphics::Backend for Backend {
+    fn trim_measurements(&mut self) {
+        match self {
+            Backend::Wgpu(r) => iced_graphics::Backend::trim_measurements(r.backend_mut()),
+            Backend::Software(b) => iced_graphics::Backend::trim_measurements(b),
+        }
+    }
+}
+
+impl backend::Text for Backend {
+    // Same icon font for both, so layouts don't depend on the backend
+    const ICON_FONT: Font = <iced_wgpu::Backend as backend::Text>::ICON_FONT;
+    const CHECKMARK_ICON: char = <iced_wgpu::Backend as backend::Text>::CHECKMARK_ICON;
+    const ARROW_DOWN_ICON: char = <iced_wgpu::Backend as backend::Text>::ARROW_DOWN_ICON;
+
+    fn default_size(&self) -> u16 {
+        match self {
+            Backend::Wgpu(r) => r.backend().default_size(),
+            Backend::Software(b) => b.default_size(),
+        }
+    }
+
+    fn measure(&self, contents: &str, size: f32, font: Font, bounds: Size) -> (f32, f32) {
+        match self {
+            Backend::Wgpu(r) => r.backend().measure(contents, size, font, bounds),
+            Backend::Software(b) => b.measure(contents, size, font, bounds),
+        }
+    }
+}
+
+impl backend::Image for Backend {
+    fn dimensions(&self, handle: &image::Handle) -> (u32, u32) {
+        match self {
+            Backend::Wgpu(r) => r.backend().dimensions(handle),
+            Backend::Software(b) => b.dimensions(handle),
+        }
+    }
+}
+
+impl backend::Svg for Backend {
+    fn viewport_dimensions(&self, handle: &svg::Handle) -> (u32, u32) {
+        match self {
+            Backend::Wgpu(r) => r.backend().viewport_dimensions(handle),
+            Backend::Software(b) => b.viewport_dimensions(handle),
+        }
+    }
+}
+
+/// Where the rendered frames go: a wgpu swapchain, or wl_shm buffers filled by the CPU.
+pub enum Target {
+    Wgpu {
+        /// Shared with the targets of an instance's other surfaces, so they can use the same Renderer
+        compositor: Rc<RefCell<WgpuCompositor>>,
+        gpu_surface: <WgpuCompositor as Compositor>::Surface,
+        swap_chain: Option<<WgpuCompositor as Compositor>::SwapChain>,
+    },
+    Software {
+        wl_surface: Attached<wl_surface::WlSurface>,
+        pool: AutoMemPool,
+        canvas: Option<software::Canvas>,
+    },
+}
+
+impl Target {
+    pub async fn new(kind: RendererKind, parent: &DesktopInstance) -> (Target, Renderer) {
+        if kind != RendererKind::Software {
+            let rwh = parent.raw_handle();
+            if let Some(mut compositor) = WgpuCompositor::request(
+                iced_wgpu::Settings {
+                    ..iced_wgpu::Settings::default()
+                },
+                Some(&rwh),
+            )
+            .await
+            {
+                let renderer = iced_wgpu::Renderer::new(compositor.create_backend());
+                let gpu_surface = compositor.create_surface(&rwh);
+                return (
+                    Target::Wgpu {
+                        compositor: Rc::new(RefCell::new(compositor)),
+                        gpu_surface,
+                        swap_chain: None,
+                    },
+                    Renderer::new(Backend::Wgpu(renderer)),
+                );
+            }
+            if kind == RendererKind::Wgpu {
+                panic!("Could not get a wgpu adapter, and software rendering was not allowed");
+            }
+            eprintln!("WARN: could not get a wgpu adapter, falling back to software rendering");
+        }
+        (
+            Target::software(&parent.env, &parent.wl_surface),
+            Renderer::new(Backend::Software(software::Backend::new())),
+        )
+    }
+
+    fn software(env: &Environment<Env>, wl_surface: &Attached<wl_surface::WlSurface>) -> Target {
+        Target::Software {
+            wl_surface: wl_surface.clone(),
+            pool: env.create_auto_pool().expect("Could not create a shm pool"),
+            canvas: None,
+        }
+    }
+
+    /// A target for another surface (e.g. a popup) that works with the same Renderer as this one.
+    pub fn for_surface(
+        &self,
+        env: &Environment<Env>,
+        display: &Display,
+        wl_surface: &Attached<wl_surface::WlSurface>,
+    ) -> Target {
+        match self {
+            Target::Wgpu { compositor, .. } => {
+                let rwh = ToRWH((*wl_surface.as_ref()).clone(), (**display).clone());
+                let gpu_surface = compositor.borrow_mut().create_surface(&rwh);
+                Target::Wgpu {
+                    compositor: compositor.clone(),
+                    gpu_surface,
+                    swap_chain: None,
+                }
+            }
+            Target::Software { .. } => Target::software(env, wl_surface),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        match self {
+            Target::Wgpu { swap_chain, .. } => swap_chain.is_some(),
+            Target::Software { canvas, .. } => canvas.is_some(),
+        }
+    }
+
+    /// (Re)creates the buffers for the given physical size.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        match self {
+            Target::Wgpu {
+                compositor,
+                gpu_surface,
+                swap_chain,
+            } => *swap_chain = Some(compositor.borrow_mut().create_swap_chain(gpu_surface, width, height)),
+            Target::Software { canvas, .. } => *canvas = Some(software::Canvas::new(width, height)),
+        }
+    }
+
+    /// Draws and presents the output of a UI draw.
+    /// `damage` is what changed since the previous one, in buffer pixels.
+    pub fn draw(
+        &mut self,
+        renderer: &mut Renderer,
+        viewport: &Viewport,
+        output: &(iced_graphics::Primitive, mouse::Interaction),
+        damage: &[Rectangle<u32>],
+    ) -> mouse::Interaction {
+        match (self, renderer.backend_mut()) {
+            // the swapchain presents (and commits) by itself, always the whole surface
+            (
+                Target::Wgpu {
+                    compositor,
+                    swap_chain: Some(swap_chain),
+                    ..
+                },
+                Backend::Wgpu(renderer),
+            ) => compositor.borrow_mut().draw::<String>(
+                renderer,
+                swap_chain,
+                viewport,
+                iced_core::Color::TRANSPARENT,
+                output,
+                &[],
+            ),
+            (
+                Target::Software {
+                    wl_surface,
+                    pool,
+                    canvas: Some(canvas),
+                },
+                Backend::Software(backend),
+            ) => {
+                let interaction = backend.draw(canvas, viewport, output, damage);
+                let (width, height) = (canvas.width() as i32, canvas.height() as i32);
+                // the pool hands out whatever memory is free, so the whole canvas is copied, but the compositor
+                // only has to look at (and e.g. re-upload to the GPU) what's damaged
+                match pool.buffer(width, height, width * 4, wl_shm::Format::Argb8888) {
+                    Ok((mem, buffer)) => {
+                        canvas.write_argb8888(mem);
+                        wl_surface.attach(Some(&buffer), 0, 0);
+                        for rect in damage {
+                            wl_surface.damage_buffer(rect.x as _, rect.y as _, rect.width as _, rect.height as _);
+                        }
+                        wl_surface.commit();
+                    }
+                    Err(e) => eprintln!("Could not allocate a shm buffer: {:?}", e),
+                }
+                interaction
+            }
+            _ => {
+                eprintln!("WARN: render target not ready or mismatched with backend");
+                output.1
+            }
+        }
+    }
+}
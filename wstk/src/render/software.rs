@@ -0,0 +1,622 @@
+//! CPU rasterizer for iced primitives, for machines without a usable GPU.
+//! Draws into a premultiplied RGBA canvas, so it keeps the same transparency
+//! behavior as the patched wgpu swapchain.
+
+use glyph_brush::{ab_glyph, GlyphCruncher};
+use iced_graphics::{backend, font, layer, Layer, Viewport};
+use iced_native::{image, mouse, svg, Font, Rectangle, Size};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::{Rc, Weak},
+};
+
+/// Premultiplied RGBA pixels that can be blitted onto a canvas.
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+pub struct Backend {
+    text: RefCell<glyph_brush::GlyphBrush<()>>,
+    fonts: RefCell<HashMap<&'static str, glyph_brush::FontId>>,
+    rasters: RefCell<HashMap<u64, Option<Pixels>>>,
+    /// Rasters retained or looked up since the last draw
+    used_rasters: RefCell<HashSet<u64>>,
+    vectors: RefCell<HashMap<u64, Option<usvg::Tree>>>,
+    vector_rasters: HashMap<(u64, u32, u32), Option<Pixels>>,
+    /// Vectors retained since the last draw, at any size
+    used_vectors: HashSet<u64>,
+    /// What's on the canvases drawn so far. Images that are neither there nor used get dropped
+    /// after a draw (e.g. replaced window thumbnails would pile up otherwise)
+    canvases: Vec<Weak<RefCell<CanvasContent>>>,
+}
+
+impl Backend {
+    pub fn new() -> Backend {
+        let default_font = font::Source::new()
+            .load(&[font::Family::SansSerif, font::Family::Serif])
+            .ok()
+            .and_then(|bytes| ab_glyph::FontArc::try_from_vec(bytes).ok())
+            .unwrap_or_else(|| ab_glyph::FontArc::try_from_slice(font::FALLBACK).unwrap());
+        Backend::with_font(default_font)
+    }
+
+    /// Always with the font bundled with iced, for renders that look the same on every machine
+    pub fn with_bundled_font() -> Backend {
+        Backend::with_font(ab_glyph::FontArc::try_from_slice(font::FALLBACK).unwrap())
+    }
+
+    fn with_font(default_font: ab_glyph::FontArc) -> Backend {
+        Backend {
+            text: RefCell::new(glyph_brush::GlyphBrushBuilder::using_font(default_font).build()),
+            fonts: RefCell::new(HashMap::new()),
+            rasters: RefCell::new(HashMap::new()),
+            used_rasters: RefCell::new(HashSet::new()),
+            vectors: RefCell::new(HashMap::new()),
+            vector_rasters: HashMap::new(),
+            used_vectors: HashSet::new(),
+            canvases: Vec::new(),
+        }
+    }
+
+    fn find_font(&self, font: Font) -> glyph_brush::FontId {
+        match font {
+            Font::Default => glyph_brush::FontId(0),
+            Font::External { name, bytes } => {
+                if let Some(id) = self.fonts.borrow().get(name) {
+                    return *id;
+                }
+                let id = match ab_glyph::FontArc::try_from_slice(bytes) {
+                    Ok(f) => self.text.borrow_mut().add_font(f),
+                    Err(_) => {
+                        eprintln!("WARN: could not load font '{}'", name);
+                        glyph_brush::FontId(0)
+                    }
+                };
+                self.fonts.borrow_mut().insert(name, id);
+                id
+            }
+        }
+    }
+
+    fn section<'a>(&self, content: &'a str, size: f32, font: Font, bounds: Size) -> glyph_brush::Section<'a> {
+        glyph_brush::Section {
+            bounds: (bounds.width, bounds.height),
+            text: vec![glyph_brush::Text::new(content)
+                .with_scale(size)
+                .with_font_id(self.find_font(font))],
+            ..Default::default()
+        }
+    }
+
+    pub fn retain_raster(&mut self, handle: &image::Handle) {
+        let _ = self.raster(handle);
+    }
+
+    pub fn retain_vector(&mut self, handle: &svg::Handle) {
+        let _ = self.vector_size(handle);
+        self.used_vectors.insert(handle.id());
+    }
+
+    fn raster(&self, handle: &image::Handle) -> Option<std::cell::Ref<'_, Pixels>> {
+        if !self.rasters.borrow().contains_key(&handle.id()) {
+            let pixels = load_raster(handle.data());
+            if pixels.is_none() {
+                eprintln!("WARN: could not load image {:?}", handle.data());
+            }
+            self.rasters.borrow_mut().insert(handle.id(), pixels);
+        }
+        self.used_rasters.borrow_mut().insert(handle.id());
+        std::cell::Ref::filter_map(self.rasters.borrow(), |r| r.get(&handle.id()).and_then(|p| p.as_ref())).ok()
+    }
+
+    fn vector_size(&self, handle: &svg::Handle) -> Option<(u32, u32)> {
+        if !self.vectors.borrow().contains_key(&handle.id()) {
+            let opts = usvg::Options::default();
+            let tree = match handle.data() {
+                svg::Data::Path(path) => usvg::Tree::from_file(path, &opts).ok(),
+                svg::Data::Bytes(bytes) => usvg::Tree::from_data(bytes, &opts).ok(),
+            };
+            self.vectors.borrow_mut().insert(handle.id(), tree);
+        }
+        self.vectors
+            .borrow()
+            .get(&handle.id())
+            .and_then(|t| t.as_ref())
+            .map(|t| {
+                let size = t.svg_node().size;
+                (size.width() as u32, size.height() as u32)
+            })
+    }
+
+    fn vector_raster(&mut self, handle: &svg::Handle, width: u32, height: u32) -> Option<&Pixels> {
+        let _ = self.vector_size(handle);
+        let key = (handle.id(), width, height);
+        if !self.vector_rasters.contains_key(&key) {
+            let pixels = self
+                .vectors
+                .borrow()
+                .get(&handle.id())
+                .and_then(|t| t.as_ref())
+                .and_then(|tree| {
+                    let mut img = tiny_skia::Pixmap::new(width, height)?;
+                    resvg::render(
+                        tree,
+                        if width > height {
+                            usvg::FitTo::Width(width)
+                        } else {
+                            usvg::FitTo::Height(height)
+                        },
+                        img.as_mut(),
+                    )?;
+                    Some(Pixels {
+                        width,
+                        height,
+                        data: img.data().to_vec(),
+                    })
+                });
+            self.vector_rasters.insert(key, pixels);
+        }
+        self.vector_rasters.get(&key).and_then(|p| p.as_ref())
+    }
+
+    /// Rasterizes the output of a UI draw onto the canvas, which must be sized for the viewport.
+    /// Only the damaged rectangles (in canvas pixels) are redrawn, the rest is kept from the previous draw.
+    pub fn draw(
+        &mut self,
+        canvas: &mut Canvas,
+        viewport: &Viewport,
+        (primitive, mouse_interaction): &(iced_graphics::Primitive, mouse::Interaction),
+        damage: &[Rectangle<u32>],
+    ) -> mouse::Interaction {
+        let scale = viewport.scale_factor() as f32;
+        let layers = Layer::generate(primitive, viewport);
+        for rect in damage {
+            let damage_clip = canvas.clip_rect(Rectangle {
+                x: rect.x as f32,
+                y: rect.y as f32,
+                width: rect.width as f32,
+                height: rect.height as f32,
+            });
+            if damage_clip.is_empty() {
+                continue;
+            }
+            canvas.clear_rect(&damage_clip);
+            for layer in layers.iter() {
+                self.draw_layer(canvas, layer, scale, &damage_clip);
+            }
+        }
+        self.evict(canvas, &layers, scale);
+        *mouse_interaction
+    }
+
+    /// Records the images of the whole frame (not only the damaged parts) as the canvas's content,
+    /// then drops the cached images that are on no canvas and weren't used since the last draw.
+    fn evict(&mut self, canvas: &Canvas, layers: &[Layer<'_>], scale: f32) {
+        {
+            let mut content = canvas.content.borrow_mut();
+            content.rasters.clear();
+            content.vectors.clear();
+            for img in layers.iter().flat_map(|l| l.images.iter()) {
+                match img {
+                    layer::Image::Raster { handle, .. } => {
+                        content.rasters.insert(handle.id());
+                    }
+                    layer::Image::Vector { handle, bounds } => {
+                        let phys = *bounds * scale;
+                        content
+                            .vectors
+                            .insert((handle.id(), phys.width.round() as u32, phys.height.round() as u32));
+                    }
+                }
+            }
+        }
+        let weak = Rc::downgrade(&canvas.content);
+        if !self.canvases.iter().any(|c| c.ptr_eq(&weak)) {
+            self.canvases.push(weak);
+        }
+        let contents = self.canvases.iter().filter_map(Weak::upgrade).collect::<Vec<_>>();
+        self.canvases.retain(|c| c.upgrade().is_some());
+
+        let used = std::mem::take(&mut *self.used_rasters.borrow_mut());
+        self.rasters
+            .borrow_mut()
+            .retain(|id, _| used.contains(id) || contents.iter().any(|c| c.borrow().rasters.contains(id)));
+        let used = std::mem::take(&mut self.used_vectors);
+        self.vector_rasters
+            .retain(|key, _| used.contains(&key.0) || contents.iter().any(|c| c.borrow().vectors.contains(key)));
+    }
+
+    fn draw_layer(&mut self, canvas: &mut Canvas, layer: &Layer<'_>, scale: f32, damage_clip: &ClipRect) {
+        let clip = damage_clip.intersect(&canvas.clip_rect(layer.bounds * scale));
+        if clip.is_empty() {
+            return;
+        }
+        for quad in layer.quads.iter() {
+            canvas.fill_quad(quad, scale, &clip);
+        }
+        for mesh in layer.meshes.iter() {
+            canvas.fill_mesh(
+                mesh,
+                scale,
+                &clip.intersect(&canvas.clip_rect(mesh.clip_bounds * scale)),
+            );
+        }
+        for img in layer.images.iter() {
+            match img {
+                layer::Image::Raster { handle, bounds } => {
+                    if let Some(pixels) = self.raster(handle) {
+                        canvas.blit(&pixels, *bounds * scale, &clip);
+                    }
+                }
+                layer::Image::Vector { handle, bounds } => {
+                    let phys = *bounds * scale;
+                    if let Some(pixels) =
+                        self.vector_raster(handle, phys.width.round() as u32, phys.height.round() as u32)
+                    {
+                        canvas.blit(pixels, phys, &clip);
+                    }
+                }
+            }
+        }
+        for text in layer.text.iter() {
+            self.draw_text(canvas, text, scale, &clip);
+        }
+    }
+
+    fn draw_text(&self, canvas: &mut Canvas, text: &layer::Text<'_>, scale: f32, clip: &ClipRect) {
+        use ab_glyph::Font as _;
+        use iced_native::{HorizontalAlignment, VerticalAlignment};
+
+        let font_id = self.find_font(text.font);
+        let section = glyph_brush::Section {
+            screen_position: (text.bounds.x * scale, text.bounds.y * scale),
+            bounds: (text.bounds.width * scale, text.bounds.height * scale),
+            text: vec![glyph_brush::Text::new(text.content)
+                .with_scale(text.size * scale)
+                .with_font_id(font_id)],
+            layout: glyph_brush::Layout::default()
+                .h_align(match text.horizontal_alignment {
+                    HorizontalAlignment::Left => glyph_brush::HorizontalAlign::Left,
+                    HorizontalAlignment::Center => glyph_brush::HorizontalAlign::Center,
+                    HorizontalAlignment::Right => glyph_brush::HorizontalAlign::Right,
+                })
+                .v_align(match text.vertical_alignment {
+                    VerticalAlignment::Top => glyph_brush::VerticalAlign::Top,
+                    VerticalAlignment::Center => glyph_brush::VerticalAlign::Center,
+                    VerticalAlignment::Bottom => glyph_brush::VerticalAlign::Bottom,
+                }),
+        };
+        let color = premultiply(text.color);
+        let mut brush = self.text.borrow_mut();
+        let glyphs = brush.glyphs(section).cloned().collect::<Vec<_>>();
+        for sg in glyphs {
+            let font = &brush.fonts()[sg.font_id.0];
+            if let Some(outline) = font.outline_glyph(sg.glyph) {
+                let b = outline.px_bounds();
+                outline.draw(|x, y, coverage| {
+                    canvas.blend_pixel(
+                        b.min.x as i32 + x as i32,
+                        b.min.y as i32 + y as i32,
+                        color,
+                        coverage,
+                        clip,
+                    );
+                });
+            }
+        }
+    }
+}
+
+impl iced_graphics::Backend for Backend {}
+
+impl backend::Text for Backend {
+    const ICON_FONT: Font = font::ICONS;
+    const CHECKMARK_ICON: char = font::CHECKMARK_ICON;
+    const ARROW_DOWN_ICON: char = font::ARROW_DOWN_ICON;
+
+    fn default_size(&self) -> u16 {
+        20
+    }
+
+    fn measure(&self, contents: &str, size: f32, font: Font, bounds: Size) -> (f32, f32) {
+        let section = self.section(contents, size, font, bounds);
+        if let Some(bounds) = self.text.borrow_mut().glyph_bounds(section) {
+            (bounds.width().ceil(), bounds.height().ceil())
+        } else {
+            (0.0, 0.0)
+        }
+    }
+}
+
+impl backend::Image for Backend {
+    fn dimensions(&self, handle: &image::Handle) -> (u32, u32) {
+        self.raster(handle).map(|p| (p.width, p.height)).unwrap_or((1, 1))
+    }
+}
+
+impl backend::Svg for Backend {
+    fn viewport_dimensions(&self, handle: &svg::Handle) -> (u32, u32) {
+        self.vector_size(handle).unwrap_or((1, 1))
+    }
+}
+
+fn load_raster(data: &image::Data) -> Option<Pixels> {
+    let (width, height, mut data) = match data {
+        image::Data::Path(path) => {
+            let img = ::image::open(path).ok()?.to_rgba8();
+            (img.width(), img.height(), img.into_raw())
+        }
+        image::Data::Bytes(bytes) => {
+            let img = ::image::load_from_memory(bytes).ok()?.to_rgba8();
+            (img.width(), img.height(), img.into_raw())
+        }
+        image::Data::Pixels { width, height, pixels } => {
+            let mut rgba = pixels.clone();
+            for px in rgba.chunks_exact_mut(4) {
+                px.swap(0, 2);
+            }
+            (*width, *height, rgba)
+        }
+    };
+    for px in data.chunks_exact_mut(4) {
+        let a = px[3] as u32;
+        px[0] = (px[0] as u32 * a / 255) as u8;
+        px[1] = (px[1] as u32 * a / 255) as u8;
+        px[2] = (px[2] as u32 * a / 255) as u8;
+    }
+    Some(Pixels { width, height, data })
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts a linear straight-alpha color (as found in layers) to premultiplied sRGB.
+fn premultiply(linear: [f32; 4]) -> [f32; 4] {
+    let a = linear[3];
+    [
+        linear_to_srgb(linear[0]) * a,
+        linear_to_srgb(linear[1]) * a,
+        linear_to_srgb(linear[2]) * a,
+        a,
+    ]
+}
+
+/// Integer pixel rectangle, in physical coordinates, exclusive at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClipRect {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl ClipRect {
+    pub fn is_empty(&self) -> bool {
+        self.x0 >= self.x1 || self.y0 >= self.y1
+    }
+
+    pub fn intersect(&self, other: &ClipRect) -> ClipRect {
+        ClipRect {
+            x0: self.x0.max(other.x0),
+            y0: self.y0.max(other.y0),
+            x1: self.x1.min(other.x1),
+            y1: self.y1.min(other.y1),
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+}
+
+/// Images on a canvas as of its last draw, which the backend keeps cached while the canvas is alive.
+#[derive(Default)]
+struct CanvasContent {
+    rasters: HashSet<u64>,
+    vectors: HashSet<(u64, u32, u32)>,
+}
+
+/// A premultiplied RGBA pixel buffer.
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    content: Rc<RefCell<CanvasContent>>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            data: vec![0; (width * height * 4) as usize],
+            content: Default::default(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Premultiplied RGBA bytes, row by row with no padding.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn clear(&mut self) {
+        for b in self.data.iter_mut() {
+            *b = 0;
+        }
+    }
+
+    fn clear_rect(&mut self, rect: &ClipRect) {
+        for y in rect.y0..rect.y1 {
+            let row = (y as u32 * self.width) as usize * 4;
+            for b in self.data[row + rect.x0 as usize * 4..row + rect.x1 as usize * 4].iter_mut() {
+                *b = 0;
+            }
+        }
+    }
+
+    /// Copies the canvas into a wl_shm ARGB8888 buffer (BGRA in memory on little endian).
+    pub fn write_argb8888(&self, dst: &mut [u8]) {
+        for (d, s) in dst.chunks_exact_mut(4).zip(self.data.chunks_exact(4)) {
+            d[0] = s[2];
+            d[1] = s[1];
+            d[2] = s[0];
+            d[3] = s[3];
+        }
+    }
+
+    fn clip_rect(&self, r: Rectangle) -> ClipRect {
+        ClipRect {
+            x0: (r.x.floor() as i32).max(0),
+            y0: (r.y.floor() as i32).max(0),
+            x1: ((r.x + r.width).ceil() as i32).min(self.width as i32),
+            y1: ((r.y + r.height).ceil() as i32).min(self.height as i32),
+        }
+    }
+
+    fn blend_pixel(&mut self, x: i32, y: i32, color: [f32; 4], coverage: f32, clip: &ClipRect) {
+        if !clip.contains(x, y) || coverage <= 0.0 {
+            return;
+        }
+        let coverage = coverage.min(1.0);
+        let i = ((y as u32 * self.width + x as u32) * 4) as usize;
+        let sa = color[3] * coverage;
+        let inv = 1.0 - sa;
+        for c in 0..3 {
+            let dst = self.data[i + c] as f32 / 255.0;
+            self.data[i + c] = ((color[c] * coverage + dst * inv) * 255.0).round().min(255.0) as u8;
+        }
+        let da = self.data[i + 3] as f32 / 255.0;
+        self.data[i + 3] = ((sa + da * inv) * 255.0).round().min(255.0) as u8;
+    }
+
+    fn fill_quad(&mut self, quad: &layer::Quad, scale: f32, clip: &ClipRect) {
+        let (x, y) = (quad.position[0] * scale, quad.position[1] * scale);
+        let (w, h) = (quad.size[0] * scale, quad.size[1] * scale);
+        let radius = (quad.border_radius * scale).min(w / 2.0).min(h / 2.0);
+        let border = quad.border_width * scale;
+        let fill = premultiply(quad.color);
+        let stroke = premultiply(quad.border_color);
+        let area = clip.intersect(&self.clip_rect(Rectangle {
+            x,
+            y,
+            width: w,
+            height: h,
+        }));
+        let (cx, cy) = (x + w / 2.0, y + h / 2.0);
+
+        for py in area.y0..area.y1 {
+            for px in area.x0..area.x1 {
+                let (dx, dy) = (px as f32 + 0.5 - cx, py as f32 + 0.5 - cy);
+                let outer = rounded_box_distance(dx, dy, w / 2.0, h / 2.0, radius);
+                let coverage = (0.5 - outer).max(0.0).min(1.0);
+                if coverage <= 0.0 {
+                    continue;
+                }
+                let color = if border > 0.0 {
+                    let inner =
+                        rounded_box_distance(dx, dy, w / 2.0 - border, h / 2.0 - border, (radius - border).max(0.0));
+                    let t = (0.5 - inner).max(0.0).min(1.0);
+                    [
+                        stroke[0] + (fill[0] - stroke[0]) * t,
+                        stroke[1] + (fill[1] - stroke[1]) * t,
+                        stroke[2] + (fill[2] - stroke[2]) * t,
+                        stroke[3] + (fill[3] - stroke[3]) * t,
+                    ]
+                } else {
+                    fill
+                };
+                self.blend_pixel(px, py, color, coverage, &area);
+            }
+        }
+    }
+
+    fn fill_mesh(&mut self, mesh: &layer::Mesh<'_>, scale: f32, clip: &ClipRect) {
+        let vs = &mesh.buffers.vertices;
+        for tri in mesh.buffers.indices.chunks_exact(3) {
+            let [a, b, c] = [vs[tri[0] as usize], vs[tri[1] as usize], vs[tri[2] as usize]];
+            let pos = |v: &iced_graphics::triangle::Vertex2D| {
+                (
+                    (mesh.origin.x + v.position[0]) * scale,
+                    (mesh.origin.y + v.position[1]) * scale,
+                )
+            };
+            let (pa, pb, pc) = (pos(&a), pos(&b), pos(&c));
+            let area = (pb.0 - pa.0) * (pc.1 - pa.1) - (pc.0 - pa.0) * (pb.1 - pa.1);
+            if area.abs() < std::f32::EPSILON {
+                continue;
+            }
+            let bbox = clip.intersect(&self.clip_rect(Rectangle {
+                x: pa.0.min(pb.0).min(pc.0),
+                y: pa.1.min(pb.1).min(pc.1),
+                width: pa.0.max(pb.0).max(pc.0) - pa.0.min(pb.0).min(pc.0),
+                height: pa.1.max(pb.1).max(pc.1) - pa.1.min(pb.1).min(pc.1),
+            }));
+            for py in bbox.y0..bbox.y1 {
+                for px in bbox.x0..bbox.x1 {
+                    let (x, y) = (px as f32 + 0.5, py as f32 + 0.5);
+                    let w0 = ((pb.0 - x) * (pc.1 - y) - (pc.0 - x) * (pb.1 - y)) / area;
+                    let w1 = ((pc.0 - x) * (pa.1 - y) - (pa.0 - x) * (pc.1 - y)) / area;
+                    let w2 = 1.0 - w0 - w1;
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+                    let mut linear = [0.0; 4];
+                    for (i, l) in linear.iter_mut().enumerate() {
+                        *l = a.color[i] * w0 + b.color[i] * w1 + c.color[i] * w2;
+                    }
+                    self.blend_pixel(px, py, premultiply(linear), 1.0, &bbox);
+                }
+            }
+        }
+    }
+
+    fn blit(&mut self, src: &Pixels, dst: Rectangle, clip: &ClipRect) {
+        if src.width == 0 || src.height == 0 || dst.width <= 0.0 || dst.height <= 0.0 {
+            return;
+        }
+        let area = clip.intersect(&self.clip_rect(dst));
+        let (sx, sy) = (src.width as f32 / dst.width, src.height as f32 / dst.height);
+        for py in area.y0..area.y1 {
+            for px in area.x0..area.x1 {
+                // bilinear sampling of premultiplied pixels
+                let u = ((px as f32 + 0.5 - dst.x) * sx - 0.5).max(0.0);
+                let v = ((py as f32 + 0.5 - dst.y) * sy - 0.5).max(0.0);
+                let (x0, y0) = ((u as u32).min(src.width - 1), (v as u32).min(src.height - 1));
+                let (x1, y1) = ((x0 + 1).min(src.width - 1), (y0 + 1).min(src.height - 1));
+                let (fx, fy) = (u - x0 as f32, v - y0 as f32);
+                let at = |x: u32, y: u32, c: usize| src.data[((y * src.width + x) * 4) as usize + c] as f32 / 255.0;
+                let mut color = [0.0; 4];
+                for (c, out) in color.iter_mut().enumerate() {
+                    let top = at(x0, y0, c) * (1.0 - fx) + at(x1, y0, c) * fx;
+                    let bottom = at(x0, y1, c) * (1.0 - fx) + at(x1, y1, c) * fx;
+                    *out = top * (1.0 - fy) + bottom * fy;
+                }
+                self.blend_pixel(px, py, color, 1.0, &area);
+            }
+        }
+    }
+}
+
+/// Signed distance from a point (relative to the center) to a rounded box.
+fn rounded_box_distance(x: f32, y: f32, half_w: f32, half_h: f32, radius: f32) -> f32 {
+    let qx = x.abs() - half_w + radius;
+    let qy = y.abs() - half_h + radius;
+    let outside = (qx.max(0.0).powi(2) + qy.max(0.0).powi(2)).sqrt();
+    outside + qx.max(qy).min(0.0) - radius
+}
@@ -0,0 +1,172 @@
+use futures::channel::mpsc;
+
+use crate::{iced::SurfaceRole, ime::*, keys::*, surfaces::*};
+
+/// An input event, tagged with the seat that produced it by `SeatInput`.
+#[derive(Debug)]
+pub enum SeatEvent {
+    Keyboard(KeyboardEvent),
+    Pointer(wl_pointer::Event),
+    Touch(wl_touch::Event),
+    TextInput(ImeEvent),
+}
+
+pub type SeatEventSender = mpsc::UnboundedSender<(wl_seat::WlSeat, SeatEvent)>;
+
+fn send_event(tx: &SeatEventSender, seat: &wl_seat::WlSeat, event: SeatEvent) {
+    if let Err(e) = tx.unbounded_send((seat.clone(), event)) {
+        if !e.is_disconnected() {
+            panic!("Unexpected send error {:?}", e)
+        }
+    }
+}
+
+/// The input devices of one seat on one instance, plus the per-seat focus state.
+/// All seats of an instance send their events into one channel.
+pub struct SeatInput {
+    pub seat: Attached<wl_seat::WlSeat>,
+    keyboard: Option<Main<wl_keyboard::WlKeyboard>>,
+    pointer: Option<Main<wl_pointer::WlPointer>>,
+    pub themed_ptr: Option<pointer::ThemedPointer>,
+    touch: Option<Main<wl_touch::WlTouch>>,
+    /// Only there if the compositor supports input methods
+    pub text_input: Option<TextInput>,
+
+    /// Which of the instance's surfaces the device is on
+    pub ptr_inside: Option<SurfaceRole>,
+    pub kb_focused: Option<SurfaceRole>,
+    pub touching: Option<SurfaceRole>,
+    pub ime_focused: Option<SurfaceRole>,
+    pub last_ptr_serial: Option<u32>,
+    pub repeat_info: RepeatInfo,
+}
+
+/// Which input devices a seat currently has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SeatCaps {
+    pub keyboard: bool,
+    pub pointer: bool,
+    pub touch: bool,
+}
+
+impl SeatCaps {
+    pub fn from_data(data: &seat::SeatData) -> SeatCaps {
+        SeatCaps {
+            keyboard: data.has_keyboard,
+            pointer: data.has_pointer,
+            touch: data.has_touch,
+        }
+    }
+
+    pub fn of(seat: &wl_seat::WlSeat) -> SeatCaps {
+        with_seat_data(seat, SeatCaps::from_data).unwrap_or_default()
+    }
+}
+
+impl SeatInput {
+    pub fn new(
+        seat: &Attached<wl_seat::WlSeat>,
+        theme_mgr: &pointer::ThemeManager,
+        text_input_mgr: Option<&Attached<text_input_manager::ZwpTextInputManagerV3>>,
+        tx: &SeatEventSender,
+    ) -> SeatInput {
+        let text_input = text_input_mgr.map(|mgr| {
+            let (tx, seat_id) = (tx.clone(), seat.detach());
+            TextInput::new(mgr, seat, move |event| {
+                send_event(&tx, &seat_id, SeatEvent::TextInput(event))
+            })
+        });
+        let mut input = SeatInput {
+            seat: seat.clone(),
+            keyboard: None,
+            pointer: None,
+            themed_ptr: None,
+            touch: None,
+            text_input,
+            ptr_inside: None,
+            kb_focused: None,
+            touching: None,
+            ime_focused: None,
+            last_ptr_serial: None,
+            repeat_info: RepeatInfo::default(),
+        };
+        input.update_caps(SeatCaps::of(seat), theme_mgr, tx);
+        input
+    }
+
+    pub fn is(&self, seat: &wl_seat::WlSeat) -> bool {
+        self.seat.detach() == *seat
+    }
+
+    /// Creates the objects for devices that appeared and releases the ones for devices that are gone.
+    /// Focus on a released device is dropped, the instance has to notice and deliver the leave.
+    pub fn update_caps(&mut self, caps: SeatCaps, theme_mgr: &pointer::ThemeManager, tx: &SeatEventSender) {
+        let seat_id = self.seat.detach();
+
+        if caps.keyboard && self.keyboard.is_none() {
+            let (tx, seat_id) = (tx.clone(), seat_id.clone());
+            self.keyboard = Some(map_keyboard(&self.seat, move |event| {
+                send_event(&tx, &seat_id, SeatEvent::Keyboard(event))
+            }));
+        } else if !caps.keyboard {
+            self.release_keyboard();
+        }
+
+        if caps.pointer && self.pointer.is_none() {
+            let ptr = self.seat.get_pointer();
+            let (tx, seat_id) = (tx.clone(), seat_id.clone());
+            ptr.quick_assign(move |_, event, _| send_event(&tx, &seat_id, SeatEvent::Pointer(event)));
+            self.pointer = Some(ptr);
+            self.themed_ptr = Some(theme_mgr.theme_pointer(self.seat.get_pointer().detach()));
+        } else if !caps.pointer {
+            self.release_pointer();
+        }
+
+        if caps.touch && self.touch.is_none() {
+            let touch = self.seat.get_touch();
+            let tx = tx.clone();
+            touch.quick_assign(move |_, event, _| send_event(&tx, &seat_id, SeatEvent::Touch(event)));
+            self.touch = Some(touch);
+        } else if !caps.touch {
+            self.release_touch();
+        }
+    }
+
+    // XXX: quick_assign resets are for https://github.com/Smithay/wayland-rs/issues/358
+
+    fn release_keyboard(&mut self) {
+        if let Some(kb) = self.keyboard.take() {
+            kb.quick_assign(|_, _, _| ());
+            kb.release();
+        }
+        self.kb_focused = None;
+    }
+
+    fn release_pointer(&mut self) {
+        if let Some(ptr) = self.pointer.take() {
+            ptr.quick_assign(|_, _, _| ());
+            ptr.release();
+        }
+        if let Some(tptr) = self.themed_ptr.take() {
+            tptr.release();
+        }
+        self.ptr_inside = None;
+        self.last_ptr_serial = None;
+    }
+
+    fn release_touch(&mut self) {
+        if let Some(touch) = self.touch.take() {
+            touch.quick_assign(|_, _, _| ());
+            touch.release();
+        }
+        self.touching = None;
+    }
+}
+
+impl Drop for SeatInput {
+    fn drop(&mut self) {
+        self.release_keyboard();
+        self.release_pointer();
+        self.release_touch();
+    }
+}
@@ -0,0 +1,109 @@
+//! Locking the session with ext-session-lock-v1: while locked, the compositor shows nothing
+//! but our lock surfaces (one per output, see `IcedInstance::new_locked`) until we unlock.
+
+use std::{cell::Cell, rc::Rc};
+
+pub use crate::protocols::ext_session_lock_v1::client::{
+    ext_session_lock_manager_v1 as lock_manager, ext_session_lock_surface_v1 as lock_surface,
+    ext_session_lock_v1 as session_lock,
+};
+use crate::surfaces::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    /// Waiting for the compositor to answer
+    Pending,
+    Locked,
+    /// The compositor won't lock, e.g. because another client holds a lock
+    Refused,
+    /// The compositor ended the lock by itself, e.g. it has its own way to unlock
+    Finished,
+    Unlocked,
+}
+
+pub struct SessionLock {
+    lock: Main<session_lock::ExtSessionLockV1>,
+    state: Rc<Cell<LockState>>,
+    changed: Rc<event_listener::Event>,
+}
+
+impl SessionLock {
+    /// Asks the compositor to lock the session, None if it doesn't support locking.
+    /// Lock surfaces should be created right away, the compositor might wait for them to be drawn.
+    pub fn new(env: &Environment<Env>) -> Option<SessionLock> {
+        let manager = env.get_global::<lock_manager::ExtSessionLockManagerV1>()?;
+        let lock = manager.lock();
+        let state = Rc::new(Cell::new(LockState::Pending));
+        let changed = Rc::new(event_listener::Event::new());
+        {
+            let state = state.clone();
+            let changed = changed.clone();
+            lock.quick_assign(move |lock, event, _| {
+                match event {
+                    session_lock::Event::Locked => state.set(LockState::Locked),
+                    // the lock object is useless now, it's destroyed differently depending on whether it was locked
+                    session_lock::Event::Finished => match state.get() {
+                        LockState::Pending => {
+                            lock.destroy();
+                            state.set(LockState::Refused)
+                        }
+                        LockState::Locked => {
+                            lock.unlock_and_destroy();
+                            state.set(LockState::Finished)
+                        }
+                        _ => (),
+                    },
+                    x => eprintln!("WARN: unknown session lock event {:?}", x),
+                }
+                changed.notify(usize::MAX);
+            });
+        }
+        Some(SessionLock { lock, state, changed })
+    }
+
+    pub fn state(&self) -> LockState {
+        self.state.get()
+    }
+
+    async fn wait_while(&self, pred: impl Fn(LockState) -> bool) {
+        while pred(self.state.get()) {
+            let listener = self.changed.listen();
+            if !pred(self.state.get()) {
+                break;
+            }
+            listener.await;
+        }
+    }
+
+    /// Waits until the compositor answers the lock request, returns whether the session is now locked
+    pub async fn locked(&self) -> bool {
+        self.wait_while(|st| st == LockState::Pending).await;
+        self.state.get() == LockState::Locked
+    }
+
+    /// Waits until the compositor ends a lock by itself
+    pub async fn finished(&self) {
+        self.wait_while(|st| st != LockState::Finished).await;
+    }
+
+    /// Unlocks the session, the lock can't be used anymore afterwards.
+    /// The request must reach the compositor before exiting (see `wayland_sync`), or the session stays locked.
+    pub fn unlock(&self) {
+        match self.state.get() {
+            LockState::Locked => {
+                self.lock.unlock_and_destroy();
+                self.state.set(LockState::Unlocked);
+            }
+            LockState::Pending => eprintln!("WARN: can't unlock a session that's not locked yet"),
+            _ => (),
+        }
+    }
+
+    pub(crate) fn get_lock_surface(
+        &self,
+        wl_surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
+    ) -> Main<lock_surface::ExtSessionLockSurfaceV1> {
+        self.lock.get_lock_surface(wl_surface, output)
+    }
+}
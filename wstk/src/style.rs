@@ -4,6 +4,8 @@ use iced_graphics::*;
 
 pub const DARK_COLOR: Color = Color::from_rgba(0.0784, 0.0784, 0.0784, 0.85);
 pub const RUNNING_DARK_COLOR: Color = Color::from_rgba(0.1584, 0.1584, 0.1784, 0.85);
+pub const ACTIVE_DARK_COLOR: Color = Color::from_rgba(0.2384, 0.2384, 0.2784, 0.85);
+pub const HOVER_DARK_COLOR: Color = Color::from_rgba(0.3184, 0.3184, 0.3584, 0.85);
 pub const BRIGHT_COLOR: Color = Color::from_rgba(0.874, 0.874, 0.874, 0.85);
 pub const VERY_BRIGHT_COLOR: Color = Color::from_rgba(0.89, 0.89, 0.89, 0.98);
 pub const SEL_COLOR: Color = Color::from_rgba(0.8, 0.8, 0.99, 0.69);
@@ -19,6 +21,31 @@ impl container::StyleSheet for DarkBar {
     }
 }
 
+/// Black at the given opacity, e.g. for dimming everything behind it
+pub struct Shade(pub f32);
+
+impl container::StyleSheet for Shade {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: Some(Background::Color(Color::from_rgba(0.0, 0.0, 0.0, self.0))),
+            ..container::Style::default()
+        }
+    }
+}
+
+/// Fully opaque, for surfaces that must hide everything behind them (lock screens)
+pub struct Backdrop;
+
+impl container::StyleSheet for Backdrop {
+    fn style(&self) -> container::Style {
+        container::Style {
+            background: Some(Background::Color(Color::BLACK)),
+            text_color: Some(Color::WHITE),
+            ..container::Style::default()
+        }
+    }
+}
+
 pub struct Dock(pub Color);
 
 impl container::StyleSheet for Dock {
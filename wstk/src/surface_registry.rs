@@ -0,0 +1,163 @@
+//! Tracks which outputs our surfaces are on, per environment, and tells each surface
+//! about outputs entering and leaving and about the integer scale that follows from them.
+
+use crate::surfaces::*;
+use futures::channel::mpsc;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+#[derive(Debug, Clone)]
+pub enum SurfaceEvent {
+    /// The largest integer scale of the outputs, or the preferred fractional scale if supported
+    Scale(f64),
+    OutputEnter(wl_output::WlOutput),
+    OutputLeave(wl_output::WlOutput),
+}
+
+struct TrackedSurface {
+    tx: mpsc::UnboundedSender<SurfaceEvent>,
+    outputs: Vec<wl_output::WlOutput>,
+    scale: i32,
+    /// Surfaces with a fractional scale get it from the compositor instead
+    integer_scale: bool,
+}
+
+impl TrackedSurface {
+    fn send(&self, event: SurfaceEvent) {
+        // the receiver can only be gone while its instance is being dropped
+        let _ = self.tx.unbounded_send(event);
+    }
+
+    fn update_scale(&mut self) {
+        if self.outputs.is_empty() {
+            // not shown anywhere, keep the scale for when it comes back
+            return;
+        }
+        let scale = self
+            .outputs
+            .iter()
+            .map(|o| output::with_output_info(o, |info| info.scale_factor).unwrap_or(1))
+            .max()
+            .unwrap_or(1);
+        if scale != self.scale {
+            self.scale = scale;
+            if self.integer_scale {
+                self.send(SurfaceEvent::Scale(scale as f64));
+            }
+        }
+    }
+
+    fn leave(&mut self, output: &wl_output::WlOutput) {
+        if self.outputs.iter().any(|o| o == output) {
+            self.outputs.retain(|o| o != output);
+            self.send(SurfaceEvent::OutputLeave(output.clone()));
+            self.update_scale();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Keyed by protocol object id, which is only reused after we destroy the surface
+    surfaces: HashMap<u32, TrackedSurface>,
+    output_listener: Option<output::OutputStatusListener>,
+}
+
+/// Shared by all surfaces of an environment, see `DesktopInstance::new`.
+#[derive(Clone, Default)]
+pub struct SurfaceRegistry(Rc<RefCell<Registry>>);
+
+impl SurfaceRegistry {
+    /// Creates a surface whose events go to `tx` until it's unregistered.
+    pub fn create_surface(
+        &self,
+        env: &Environment<Env>,
+        tx: mpsc::UnboundedSender<SurfaceEvent>,
+        integer_scale: bool,
+    ) -> Attached<wl_surface::WlSurface> {
+        self.listen_for_outputs(env);
+        let surface = env.require_global::<wl_compositor::WlCompositor>().create_surface();
+        let registry = Rc::downgrade(&self.0);
+        surface.quick_assign(move |surface, event, _| {
+            let registry = match registry.upgrade() {
+                Some(registry) => registry,
+                None => return,
+            };
+            let mut registry = registry.borrow_mut();
+            let tracked = match registry.surfaces.get_mut(&surface.as_ref().id()) {
+                Some(tracked) => tracked,
+                None => return, // events racing with the destruction
+            };
+            match event {
+                wl_surface::Event::Enter { output } => {
+                    if !tracked.outputs.contains(&output) {
+                        tracked.outputs.push(output.clone());
+                        tracked.send(SurfaceEvent::OutputEnter(output));
+                        tracked.update_scale();
+                    }
+                }
+                wl_surface::Event::Leave { output } => tracked.leave(&output),
+                _ => (),
+            }
+        });
+        self.0.borrow_mut().surfaces.insert(
+            surface.as_ref().id(),
+            TrackedSurface {
+                tx,
+                outputs: Vec::new(),
+                scale: 1,
+                integer_scale,
+            },
+        );
+        surface.into()
+    }
+
+    /// Stops tracking the surface, call right before destroying it.
+    pub fn unregister(&self, surface: &wl_surface::WlSurface) {
+        if self.0.borrow_mut().surfaces.remove(&surface.as_ref().id()).is_none() {
+            eprintln!("WARN: unregistering an unknown surface");
+        }
+    }
+
+    /// The largest integer scale of the outputs the surface is (or was last) on
+    pub fn scale(&self, surface: &wl_surface::WlSurface) -> i32 {
+        self.0
+            .borrow()
+            .surfaces
+            .get(&surface.as_ref().id())
+            .map_or(1, |tracked| tracked.scale)
+    }
+
+    pub fn outputs(&self, surface: &wl_surface::WlSurface) -> Vec<wl_output::WlOutput> {
+        self.0
+            .borrow()
+            .surfaces
+            .get(&surface.as_ref().id())
+            .map_or_else(Vec::new, |tracked| tracked.outputs.clone())
+    }
+
+    /// Output scale changes and removals affect the scales of the surfaces on them
+    fn listen_for_outputs(&self, env: &Environment<Env>) {
+        if self.0.borrow().output_listener.is_some() {
+            return;
+        }
+        let registry: Weak<RefCell<Registry>> = Rc::downgrade(&self.0);
+        let listener = env.listen_for_outputs(move |output, info, _| {
+            let registry = match registry.upgrade() {
+                Some(registry) => registry,
+                None => return,
+            };
+            for tracked in registry.borrow_mut().surfaces.values_mut() {
+                if info.obsolete {
+                    tracked.leave(&output);
+                } else if tracked.outputs.contains(&output) {
+                    tracked.update_scale();
+                }
+            }
+        });
+        self.0.borrow_mut().output_listener = Some(listener);
+    }
+}
@@ -1,14 +1,16 @@
 pub use smithay_client_toolkit::{
     data_device,
     environment::{Environment, SimpleGlobal},
-    get_surface_scale_factor, output,
+    output, primary_selection,
     reexports::{
         client::{
             protocol::{
-                wl_compositor, wl_keyboard, wl_output, wl_pointer, wl_region, wl_seat, wl_shm, wl_surface, wl_touch,
+                wl_callback, wl_compositor, wl_keyboard, wl_output, wl_pointer, wl_region, wl_seat, wl_shm, wl_surface,
+                wl_touch,
             },
             Attached, ConnectError, Display, EventQueue, Interface, Main, Proxy,
         },
+        protocols::viewporter::client::{wp_viewport, wp_viewporter},
         protocols::wlr::unstable::foreign_toplevel::v1::client::{
             zwlr_foreign_toplevel_handle_v1 as toplevel_handle, zwlr_foreign_toplevel_manager_v1 as toplevel_manager,
         },
@@ -21,37 +23,230 @@ pub use smithay_client_toolkit::{
 use smithay_client_toolkit::{default_environment, new_default_environment};
 use std::rc::Rc;
 
-use futures::channel::mpsc;
 pub use futures::prelude::*;
+use futures::{channel::mpsc, future::FusedFuture};
 
-use crate::{event_loop::AsyncMain, handle::*, toplevels::*};
+pub use crate::protocols::fractional_scale_v1::client::{
+    wp_fractional_scale_manager_v1 as fractional_scale_manager, wp_fractional_scale_v1 as fractional_scale,
+};
+use crate::{
+    capture::*, clipboard::*, event_loop::AsyncMain, gamma::*, handle::*, idle::*, ime::*, output_config::*, popup::*,
+    session_lock::*, surface_registry::*, toplevels::*,
+};
 
 default_environment!(Env,
     fields = [
         layer_shell: SimpleGlobal<layer_shell::ZwlrLayerShellV1>,
+        viewporter: SimpleGlobal<wp_viewporter::WpViewporter>,
+        fractional_scale_manager: SimpleGlobal<fractional_scale_manager::WpFractionalScaleManagerV1>,
+        wm_base: WmBaseGlobal,
+        text_input_manager: SimpleGlobal<text_input_manager::ZwpTextInputManagerV3>,
+        lock_manager: SimpleGlobal<lock_manager::ExtSessionLockManagerV1>,
+        idle_notifier: SimpleGlobal<idle_notifier::ExtIdleNotifierV1>,
+        idle_inhibit_manager: SimpleGlobal<idle_inhibit_manager::ZwpIdleInhibitManagerV1>,
+        output_power_manager: SimpleGlobal<output_power_manager::ZwlrOutputPowerManagerV1>,
+        copy_capture_manager: SimpleGlobal<copy_capture_manager::ExtImageCopyCaptureManagerV1>,
+        toplevel_source_manager: SimpleGlobal<toplevel_source_manager::ExtForeignToplevelImageCaptureSourceManagerV1>,
+        output_source_manager: SimpleGlobal<output_source_manager::ExtOutputImageCaptureSourceManagerV1>,
+        screencopy_manager: SimpleGlobal<screencopy_manager::ZwlrScreencopyManagerV1>,
+        gamma_control_manager: SimpleGlobal<gamma_control_manager::ZwlrGammaControlManagerV1>,
         toplevel_service: ToplevelServiceRc,
+        output_config_service: OutputConfigServiceRc,
+        surface_registry: SurfaceRegistry,
     ],
     singles = [
         layer_shell::ZwlrLayerShellV1 => layer_shell,
+        wp_viewporter::WpViewporter => viewporter,
+        fractional_scale_manager::WpFractionalScaleManagerV1 => fractional_scale_manager,
+        xdg_wm_base::XdgWmBase => wm_base,
+        text_input_manager::ZwpTextInputManagerV3 => text_input_manager,
+        lock_manager::ExtSessionLockManagerV1 => lock_manager,
+        idle_notifier::ExtIdleNotifierV1 => idle_notifier,
+        idle_inhibit_manager::ZwpIdleInhibitManagerV1 => idle_inhibit_manager,
+        output_power_manager::ZwlrOutputPowerManagerV1 => output_power_manager,
+        copy_capture_manager::ExtImageCopyCaptureManagerV1 => copy_capture_manager,
+        toplevel_source_manager::ExtForeignToplevelImageCaptureSourceManagerV1 => toplevel_source_manager,
+        output_source_manager::ExtOutputImageCaptureSourceManagerV1 => output_source_manager,
+        screencopy_manager::ZwlrScreencopyManagerV1 => screencopy_manager,
+        gamma_control_manager::ZwlrGammaControlManagerV1 => gamma_control_manager,
         toplevel_manager::ZwlrForeignToplevelManagerV1 => toplevel_service,
+        ext_toplevel_list::ExtForeignToplevelListV1 => toplevel_service,
+        output_manager::ZwlrOutputManagerV1 => output_config_service,
     ],
 );
 toplevel_handler!(Env, toplevel_service);
+output_config_handler!(Env, output_config_service);
 
 pub fn make_env() -> Result<(Environment<Env>, Display, EventQueue), ConnectError> {
     new_default_environment!(
         Env,
         fields = [
             layer_shell: SimpleGlobal::new(),
+            viewporter: SimpleGlobal::new(),
+            fractional_scale_manager: SimpleGlobal::new(),
+            wm_base: WmBaseGlobal::new(),
+            text_input_manager: SimpleGlobal::new(),
+            lock_manager: SimpleGlobal::new(),
+            idle_notifier: SimpleGlobal::new(),
+            idle_inhibit_manager: SimpleGlobal::new(),
+            output_power_manager: SimpleGlobal::new(),
+            copy_capture_manager: SimpleGlobal::new(),
+            toplevel_source_manager: SimpleGlobal::new(),
+            output_source_manager: SimpleGlobal::new(),
+            screencopy_manager: SimpleGlobal::new(),
+            gamma_control_manager: SimpleGlobal::new(),
             toplevel_service: ToplevelServiceRc(Rc::new(ToplevelService::new())),
+            output_config_service: OutputConfigServiceRc(Rc::new(OutputConfigService::new())),
+            surface_registry: SurfaceRegistry::default(),
         ]
     )
 }
 
-static mut SCALE_CHANNELS: Vec<(wl_surface::WlSurface, mpsc::UnboundedSender<i32>)> = Vec::new();
+/// How the compositor gives keyboard focus to a layer surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardMode {
+    None = 0,
+    /// Takes all keyboard input while on the top or overlay layer, e.g. for password prompts
+    Exclusive = 1,
+    /// Focused like a window, e.g. by clicking it (needs layer-shell v4, otherwise it's None)
+    OnDemand = 2,
+}
+
+/// Distances from the anchored edges of the output, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Margins {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+/// Where and how a surface is placed on the layer shell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerProps {
+    pub layer: layer_shell::Layer,
+    /// Lets compositors apply rules to the surface, e.g. "waysmoke-dock". Can't change after creation
+    pub namespace: String,
+    pub anchor: layer_surface::Anchor,
+    /// Zero stretches the surface between the opposite edges it's anchored to
+    pub size: (u32, u32),
+    pub margins: Margins,
+    /// Space reserved along the anchored edge, or -1 to ignore others' reserved space
+    pub exclusive_zone: i32,
+    pub keyboard: KeyboardMode,
+}
+
+impl Default for LayerProps {
+    fn default() -> LayerProps {
+        LayerProps {
+            layer: layer_shell::Layer::Top,
+            namespace: "waysmoke".to_owned(),
+            anchor: layer_surface::Anchor::empty(),
+            size: (0, 0),
+            margins: Margins::default(),
+            exclusive_zone: 0,
+            keyboard: KeyboardMode::None,
+        }
+    }
+}
 
 pub trait DesktopSurface {
-    fn setup_lsh(&self, layer_surface: &Main<layer_surface::ZwlrLayerSurfaceV1>);
+    /// Checked after every render, changes apply with the next commit.
+    /// Not used for lock surfaces, which always cover their whole output.
+    fn layer_props(&self) -> LayerProps;
+}
+
+/// The role that puts a DesktopInstance's surface on screen
+pub enum ShellSurface {
+    Layer(AsyncMain<layer_surface::ZwlrLayerSurfaceV1>),
+    /// Covers one output while the session is locked, see `SessionLock`
+    Lock(AsyncMain<lock_surface::ExtSessionLockSurfaceV1>),
+}
+
+/// Events of the shell surface, whichever role it has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellEvent {
+    /// Has to be acknowledged with `ShellSurface::ack_configure` before drawing at the new size
+    Configure {
+        serial: u32,
+        width: u32,
+        height: u32,
+    },
+    Closed,
+    Unknown,
+}
+
+impl ShellSurface {
+    pub fn next_event(&mut self) -> impl FusedFuture<Output = ShellEvent> + '_ {
+        match self {
+            ShellSurface::Layer(s) => s
+                .next()
+                .map(|event| match event {
+                    layer_surface::Event::Configure { serial, width, height } => {
+                        ShellEvent::Configure { serial, width, height }
+                    }
+                    layer_surface::Event::Closed => ShellEvent::Closed,
+                    _ => ShellEvent::Unknown,
+                })
+                .left_future(),
+            ShellSurface::Lock(s) => s
+                .next()
+                .map(|event| match event {
+                    lock_surface::Event::Configure { serial, width, height } => {
+                        ShellEvent::Configure { serial, width, height }
+                    }
+                    _ => ShellEvent::Unknown,
+                })
+                .right_future(),
+        }
+    }
+
+    pub fn ack_configure(&self, serial: u32) {
+        match self {
+            ShellSurface::Layer(s) => s.ack_configure(serial),
+            ShellSurface::Lock(s) => s.ack_configure(serial),
+        }
+    }
+}
+
+/// Sends whatever differs from the previous properties (everything if there are none)
+fn apply_layer_props(layer_surface: &layer_surface::ZwlrLayerSurfaceV1, prev: Option<&LayerProps>, props: &LayerProps) {
+    let changed = |f: &dyn Fn(&LayerProps) -> bool| prev.map_or(true, |prev| !f(prev));
+    let version = layer_surface.as_ref().version();
+    if let Some(prev) = prev {
+        if prev.layer != props.layer {
+            if version >= 2 {
+                layer_surface.set_layer(props.layer);
+            } else {
+                eprintln!("WARN: layer-shell v{} can't move surfaces between layers", version);
+            }
+        }
+        if prev.namespace != props.namespace {
+            eprintln!("WARN: the namespace of a layer surface can't be changed");
+        }
+    }
+    if changed(&|prev| prev.anchor == props.anchor) {
+        layer_surface.set_anchor(props.anchor);
+    }
+    if changed(&|prev| prev.size == props.size) {
+        layer_surface.set_size(props.size.0, props.size.1);
+    }
+    if changed(&|prev| prev.margins == props.margins) {
+        let m = props.margins;
+        layer_surface.set_margin(m.top, m.right, m.bottom, m.left);
+    }
+    if changed(&|prev| prev.exclusive_zone == props.exclusive_zone) {
+        layer_surface.set_exclusive_zone(props.exclusive_zone);
+    }
+    if changed(&|prev| prev.keyboard == props.keyboard) {
+        let keyboard = if props.keyboard == KeyboardMode::OnDemand && version < 4 {
+            eprintln!("WARN: layer-shell v{} has no on-demand keyboard focus", version);
+            KeyboardMode::None
+        } else {
+            props.keyboard
+        };
+        layer_surface.set_keyboard_interactivity(keyboard as u32);
+    }
 }
 
 pub struct DesktopInstance {
@@ -59,54 +254,106 @@ pub struct DesktopInstance {
     pub display: Display,
     pub theme_mgr: pointer::ThemeManager,
     pub wl_surface: Attached<wl_surface::WlSurface>,
-    pub layer_surface: AsyncMain<layer_surface::ZwlrLayerSurfaceV1>,
-    pub scale_rx: mpsc::UnboundedReceiver<i32>,
+    pub shell_surface: ShellSurface,
+    layer_props: LayerProps,
+    /// Scale changes and the outputs the surface is shown on
+    pub surface_events: mpsc::UnboundedReceiver<SurfaceEvent>,
+    surface_registry: SurfaceRegistry,
+    viewport: Option<Main<wp_viewport::WpViewport>>,
+    fractional_scale: Option<Main<fractional_scale::WpFractionalScaleV1>>,
+    pub clipboard: ClipboardService,
+    pub paste_rx: mpsc::UnboundedReceiver<Paste>,
 }
 
 impl DesktopInstance {
+    /// A layer surface on the given output
     pub fn new(
         surface: &dyn DesktopSurface,
         env: Environment<Env>,
         display: Display,
         output: &wl_output::WlOutput,
+    ) -> DesktopInstance {
+        let layer_props = surface.layer_props();
+        let mut instance = DesktopInstance::with_role(env, display, |env, wl_surface| {
+            let layer_shell = env.require_global::<layer_shell::ZwlrLayerShellV1>();
+            let layer_surface = layer_shell.get_layer_surface(
+                wl_surface,
+                Some(output),
+                layer_props.layer,
+                layer_props.namespace.clone(),
+            );
+            apply_layer_props(&layer_surface, None, &layer_props);
+            ShellSurface::Layer(AsyncMain::new(layer_surface, Some(|s| s.destroy())))
+        });
+        instance.layer_props = layer_props;
+        instance
+    }
+
+    /// A lock surface on the given output, shown once the session is locked
+    pub fn new_locked(
+        lock: &SessionLock,
+        env: Environment<Env>,
+        display: Display,
+        output: &wl_output::WlOutput,
+    ) -> DesktopInstance {
+        DesktopInstance::with_role(env, display, |_, wl_surface| {
+            ShellSurface::Lock(AsyncMain::new(
+                lock.get_lock_surface(wl_surface, output),
+                Some(|s| s.destroy()),
+            ))
+        })
+    }
+
+    fn with_role(
+        env: Environment<Env>,
+        display: Display,
+        mk_role: impl FnOnce(&Environment<Env>, &Attached<wl_surface::WlSurface>) -> ShellSurface,
     ) -> DesktopInstance {
         let theme_mgr = pointer::ThemeManager::init(
             pointer::ThemeSpec::System, // XCURSOR_THEME XCURSOR_SIZE env vars
             env.require_global::<wl_compositor::WlCompositor>(),
             env.require_global::<wl_shm::WlShm>(),
         );
-        let layer_shell = env.require_global::<layer_shell::ZwlrLayerShellV1>();
-
-        let (scale_tx, scale_rx) = mpsc::unbounded();
-        let wl_surface: Attached<wl_surface::WlSurface> =
-            env.create_surface_with_scale_callback(|scale, wlsurf, _dd| unsafe {
-                SCALE_CHANNELS
-                    .iter()
-                    .find(|(surf, _)| *surf == wlsurf)
-                    .unwrap()
-                    .1
-                    .unbounded_send(scale)
-                    .unwrap();
-            });
-        unsafe {
-            SCALE_CHANNELS.push((wl_surface.detach(), scale_tx));
-        }
 
-        let layer_surface = layer_shell.get_layer_surface(
-            &wl_surface,
-            Some(output),
-            layer_shell::Layer::Top,
-            "Waysmoke Surface".to_owned(),
-        );
-        surface.setup_lsh(&layer_surface);
+        let viewporter = env.get_global::<wp_viewporter::WpViewporter>();
+        let fractional_scale_manager = env.get_global::<fractional_scale_manager::WpFractionalScaleManagerV1>();
+        let is_fractional = viewporter.is_some() && fractional_scale_manager.is_some();
+
+        let (surface_tx, surface_events) = mpsc::unbounded();
+        let surface_registry = env.with_inner(|inner| inner.surface_registry.clone());
+        // preferred_scale already accounts for the outputs
+        let wl_surface = surface_registry.create_surface(&env, surface_tx.clone(), !is_fractional);
+
+        let (viewport, fractional_scale) = match (viewporter, fractional_scale_manager) {
+            (Some(viewporter), Some(manager)) => {
+                let fractional_scale = manager.get_fractional_scale(&wl_surface);
+                fractional_scale.quick_assign(move |_, event, _| match event {
+                    fractional_scale::Event::PreferredScale { scale } => {
+                        let _ = surface_tx.unbounded_send(SurfaceEvent::Scale(scale as f64 / 120.0));
+                    }
+                    _ => (),
+                });
+                (Some(viewporter.get_viewport(&wl_surface)), Some(fractional_scale))
+            }
+            _ => (None, None),
+        };
+        let shell_surface = mk_role(&env, &wl_surface);
+
+        let (clipboard, paste_rx) = ClipboardService::new(env.clone(), display.clone());
 
         DesktopInstance {
             env,
             display,
             theme_mgr,
             wl_surface,
-            layer_surface: AsyncMain::new(layer_surface, Some(|s| s.destroy())),
-            scale_rx,
+            shell_surface,
+            layer_props: LayerProps::default(),
+            surface_events,
+            surface_registry,
+            viewport,
+            fractional_scale,
+            clipboard,
+            paste_rx,
         }
     }
 
@@ -129,17 +376,53 @@ impl DesktopInstance {
     pub fn clear_input_region(&self) {
         self.wl_surface.set_input_region(None);
     }
+
+    /// Changes the layer surface to match the new properties, returns whether a commit is needed.
+    pub fn set_layer_props(&mut self, props: LayerProps) -> bool {
+        let layer_surface = match self.shell_surface {
+            ShellSurface::Layer(ref s) => s,
+            ShellSurface::Lock(_) => return false,
+        };
+        if props == self.layer_props {
+            return false;
+        }
+        apply_layer_props(layer_surface, Some(&self.layer_props), &props);
+        self.layer_props = props;
+        true
+    }
+
+    /// The largest integer scale of the outputs the surface is on
+    pub fn integer_scale(&self) -> i32 {
+        self.surface_registry.scale(&self.wl_surface)
+    }
+
+    pub fn outputs(&self) -> Vec<wl_output::WlOutput> {
+        self.surface_registry.outputs(&self.wl_surface)
+    }
+
+    pub fn is_fractional(&self) -> bool {
+        self.viewport.is_some()
+    }
+
+    /// Declares how buffers of (logical size × scale) map onto the surface.
+    pub fn set_scale(&self, scale: f64, width: u32, height: u32) {
+        if let Some(ref viewport) = self.viewport {
+            self.wl_surface.set_buffer_scale(1);
+            viewport.set_destination(width as i32, height as i32);
+        } else {
+            self.wl_surface.set_buffer_scale(scale as i32);
+        }
+    }
 }
 
 impl Drop for DesktopInstance {
     fn drop(&mut self) {
-        unsafe {
-            let wlsurf = self.wl_surface.detach();
-            if let Some(i) = SCALE_CHANNELS.iter().position(|(surf, _)| *surf == wlsurf) {
-                SCALE_CHANNELS.remove(i);
-            } else {
-                eprintln!("WTF: no scale channel for dropped surface");
-            }
+        self.surface_registry.unregister(&self.wl_surface);
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
         }
         self.wl_surface.destroy();
     }
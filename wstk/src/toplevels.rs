@@ -1,132 +1,464 @@
+//! Windows of other clients, from whichever toplevel protocol the compositor has.
+//! wlr-foreign-toplevel-management can also control them, ext-foreign-toplevel-list only lists them
+//! (but its handles are what capturing a toplevel takes, so it's bound either way).
+
 use event_listener;
-use futures::prelude::*;
-use smithay_client_toolkit::{
-    environment::GlobalHandler,
-    reexports::client::{
-        protocol::{wl_output, wl_registry},
-        Attached, DispatchData, Proxy,
-    },
+use futures::{channel::mpsc, prelude::*};
+use smithay_client_toolkit::reexports::client::{
+    protocol::{wl_output, wl_seat, wl_surface},
+    Attached,
 };
 
+pub use crate::protocols::ext_foreign_toplevel_list_v1::client::{
+    ext_foreign_toplevel_handle_v1 as ext_toplevel_handle, ext_foreign_toplevel_list_v1 as ext_toplevel_list,
+};
 pub use smithay_client_toolkit::reexports::protocols::wlr::unstable::foreign_toplevel::v1::client::{
     zwlr_foreign_toplevel_handle_v1 as toplevel_handle, zwlr_foreign_toplevel_manager_v1 as toplevel_manager,
 };
 
 use std::{
-    cell::{Ref, RefCell},
+    cell::{Cell, Ref, RefCell},
     collections::HashMap,
-    hash::{Hash, Hasher},
+    convert::TryInto,
     rc::Rc,
+    time::Instant,
 };
 
-#[derive(PartialEq, Eq, Clone)]
-pub struct ToplevelKey(toplevel_handle::ZwlrForeignToplevelHandleV1);
+mod ext;
+mod wlr;
 
-impl Hash for ToplevelKey {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        Proxy::from(self.0.clone()).c_ptr().hash(state);
+/// Identifies a toplevel for as long as it exists.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum ToplevelKey {
+    /// wlr handles have no identifiers, so they're numbered in order of appearance
+    Wlr(u64),
+    /// The compositor's identifier, never reused
+    Ext(String),
+}
+
+/// Decoded `state` of a toplevel handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ToplevelFlags {
+    pub activated: bool,
+    pub maximized: bool,
+    pub minimized: bool,
+    pub fullscreen: bool,
+}
+
+impl ToplevelFlags {
+    /// Reads the wl_array of native-endian u32 states, skipping ones newer than us
+    pub fn from_raw(raw: &[u8]) -> ToplevelFlags {
+        let mut flags = ToplevelFlags::default();
+        for chunk in raw.chunks_exact(4) {
+            match toplevel_handle::State::from_raw(u32::from_ne_bytes(chunk.try_into().unwrap())) {
+                Some(toplevel_handle::State::Activated) => flags.activated = true,
+                Some(toplevel_handle::State::Maximized) => flags.maximized = true,
+                Some(toplevel_handle::State::Minimized) => flags.minimized = true,
+                Some(toplevel_handle::State::Fullscreen) => flags.fullscreen = true,
+                _ => (),
+            }
+        }
+        flags
     }
 }
 
+/// The protocol object behind a toplevel.
+#[derive(Clone)]
+pub enum ToplevelHandle {
+    Wlr(toplevel_handle::ZwlrForeignToplevelHandleV1),
+    Ext(ext_toplevel_handle::ExtForeignToplevelHandleV1),
+}
+
 #[derive(Clone)]
 pub struct ToplevelState {
-    pub handle: toplevel_handle::ZwlrForeignToplevelHandleV1,
+    pub handle: ToplevelHandle,
     pub title: String,
     pub app_id: String,
     pub gtk_app_id: Option<String>,
+    /// Only known with the wlr protocol
     pub outputs: Vec<wl_output::WlOutput>,
-    pub state: Vec<u8>,
+    /// Only known with the wlr protocol
+    pub state: ToplevelFlags,
+    /// The toplevel this one is a dialog (or similar) of, only known with the wlr protocol
+    pub parent: Option<ToplevelKey>,
+    /// When it was activated for the last time since we've known about it
+    pub activated_at: Option<Instant>,
+    /// The same window in ext-foreign-toplevel-list, which capturing needs. With the wlr protocol,
+    /// it's a best guess by app id and title, set once the compositor has listed it in both.
+    pub ext_handle: Option<ext_toplevel_handle::ExtForeignToplevelHandleV1>,
 }
 
 impl ToplevelState {
+    fn new(handle: ToplevelHandle) -> ToplevelState {
+        let ext_handle = match handle {
+            ToplevelHandle::Ext(ref handle) => Some(handle.clone()),
+            ToplevelHandle::Wlr(_) => None,
+        };
+        ToplevelState {
+            handle,
+            title: "".to_owned(),
+            app_id: "".to_owned(),
+            gtk_app_id: None,
+            outputs: Vec::new(),
+            state: ToplevelFlags::default(),
+            parent: None,
+            activated_at: None,
+            ext_handle,
+        }
+    }
+
+    fn set_app_id(&mut self, app_id: &str) {
+        // Wayfire with option workarounds/app_id_mode == "full" adds gtk-shell id after a space
+        let mut words = app_id.split(' ');
+        self.app_id = words.next().unwrap_or("").to_owned();
+        self.gtk_app_id = words.next().map(|x| x.to_owned());
+        if words.next().is_some() {
+            eprintln!("WARN: app_id with more than one space: '{}'", app_id);
+        }
+    }
+
     pub fn matches_id(&self, id: &str) -> bool {
         id == self.app_id || self.gtk_app_id.as_ref().map(|x| id == x).unwrap_or(false)
     }
+
+    fn same_window(&self, other: &ToplevelState) -> bool {
+        self.title == other.title && self.app_id == other.app_id && self.gtk_app_id == other.gtk_app_id
+    }
+
+    fn wlr(&self) -> Option<&toplevel_handle::ZwlrForeignToplevelHandleV1> {
+        match self.handle {
+            ToplevelHandle::Wlr(ref handle) => Some(handle),
+            ToplevelHandle::Ext(_) => None,
+        }
+    }
+
+    /// Whether the methods below do anything, i.e. the toplevel came from the wlr protocol
+    pub fn can_control(&self) -> bool {
+        self.wlr().is_some()
+    }
+
+    pub fn activate(&self, seat: &wl_seat::WlSeat) {
+        if let Some(handle) = self.wlr() {
+            handle.activate(seat);
+        }
+    }
+
+    pub fn set_maximized(&self, maximized: bool) {
+        match self.wlr() {
+            Some(handle) if maximized => handle.set_maximized(),
+            Some(handle) => handle.unset_maximized(),
+            None => (),
+        }
+    }
+
+    pub fn set_minimized(&self, minimized: bool) {
+        match self.wlr() {
+            Some(handle) if minimized => handle.set_minimized(),
+            Some(handle) => handle.unset_minimized(),
+            None => (),
+        }
+    }
+
+    /// With no output, the compositor picks one. Ignored by compositors that don't support it.
+    pub fn set_fullscreen(&self, output: Option<&wl_output::WlOutput>) {
+        match self.wlr() {
+            Some(handle) if handle.as_ref().version() >= 2 => handle.set_fullscreen(output),
+            _ => (),
+        }
+    }
+
+    pub fn unset_fullscreen(&self) {
+        match self.wlr() {
+            Some(handle) if handle.as_ref().version() >= 2 => handle.unset_fullscreen(),
+            _ => (),
+        }
+    }
+
+    /// Asks the client to close the window, it might ask the user first or ignore it.
+    pub fn close(&self) {
+        if let Some(handle) = self.wlr() {
+            handle.close();
+        }
+    }
+
+    /// Where the toplevel is represented on one of our surfaces (e.g. a dock icon),
+    /// compositors use it as the target of minimize animations.
+    pub fn set_rectangle(&self, surface: &wl_surface::WlSurface, x: i32, y: i32, width: i32, height: i32) {
+        if let Some(handle) = self.wlr() {
+            handle.set_rectangle(surface, x, y, width, height);
+        }
+    }
 }
 
-pub type ToplevelStates = HashMap<ToplevelKey, ToplevelState>;
+/// All current toplevels, iterated in most recently activated first order
+/// (the never activated ones come last, in order of appearance).
+#[derive(Default)]
+pub struct ToplevelStates {
+    map: HashMap<ToplevelKey, ToplevelState>,
+    mru: Vec<ToplevelKey>,
+}
 
-pub struct ToplevelService {
-    global: RefCell<Option<Attached<toplevel_manager::ZwlrForeignToplevelManagerV1>>>,
-    notifier: Rc<event_listener::Event>,
+impl ToplevelStates {
+    pub fn get(&self, key: &ToplevelKey) -> Option<&ToplevelState> {
+        self.map.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.mru.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mru.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ToplevelKey, &ToplevelState)> {
+        self.mru.iter().map(move |key| (key, &self.map[key]))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &ToplevelKey> {
+        self.mru.iter()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &ToplevelState> {
+        self.iter().map(|(_, topl)| topl)
+    }
+
+    /// Direct children of the toplevel, most recently activated first
+    pub fn children<'a>(&'a self, key: &'a ToplevelKey) -> impl Iterator<Item = (&'a ToplevelKey, &'a ToplevelState)> {
+        self.iter().filter(move |(_, topl)| topl.parent.as_ref() == Some(key))
+    }
+
+    fn key_of(&self, handle: &toplevel_handle::ZwlrForeignToplevelHandleV1) -> Option<ToplevelKey> {
+        self.iter()
+            .find(|(_, topl)| topl.wlr() == Some(handle))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Stores the new state, returning the previous one.
+    /// Moves the toplevel to the front when it becomes activated.
+    fn insert(&mut self, key: ToplevelKey, mut topl: ToplevelState) -> Option<ToplevelState> {
+        let old = self.map.get(&key);
+        let was_activated = old.map_or(false, |o| o.state.activated);
+        topl.activated_at = old.and_then(|o| o.activated_at);
+        if topl.ext_handle.is_none() {
+            topl.ext_handle = old.and_then(|o| o.ext_handle.clone());
+        }
+        if old.is_none() {
+            self.mru.push(key.clone());
+        }
+        if topl.state.activated && !was_activated {
+            topl.activated_at = Some(Instant::now());
+            self.mru.retain(|k| *k != key);
+            self.mru.insert(0, key.clone());
+        }
+        self.map.insert(key, topl)
+    }
+
+    fn remove(&mut self, key: &ToplevelKey) -> Option<ToplevelState> {
+        self.mru.retain(|k| k != key);
+        self.map.remove(key)
+    }
+
+    fn drain_keys(&mut self) -> Vec<ToplevelKey> {
+        self.map.clear();
+        std::mem::take(&mut self.mru)
+    }
+
+    /// Gives the toplevels without an ext handle the one of a listed toplevel with the same app id and title
+    fn pair_ext(&mut self, listed: &[ToplevelState]) {
+        let mut claimed: Vec<_> = self.map.values().filter_map(|t| t.ext_handle.clone()).collect();
+        for topl in self.map.values_mut().filter(|t| t.ext_handle.is_none()) {
+            let found = listed
+                .iter()
+                .find(|l| topl.same_window(l) && l.ext_handle.as_ref().map_or(false, |h| !claimed.contains(h)));
+            if let Some(handle) = found.and_then(|l| l.ext_handle.clone()) {
+                claimed.push(handle.clone());
+                topl.ext_handle = Some(handle);
+            }
+        }
+    }
+
+    fn unpair_ext(&mut self, handle: &ext_toplevel_handle::ExtForeignToplevelHandleV1) {
+        for topl in self.map.values_mut() {
+            if topl.ext_handle.as_ref() == Some(handle) {
+                topl.ext_handle = None;
+            }
+        }
+    }
+}
+
+/// Which parts of a toplevel changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ToplevelFields {
+    pub title: bool,
+    /// Either of `app_id` and `gtk_app_id`
+    pub app_id: bool,
+    pub outputs: bool,
+    /// Includes moving in the most recently activated order
+    pub state: bool,
+    pub parent: bool,
+}
+
+impl ToplevelFields {
+    fn between(old: &ToplevelState, new: &ToplevelState) -> ToplevelFields {
+        ToplevelFields {
+            title: old.title != new.title,
+            app_id: old.app_id != new.app_id || old.gtk_app_id != new.gtk_app_id,
+            outputs: old.outputs != new.outputs,
+            state: old.state != new.state,
+            parent: old.parent != new.parent,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        *self != ToplevelFields::default()
+    }
+}
+
+/// A change to the toplevel list, the new state is in `ToplevelService::state` by the time it arrives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToplevelEvent {
+    Added(ToplevelKey),
+    Changed { key: ToplevelKey, fields: ToplevelFields },
+    Removed(ToplevelKey),
+}
+
+/// Which protocol the toplevels come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToplevelBackend {
+    /// The compositor has neither, there will never be any toplevels
+    None,
+    Wlr,
+    Ext,
+}
+
+/// What the protocol backends feed their toplevels into.
+#[derive(Clone)]
+struct ToplevelSink {
     states: Rc<RefCell<ToplevelStates>>,
+    notifier: Rc<event_listener::Event>,
+    subscribers: Rc<RefCell<Vec<mpsc::UnboundedSender<ToplevelEvent>>>>,
+    backend: Rc<Cell<ToplevelBackend>>,
+    /// Everything ext-foreign-toplevel-list has, which is kept bound alongside wlr for the handles
+    ext_listed: Rc<RefCell<Vec<ToplevelState>>>,
+}
+
+impl ToplevelSink {
+    fn is_active(&self, backend: ToplevelBackend) -> bool {
+        self.backend.get() == backend
+    }
+
+    fn key_of(&self, handle: &toplevel_handle::ZwlrForeignToplevelHandleV1) -> Option<ToplevelKey> {
+        self.states.borrow().key_of(handle)
+    }
+
+    fn send(&self, event: ToplevelEvent) {
+        self.subscribers
+            .borrow_mut()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    fn update(&self, key: ToplevelKey, topl: ToplevelState) {
+        let old = self.states.borrow_mut().insert(key.clone(), topl.clone());
+        if self.is_active(ToplevelBackend::Wlr) {
+            self.states.borrow_mut().pair_ext(&self.ext_listed.borrow());
+        }
+        let event = match old {
+            Some(old) => {
+                let fields = ToplevelFields::between(&old, &topl);
+                if !fields.any() {
+                    return;
+                }
+                ToplevelEvent::Changed { key, fields }
+            }
+            None => ToplevelEvent::Added(key),
+        };
+        self.send(event);
+        self.notifier.notify(usize::MAX);
+    }
+
+    fn remove(&self, key: &ToplevelKey) {
+        if self.states.borrow_mut().remove(key).is_some() {
+            self.send(ToplevelEvent::Removed(key.clone()));
+            self.notifier.notify(usize::MAX);
+        }
+    }
+
+    /// Records a toplevel of the ext list, whichever backend is active
+    fn update_ext(&self, topl: ToplevelState) {
+        let mut listed = self.ext_listed.borrow_mut();
+        match listed.iter_mut().find(|l| l.ext_handle == topl.ext_handle) {
+            Some(l) => *l = topl,
+            None => listed.push(topl),
+        }
+        if self.is_active(ToplevelBackend::Wlr) {
+            self.states.borrow_mut().pair_ext(&listed);
+        }
+    }
+
+    fn remove_ext(&self, handle: &ext_toplevel_handle::ExtForeignToplevelHandleV1) {
+        self.ext_listed
+            .borrow_mut()
+            .retain(|l| l.ext_handle.as_ref() != Some(handle));
+        self.states.borrow_mut().unpair_ext(handle);
+    }
+
+    fn clear(&self) {
+        let keys = self.states.borrow_mut().drain_keys();
+        for key in keys {
+            self.send(ToplevelEvent::Removed(key));
+        }
+        self.notifier.notify(usize::MAX);
+    }
+}
+
+pub struct ToplevelService {
+    wlr_manager: RefCell<Option<Attached<toplevel_manager::ZwlrForeignToplevelManagerV1>>>,
+    ext_list: RefCell<Option<Attached<ext_toplevel_list::ExtForeignToplevelListV1>>>,
+    sink: ToplevelSink,
 }
 
 impl ToplevelService {
     pub fn new() -> ToplevelService {
         ToplevelService {
-            global: RefCell::new(None),
-            notifier: Rc::new(event_listener::Event::new()),
-            states: Rc::new(RefCell::new(HashMap::new())),
+            wlr_manager: RefCell::new(None),
+            ext_list: RefCell::new(None),
+            sink: ToplevelSink {
+                states: Rc::new(RefCell::new(ToplevelStates::default())),
+                notifier: Rc::new(event_listener::Event::new()),
+                subscribers: Rc::new(RefCell::new(Vec::new())),
+                backend: Rc::new(Cell::new(ToplevelBackend::None)),
+                ext_listed: Rc::new(RefCell::new(Vec::new())),
+            },
         }
     }
 
-    pub fn state(&self) -> Ref<'_, ToplevelStates> {
-        self.states.borrow()
+    pub fn backend(&self) -> ToplevelBackend {
+        self.sink.backend.get()
     }
 
-    pub fn subscribe(&self) -> impl Future<Output = ()> {
-        self.notifier.listen()
+    pub fn state(&self) -> Ref<'_, ToplevelStates> {
+        self.sink.states.borrow()
     }
-}
-
-pub struct ToplevelServiceRc(pub Rc<ToplevelService>);
 
-impl GlobalHandler<toplevel_manager::ZwlrForeignToplevelManagerV1> for ToplevelServiceRc {
-    fn created(&mut self, registry: Attached<wl_registry::WlRegistry>, id: u32, version: u32, _: DispatchData) {
-        let main = registry.bind::<toplevel_manager::ZwlrForeignToplevelManagerV1>(version, id);
-        let states = self.0.states.clone();
-        let notifier = self.0.notifier.clone();
-        main.quick_assign(move |_, event, _| match event {
-            toplevel_manager::Event::Toplevel { toplevel } => {
-                let mut topl = ToplevelState {
-                    handle: toplevel.detach(),
-                    title: "".to_owned(),
-                    app_id: "".to_owned(),
-                    gtk_app_id: None,
-                    outputs: Vec::new(),
-                    state: Vec::new(),
-                };
-                let states = states.clone();
-                let notifier = notifier.clone();
-                toplevel.quick_assign(move |_, event, _| match event {
-                    toplevel_handle::Event::Title { title } => topl.title = title,
-                    toplevel_handle::Event::AppId { app_id } => {
-                        // Wayfire with option workarounds/app_id_mode == "full" adds gtk-shell id after a space
-                        let mut words = app_id.split(' ');
-                        topl.app_id = words.next().unwrap_or("").to_owned();
-                        topl.gtk_app_id = words.next().map(|x| x.to_owned());
-                        if words.next().is_some() {
-                            eprintln!("WARN: app_id with more than one space: '{}'", app_id);
-                        }
-                    }
-                    toplevel_handle::Event::OutputEnter { output } => topl.outputs.push(output),
-                    toplevel_handle::Event::OutputLeave { output } => topl.outputs.retain(|o| *o != output),
-                    toplevel_handle::Event::State { state } => topl.state = state,
-                    toplevel_handle::Event::Done => {
-                        states
-                            .borrow_mut()
-                            .insert(ToplevelKey(topl.handle.clone()), topl.clone());
-                        notifier.notify(usize::MAX);
-                    }
-                    toplevel_handle::Event::Closed => {
-                        states.borrow_mut().remove(&ToplevelKey(topl.handle.clone()));
-                        notifier.notify(usize::MAX);
-                    }
-                    toplevel_handle::Event::Parent { .. } => {}
-                    x => panic!("Unknown toplevel event {:?}", x),
-                });
-            }
-            x => panic!("Unknown toplevel manager event {:?}", x),
-        });
-        *self.0.global.borrow_mut() = Some((*main).clone())
+    /// Wakes up on any change, for when it doesn't matter what changed
+    pub fn subscribe(&self) -> impl Future<Output = ()> {
+        self.sink.notifier.listen()
     }
 
-    fn get(&self) -> Option<Attached<toplevel_manager::ZwlrForeignToplevelManagerV1>> {
-        self.0.global.borrow().clone()
+    /// Every change as it happens, starting with an `Added` for each existing toplevel
+    pub fn events(&self) -> mpsc::UnboundedReceiver<ToplevelEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        for key in self.sink.states.borrow().keys() {
+            let _ = tx.unbounded_send(ToplevelEvent::Added(key.clone()));
+        }
+        self.sink.subscribers.borrow_mut().push(tx);
+        rx
     }
 }
 
+pub struct ToplevelServiceRc(pub Rc<ToplevelService>);
+
 pub trait HasToplevelService {
     fn toplevel_service(&self) -> Rc<ToplevelService>;
 }
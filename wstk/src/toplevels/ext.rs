@@ -0,0 +1,65 @@
+//! ext-foreign-toplevel-list, the toplevels when the compositor doesn't have the wlr protocol,
+//! and the handles to capture them with either way
+
+use super::*;
+use smithay_client_toolkit::{
+    environment::GlobalHandler,
+    reexports::client::{protocol::wl_registry, DispatchData},
+};
+
+impl GlobalHandler<ext_toplevel_list::ExtForeignToplevelListV1> for ToplevelServiceRc {
+    fn created(&mut self, registry: Attached<wl_registry::WlRegistry>, id: u32, _version: u32, _: DispatchData) {
+        if self.0.backend() == ToplevelBackend::None {
+            self.0.sink.backend.set(ToplevelBackend::Ext);
+        }
+        let main = registry.bind::<ext_toplevel_list::ExtForeignToplevelListV1>(1, id);
+        let sink = self.0.sink.clone();
+        main.quick_assign(move |list, event, _| match event {
+            ext_toplevel_list::Event::Toplevel { toplevel } => {
+                let mut topl = ToplevelState::new(ToplevelHandle::Ext(toplevel.detach()));
+                let mut key = None;
+                let sink = sink.clone();
+                toplevel.quick_assign(move |handle, event, _| match event {
+                    ext_toplevel_handle::Event::Identifier { identifier } => key = Some(ToplevelKey::Ext(identifier)),
+                    ext_toplevel_handle::Event::Title { title } => topl.title = title,
+                    ext_toplevel_handle::Event::AppId { app_id } => topl.set_app_id(&app_id),
+                    ext_toplevel_handle::Event::Done => {
+                        sink.update_ext(topl.clone());
+                        // with wlr, the toplevels come from there
+                        if !sink.is_active(ToplevelBackend::Ext) {
+                            return;
+                        }
+                        match key {
+                            Some(ref key) => sink.update(key.clone(), topl.clone()),
+                            None => eprintln!("WARN: toplevel without an identifier"),
+                        }
+                    }
+                    ext_toplevel_handle::Event::Closed => {
+                        sink.remove_ext(&handle);
+                        match key {
+                            Some(ref key) if sink.is_active(ToplevelBackend::Ext) => sink.remove(key),
+                            _ => (),
+                        }
+                        handle.destroy();
+                    }
+                    x => eprintln!("WARN: unknown toplevel event {:?}", x),
+                });
+            }
+            ext_toplevel_list::Event::Finished => list.destroy(),
+            x => eprintln!("WARN: unknown toplevel list event {:?}", x),
+        });
+        *self.0.ext_list.borrow_mut() = Some((*main).clone())
+    }
+
+    fn get(&self) -> Option<Attached<ext_toplevel_list::ExtForeignToplevelListV1>> {
+        self.0.ext_list.borrow().clone()
+    }
+}
+
+impl ToplevelService {
+    /// Drops the toplevels listed with ext when the wlr protocol shows up after all.
+    /// The list stays bound for its handles.
+    pub(super) fn leave_ext(&self) {
+        self.sink.clear();
+    }
+}
@@ -0,0 +1,50 @@
+//! wlr-foreign-toplevel-management, preferred since it can also control toplevels
+
+use super::*;
+use smithay_client_toolkit::{
+    environment::GlobalHandler,
+    reexports::client::{protocol::wl_registry, DispatchData},
+};
+
+impl GlobalHandler<toplevel_manager::ZwlrForeignToplevelManagerV1> for ToplevelServiceRc {
+    fn created(&mut self, registry: Attached<wl_registry::WlRegistry>, id: u32, version: u32, _: DispatchData) {
+        if self.0.backend() == ToplevelBackend::Ext {
+            self.0.leave_ext();
+        }
+        self.0.sink.backend.set(ToplevelBackend::Wlr);
+        let main = registry.bind::<toplevel_manager::ZwlrForeignToplevelManagerV1>(version.min(3), id);
+        let sink = self.0.sink.clone();
+        let mut next_id = 0;
+        main.quick_assign(move |_, event, _| match event {
+            toplevel_manager::Event::Toplevel { toplevel } => {
+                let key = ToplevelKey::Wlr(next_id);
+                next_id += 1;
+                let mut topl = ToplevelState::new(ToplevelHandle::Wlr(toplevel.detach()));
+                let sink = sink.clone();
+                toplevel.quick_assign(move |handle, event, _| match event {
+                    toplevel_handle::Event::Title { title } => topl.title = title,
+                    toplevel_handle::Event::AppId { app_id } => topl.set_app_id(&app_id),
+                    toplevel_handle::Event::OutputEnter { output } => topl.outputs.push(output),
+                    toplevel_handle::Event::OutputLeave { output } => topl.outputs.retain(|o| *o != output),
+                    toplevel_handle::Event::State { state } => topl.state = ToplevelFlags::from_raw(&state),
+                    toplevel_handle::Event::Done => sink.update(key.clone(), topl.clone()),
+                    toplevel_handle::Event::Closed => {
+                        sink.remove(&key);
+                        handle.destroy();
+                    }
+                    toplevel_handle::Event::Parent { parent } => {
+                        topl.parent = parent.and_then(|parent| sink.key_of(&parent))
+                    }
+                    x => eprintln!("WARN: unknown toplevel event {:?}", x),
+                });
+            }
+            toplevel_manager::Event::Finished => eprintln!("WARN: the compositor stopped sending toplevels"),
+            x => eprintln!("WARN: unknown toplevel manager event {:?}", x),
+        });
+        *self.0.wlr_manager.borrow_mut() = Some((*main).clone())
+    }
+
+    fn get(&self) -> Option<Attached<toplevel_manager::ZwlrForeignToplevelManagerV1>> {
+        self.0.wlr_manager.borrow().clone()
+    }
+}
@@ -6,3 +6,6 @@ pub use getregion::*;
 
 pub mod prim;
 pub use prim::*;
+
+pub mod slide;
+pub use slide::*;
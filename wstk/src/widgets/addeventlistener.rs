@@ -1,12 +1,28 @@
 //! The antidote to iced's annoying rigidity and inflexibility,
 //! the equivalent of anything.addEventListener('mouseover', ..) :P
+//! Clicks, scrolls and long presses only reach it when the content doesn't handle them.
 
 use iced_native::*;
 use std::hash::Hash;
 
+/// A touch held in place is delivered as a press and release of this pseudo button,
+/// which iced's own widgets ignore
+pub const LONG_PRESS_BUTTON: mouse::Button = mouse::Button::Other(u8::MAX);
+
+/// Where an input happened, relative to the listener, and the modifiers held at the time
+/// (only known while the surface has keyboard focus)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventInfo {
+    pub position: Point,
+    pub modifiers: keyboard::Modifiers,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct State {
     is_hovered: bool,
+    /// Pressed over the listener, a click if it's released there too
+    pressed: Option<mouse::Button>,
+    modifiers: keyboard::Modifiers,
 }
 
 pub struct AddEventListener<'a, Message, Renderer: self::Renderer> {
@@ -14,6 +30,10 @@ pub struct AddEventListener<'a, Message, Renderer: self::Renderer> {
     content: Element<'a, Message, Renderer>,
     pointer_enter: Option<Message>,
     pointer_leave: Option<Message>,
+    secondary_click: Option<Box<dyn Fn(EventInfo) -> Message + 'a>>,
+    middle_click: Option<Box<dyn Fn(EventInfo) -> Message + 'a>>,
+    long_press: Option<Box<dyn Fn(EventInfo) -> Message + 'a>>,
+    scroll: Option<Box<dyn Fn(mouse::ScrollDelta, EventInfo) -> Message + 'a>>,
 }
 
 impl<'a, Message, Renderer> AddEventListener<'a, Message, Renderer>
@@ -29,6 +49,10 @@ where
             content: content.into(),
             pointer_enter: None,
             pointer_leave: None,
+            secondary_click: None,
+            middle_click: None,
+            long_press: None,
+            scroll: None,
         }
     }
 
@@ -41,6 +65,38 @@ where
         self.pointer_leave = Some(msg);
         self
     }
+
+    /// Usually the right button, e.g. for context menus
+    pub fn on_secondary_click(mut self, f: impl Fn(EventInfo) -> Message + 'a) -> Self {
+        self.secondary_click = Some(Box::new(f));
+        self
+    }
+
+    pub fn on_middle_click(mut self, f: impl Fn(EventInfo) -> Message + 'a) -> Self {
+        self.middle_click = Some(Box::new(f));
+        self
+    }
+
+    /// A touch held in place, fired while it's still held (lifting it then doesn't tap)
+    pub fn on_long_press(mut self, f: impl Fn(EventInfo) -> Message + 'a) -> Self {
+        self.long_press = Some(Box::new(f));
+        self
+    }
+
+    /// Fired for every wheel step or touchpad movement, accumulating is up to the handler
+    pub fn on_scroll(mut self, f: impl Fn(mouse::ScrollDelta, EventInfo) -> Message + 'a) -> Self {
+        self.scroll = Some(Box::new(f));
+        self
+    }
+
+    fn click_handler(&self, button: mouse::Button) -> Option<&(dyn Fn(EventInfo) -> Message + 'a)> {
+        match button {
+            mouse::Button::Right => self.secondary_click.as_deref(),
+            mouse::Button::Middle => self.middle_click.as_deref(),
+            LONG_PRESS_BUTTON => self.long_press.as_deref(),
+            _ => None,
+        }
+    }
 }
 
 impl<'a, Message, Renderer> Widget<Message, Renderer> for AddEventListener<'a, Message, Renderer>
@@ -84,15 +140,52 @@ where
             }
         }
         self.state.is_hovered = is_mouse_over;
+        if let Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) = event {
+            self.state.modifiers = modifiers;
+        }
 
-        self.content.on_event(
-            event,
+        let status = self.content.on_event(
+            event.clone(),
             layout.children().next().unwrap(),
             cursor_position,
             renderer,
             clipboard,
             messages,
-        )
+        );
+        if status == event::Status::Captured {
+            return status;
+        }
+
+        let info = EventInfo {
+            position: Point::new(cursor_position.x - bounds.x, cursor_position.y - bounds.y),
+            modifiers: self.state.modifiers,
+        };
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(button))
+                if is_mouse_over && self.click_handler(button).is_some() =>
+            {
+                self.state.pressed = Some(button);
+                event::Status::Captured
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(button)) if self.state.pressed == Some(button) => {
+                self.state.pressed = None;
+                match self.click_handler(button) {
+                    Some(f) if is_mouse_over => {
+                        messages.push(f(info));
+                        event::Status::Captured
+                    }
+                    _ => event::Status::Ignored,
+                }
+            }
+            Event::Mouse(mouse::Event::WheelScrolled { delta }) if is_mouse_over => match self.scroll {
+                Some(ref f) => {
+                    messages.push(f(delta, info));
+                    event::Status::Captured
+                }
+                None => event::Status::Ignored,
+            },
+            _ => event::Status::Ignored,
+        }
     }
 
     fn draw(
@@ -0,0 +1,126 @@
+//! A widget that draws its content shifted within its own bounds, cutting off what sticks out,
+//! for sliding things in and out of view without changing the layout
+
+use iced_native::*;
+use std::hash::Hash;
+
+pub struct Slide<'a, Message, Backend: iced_graphics::Backend> {
+    content: Element<'a, Message, iced_graphics::Renderer<Backend>>,
+    offset: Vector,
+}
+
+impl<'a, Message, Backend> Slide<'a, Message, Backend>
+where
+    Backend: iced_graphics::Backend,
+{
+    pub fn new<T>(content: T) -> Self
+    where
+        T: Into<Element<'a, Message, iced_graphics::Renderer<Backend>>>,
+    {
+        Slide {
+            content: content.into(),
+            offset: Vector::new(0.0, 0.0),
+        }
+    }
+
+    /// How far the content is moved, in logical pixels
+    pub fn offset(mut self, x: f32, y: f32) -> Self {
+        self.offset = Vector::new(x, y);
+        self
+    }
+}
+
+impl<'a, Message, Backend> Widget<Message, iced_graphics::Renderer<Backend>> for Slide<'a, Message, Backend>
+where
+    Backend: iced_graphics::Backend,
+{
+    fn width(&self) -> Length {
+        self.content.width()
+    }
+
+    fn height(&self) -> Length {
+        self.content.height()
+    }
+
+    fn layout(&self, renderer: &iced_graphics::Renderer<Backend>, limits: &layout::Limits) -> layout::Node {
+        let content = self.content.layout(renderer, limits);
+        layout::Node::with_children(content.size(), vec![content])
+    }
+
+    fn on_event(
+        &mut self,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        renderer: &iced_graphics::Renderer<Backend>,
+        clipboard: &mut dyn Clipboard,
+        messages: &mut Vec<Message>,
+    ) -> event::Status {
+        // the content is where it's drawn, not where it's laid out
+        let cursor_position = if layout.bounds().contains(cursor_position) {
+            cursor_position - self.offset
+        } else {
+            Point::new(-1.0, -1.0)
+        };
+        self.content.on_event(
+            event,
+            layout.children().next().unwrap(),
+            cursor_position,
+            renderer,
+            clipboard,
+            messages,
+        )
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut iced_graphics::Renderer<Backend>,
+        defaults: &iced_graphics::Defaults,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        viewport: &Rectangle,
+    ) -> (iced_graphics::Primitive, mouse::Interaction) {
+        let bounds = layout.bounds();
+        let cursor_position = if bounds.contains(cursor_position) {
+            cursor_position - self.offset
+        } else {
+            Point::new(-1.0, -1.0)
+        };
+        let (content, interaction) = self.content.draw(
+            renderer,
+            defaults,
+            layout.children().next().unwrap(),
+            cursor_position,
+            viewport,
+        );
+
+        (
+            iced_graphics::Primitive::Clip {
+                bounds,
+                offset: Vector::new(0, 0),
+                content: Box::new(iced_graphics::Primitive::Translate {
+                    translation: self.offset,
+                    content: Box::new(content),
+                }),
+            },
+            interaction,
+        )
+    }
+
+    fn hash_layout(&self, state: &mut Hasher) {
+        struct Marker;
+        std::any::TypeId::of::<Marker>().hash(state);
+
+        self.content.hash_layout(state);
+    }
+}
+
+impl<'a, Message, Backend> From<Slide<'a, Message, Backend>> for Element<'a, Message, iced_graphics::Renderer<Backend>>
+where
+    Message: 'a,
+    Backend: 'a + iced_graphics::Backend,
+{
+    fn from(x: Slide<'a, Message, Backend>) -> Element<'a, Message, iced_graphics::Renderer<Backend>> {
+        Element::new(x)
+    }
+}